@@ -1,74 +1,275 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use dashmap::DashMap;
-use tokio::net::TcpStream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::broadcast;
 use tokio::sync::oneshot;
 use tokio::sync::oneshot::Sender as OneShotSender;
+use tokio::sync::{Mutex, Semaphore};
 use tokio::time;
-use tokio::time::Duration;
+use tokio::time::{Duration, Instant};
 
 use crate::api::request::{ApiRequest, ApiRequestEnvelope};
-use crate::api::response::{ApiResponse, ApiResponseEnvelope};
+use crate::api::response::{ApiResponse, ApiResponseEnvelope, ReplicaAudit};
 use crate::api::ApiClientConnection;
+#[cfg(feature = "tls")]
+use crate::error::ConfigError::TlsOverEncryptedTransport;
+use crate::error::NetworkError;
 use crate::error::NetworkError::{ConnectionClosed, RequestTimeout};
-use crate::error::ProtocolError::{BadResponse, LeaderRequired, ServerError};
+use crate::error::ProtocolError::{
+    BadResponse, CommandDisabled, LeaderRequired, NodeStarting, ServerError,
+};
 use crate::error::{AsyncError, Result};
+use crate::tcp::{ConnectionEvent, ConnectionStats, FrameCodec, ReconnectConfig};
+#[cfg(feature = "tls")]
+use crate::tls::TlsClientConfig;
+use crate::tls::{self, TlsConnector};
+use crate::transport::Transport;
+use crate::CHAN_BUF_SIZE;
 
 #[cfg(not(test))]
 const TIMEOUT_IN_MILLIS: u64 = 2000;
 #[cfg(test)]
 const TIMEOUT_IN_MILLIS: u64 = 80;
+const READY_RETRY_INTERVAL_IN_MILLIS: u64 = 10;
+// how many times we follow a redirect to a new leader before giving up on a request
+const MAX_REDIRECTS: usize = 3;
 
-type ApiCallbackRegistry = Arc<DashMap<u64, OneShotSender<ApiResponseEnvelope>>>;
+pub const DEFAULT_MAX_INFLIGHT_REQUESTS: usize = 1024;
+pub const ID_LEASE_SIZE: u64 = 100;
+
+type ApiCallbackRegistry = Arc<DashMap<u64, PendingRequest>>;
 
 #[derive(Clone)]
 pub struct ApiClientConfig {
     pub server_address: SocketAddr,
+    pub max_inflight_requests: usize,
+    pub reconnect: ReconnectConfig,
+    pub codec: FrameCodec,
+    pub value_format: ValueFormat, // how `get_as` and `put_from` encode typed values
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsClientConfig>, // if set, connect to the server over TLS
+    pub transport: Arc<dyn Transport>,
+}
+
+/// How `ApiClient::get_as` and `ApiClient::put_from` encode typed values into the strings we store
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ValueFormat {
+    #[default]
+    Json,
+    /// more compact than JSON, but binary (so stored base64-encoded)
+    MessagePack,
+    /// more compact still, but not self-describing (so values must be read as the type written)
+    Bincode,
+}
+
+impl ValueFormat {
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<String> {
+        match self {
+            ValueFormat::Json => Ok(serde_json::to_string(value)?),
+            ValueFormat::MessagePack => Ok(BASE64.encode(rmp_serde::to_vec_named(value)?)),
+            ValueFormat::Bincode => Ok(BASE64.encode(bincode::serialize(value)?)),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, value: &str) -> Result<T> {
+        match self {
+            ValueFormat::Json => Ok(serde_json::from_str(value)?),
+            ValueFormat::MessagePack => Ok(rmp_serde::from_slice(&BASE64.decode(value)?)?),
+            ValueFormat::Bincode => Ok(bincode::deserialize(&BASE64.decode(value)?)?),
+        }
+    }
 }
 
 pub struct ApiClient {
-    connection: Arc<ApiClientConnection>,
+    connection: Arc<RwLock<Arc<ApiClientConnection>>>, // swapped out on reconnect or redirect
+    connection_events_tx: broadcast::Sender<ConnectionEvent>,
     on_response_callbacks: ApiCallbackRegistry,
+    reconnect: ReconnectConfig,
+    codec: FrameCodec,
+    value_format: ValueFormat,
+    tls: Option<TlsConnector>,
+    transport: Arc<dyn Transport>,
+    inflight_permits: Semaphore,
     request_id: AtomicU64,
+    is_async: AtomicBool,
+    num_cancelled_requests: AtomicU64,
+    // ids leased from each sequence that we have not yet handed out
+    id_leases: Mutex<HashMap<String, Range<u64>>>,
+}
+
+/// Everything a task listening for responses on the client's current connection shares with the
+/// client itself
+#[derive(Clone)]
+struct ResponseListener {
+    connection: Arc<RwLock<Arc<ApiClientConnection>>>,
+    callbacks: ApiCallbackRegistry,
+    events_tx: broadcast::Sender<ConnectionEvent>,
+    reconnect: ReconnectConfig,
+    codec: FrameCodec,
+    tls: Option<TlsConnector>,
+    transport: Arc<dyn Transport>,
+}
+
+/// A request that has been written to the server but not yet answered
+struct PendingRequest {
+    callback: OneShotSender<ApiResponseEnvelope>,
+    request_type: String,
+    issued_at: Instant,
+}
+
+/// Removes a request from the callback registry once `ApiClient::write` is done with it -- whether
+/// because it was settled (answered, timed out, or failed to send) or because the caller dropped
+/// the future awaiting it, in which case we count it as cancelled.
+struct PendingRequestGuard<'a> {
+    id: u64,
+    client: &'a ApiClient,
+    is_settled: bool,
+}
+
+impl Drop for PendingRequestGuard<'_> {
+    fn drop(&mut self) {
+        if self.client.on_response_callbacks.remove(&self.id).is_some() && !self.is_settled {
+            self.client
+                .num_cancelled_requests
+                .fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Snapshot of a pending request, as reported by `ApiClient::inflight`
+#[derive(Clone, Debug, PartialEq)]
+pub struct InflightRequest {
+    pub id: u64,
+    pub request_type: String,
+    pub elapsed: Duration,
 }
 
 impl ApiClientConfig {
     /// Create a live `ApiClient` from an inert `ApiClientConfig` as follows: Create TCP socket
     /// connections to all peers, then store a reference to each connection, and listen for
-    /// responses on it, forwarding any responses to callbacks registered in `Client::write`,
-    /// removing the handlers from the handler registry as they are used.
+    /// responses on it (reconnecting if the server drops it), as described in
+    /// `ResponseListener::listen`. (Requests pending on a dropped connection are left to time out.)
     pub async fn run(self) -> Result<ApiClient> {
+        self.reconnect.validate()?;
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() && self.transport.encrypts() {
+            return Err(TlsOverEncryptedTransport.boxed());
+        }
         // open tcp socket connection to server
-        let connection = Arc::new(ApiClientConnection::new(
-            TcpStream::connect(self.server_address).await?,
-        ));
-
-        // construct machinery for matching responses to requests
-        let request_id = AtomicU64::new(0);
-        let on_response_callbacks: ApiCallbackRegistry = Arc::new(DashMap::new());
+        #[cfg(feature = "tls")]
+        let tls = self.tls.as_ref().map(TlsClientConfig::load).transpose()?;
+        #[cfg(not(feature = "tls"))]
+        let tls: Option<TlsConnector> = None;
+        let socket = self.transport.connect(self.server_address).await?;
+        let stream = tls::connect(socket, self.server_address, tls.as_ref()).await?;
+        let connection = Arc::new(RwLock::new(Arc::new(ApiClientConnection::with_codec(
+            stream, self.codec,
+        ))));
+        let (connection_events_tx, _) = broadcast::channel(CHAN_BUF_SIZE);
 
         // listen for responses on socket and pass them to response handlers
-        let callbacks = on_response_callbacks.clone();
-        let conn = connection.clone();
+        let listener = ResponseListener {
+            connection: connection.clone(),
+            callbacks: Arc::new(DashMap::new()),
+            events_tx: connection_events_tx.clone(),
+            reconnect: self.reconnect,
+            codec: self.codec,
+            tls,
+            transport: self.transport,
+        };
+        let current_connection = connection.read().unwrap().clone();
+        listener
+            .clone()
+            .listen(self.server_address, current_connection);
+
+        // Return live client to caller
+        Ok(ApiClient {
+            connection,
+            connection_events_tx,
+            on_response_callbacks: listener.callbacks,
+            reconnect: listener.reconnect,
+            codec: listener.codec,
+            value_format: self.value_format,
+            tls: listener.tls,
+            transport: listener.transport,
+            inflight_permits: Semaphore::new(self.max_inflight_requests),
+            request_id: AtomicU64::new(0),
+            is_async: AtomicBool::new(false),
+            num_cancelled_requests: AtomicU64::new(0),
+            id_leases: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl ResponseListener {
+    /// Listen for responses on `connection` (to the server at `server_address`), forwarding them
+    /// to callbacks registered in `ApiClient::write`, and removing the handlers from the registry as
+    /// they are used. If the server drops the connection, try to re-establish it (per `reconnect`)
+    /// and keep listening on the new one. Stop once the client has moved on to another connection.
+    fn listen(self, server_address: SocketAddr, connection: Arc<ApiClientConnection>) {
         tokio::spawn(async move {
+            let address = server_address.to_string();
+            let mut conn = connection;
             loop {
-                if let Ok(response) = conn.read().await {
+                match conn.read().await {
                     // send the responses over a oneshot channel to handlers registered in #write (below)
-                    if let Some((_, callback)) = callbacks.remove(&response.id) {
-                        let _ = callback.send(response);
+                    Ok(response) => {
+                        if let Some((_, pending)) = self.callbacks.remove(&response.id) {
+                            let _ = pending.callback.send(response);
+                        }
                     }
+                    // replace the connection if it is no longer usable (and still the current one)
+                    Err(e) if NetworkError::is_disconnect(&e) => {
+                        if !self.is_current(&conn) {
+                            return;
+                        }
+                        let _ = self
+                            .events_tx
+                            .send(ConnectionEvent::Disconnected(address.clone()));
+                        let stream = match self
+                            .reconnect
+                            .reconnect(server_address, &*self.transport, self.tls.as_ref())
+                            .await
+                        {
+                            Some(stream) => stream,
+                            None => {
+                                let _ = self
+                                    .events_tx
+                                    .send(ConnectionEvent::Abandoned(address.clone()));
+                                return;
+                            }
+                        };
+                        let new_conn =
+                            Arc::new(ApiClientConnection::with_codec(stream, self.codec));
+                        {
+                            let mut current = self.connection.write().unwrap();
+                            if !Arc::ptr_eq(&current, &conn) {
+                                return;
+                            }
+                            *current = new_conn.clone();
+                        }
+                        conn = new_conn;
+                        let _ = self
+                            .events_tx
+                            .send(ConnectionEvent::Reconnected(address.clone()));
+                    }
+                    // skip any frame we could not deserialize
+                    Err(_) => {}
                 }
             }
         });
+    }
 
-        // Return live client to caller
-        Ok(ApiClient {
-            connection,
-            on_response_callbacks,
-            request_id,
-        })
+    fn is_current(&self, connection: &Arc<ApiClientConnection>) -> bool {
+        Arc::ptr_eq(&self.connection.read().unwrap(), connection)
     }
 }
 
@@ -80,6 +281,46 @@ impl ApiClient {
         self.request_id.fetch_add(1, Ordering::SeqCst)
     }
 
+    /// List all requests written to the server that have not yet received a response (or timed out).
+    pub fn inflight(&self) -> Vec<InflightRequest> {
+        self.on_response_callbacks
+            .iter()
+            .map(|entry| InflightRequest {
+                id: *entry.key(),
+                request_type: entry.request_type.clone(),
+                elapsed: entry.issued_at.elapsed(),
+            })
+            .collect()
+    }
+
+    /// Report traffic over the connection to the server.
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.connection().stats()
+    }
+
+    /// Subscribe to changes in the state of our connection to the server (as it drops and we try
+    /// to re-establish it).
+    pub fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.connection_events_tx.subscribe()
+    }
+
+    fn connection(&self) -> Arc<ApiClientConnection> {
+        self.connection.read().unwrap().clone()
+    }
+
+    /// Number of requests abandoned by callers (by dropping the future returned from `get` or
+    /// `put`) before a response arrived.
+    pub fn num_cancelled_requests(&self) -> u64 {
+        self.num_cancelled_requests.load(Ordering::SeqCst)
+    }
+
+    /// Toggle whether subsequent puts are issued in async (fire-and-forget) mode, in which the
+    /// leader acknowledges them once appended to its log, before they have been replicated.
+    /// Suitable only for data we can afford to lose.
+    pub fn set_async(&self, is_async: bool) {
+        self.is_async.store(is_async, Ordering::SeqCst);
+    }
+
     // TODO: legacy
     pub fn run() -> Result<()> {
         Ok(())
@@ -96,45 +337,305 @@ impl ApiClient {
         match response.response {
             ApiResponse::ToGet { value } => Ok(value),
             ApiResponse::ServerError { msg } => Err(ServerError(msg).boxed()),
+            ApiResponse::CommandDisabled { request_type } => {
+                Err(CommandDisabled(request_type).boxed())
+            }
+            ApiResponse::Starting { phase } => Err(NodeStarting(phase).boxed()),
             _ => Err(BadResponse(response.response.display_type()).boxed()),
         }
     }
 
+    /// Like `get`, but decode the stored value into a `T` (per our `ValueFormat`).
+    pub async fn get_as<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get(key).await? {
+            Some(value) => Ok(Some(self.value_format.decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `put`, but encode `value` (per our `ValueFormat`) before storing it.
+    pub async fn put_from<T: Serialize>(&self, key: &str, value: &T) -> Result<bool> {
+        self.put(key, &self.value_format.encode(value)?).await
+    }
+
     pub async fn put(&self, key: &str, value: &str) -> Result<bool> {
+        self.put_with(key, value, None, None).await
+    }
+
+    /// Like `put`, but have the value expire (so that reads no longer find it) `ttl_ms` millis
+    /// after the leader receives it.
+    pub async fn put_with_ttl(&self, key: &str, value: &str, ttl_ms: u64) -> Result<bool> {
+        self.put_with(key, value, None, Some(ttl_ms)).await
+    }
+
+    /// Like `put`, but wait for a response until at least `min_replicas` nodes (counting the leader)
+    /// have stored the value, failing with a `ServerError` if they do not do so in time.
+    pub async fn put_with_min_replicas(
+        &self,
+        key: &str,
+        value: &str,
+        min_replicas: usize,
+    ) -> Result<bool> {
+        self.put_with(key, value, Some(min_replicas), None).await
+    }
+
+    async fn put_with(
+        &self,
+        key: &str,
+        value: &str,
+        min_replicas: Option<usize>,
+        ttl_ms: Option<u64>,
+    ) -> Result<bool> {
         let request = ApiRequestEnvelope {
             id: self.next_id(),
             request: ApiRequest::Put {
                 key: key.to_string(),
                 value: value.to_string(),
+                is_async: self.is_async.load(Ordering::SeqCst),
+                min_replicas,
+                ttl_ms,
             },
         };
-        let response: ApiResponseEnvelope = self.write(request).await?;
+        let response: ApiResponseEnvelope = self.write_to_leader(request).await?;
         match response.response {
             ApiResponse::ToPut { was_modified } => Ok(was_modified),
             ApiResponse::ServerError { msg } => Err(ServerError(msg).boxed()),
-            ApiResponse::Redirect { leader_address } => Err(LeaderRequired(leader_address).boxed()),
+            ApiResponse::CommandDisabled { request_type } => {
+                Err(CommandDisabled(request_type).boxed())
+            }
+            ApiResponse::Starting { phase } => Err(NodeStarting(phase).boxed()),
+            ApiResponse::Redirect { leader_address, .. } => {
+                Err(LeaderRequired(leader_address).boxed())
+            }
+            _ => Err(BadResponse(response.response.display_type()).boxed()),
+        }
+    }
+
+    /// Hand out the next id from `sequence_name` that is unique across the cluster, leasing a fresh
+    /// range of `ID_LEASE_SIZE` ids from the leader whenever we have used up the last one. (Ids
+    /// leased but never handed out -- eg: because the client shut down -- are skipped.)
+    pub async fn unique_id(&self, sequence_name: &str) -> Result<u64> {
+        let mut leases = self.id_leases.lock().await;
+        if let Some(id) = leases.get_mut(sequence_name).and_then(|lease| lease.next()) {
+            return Ok(id);
+        }
+        let mut lease = self.lease_ids(sequence_name, ID_LEASE_SIZE).await?;
+        let id = lease
+            .next()
+            .ok_or_else(|| BadResponse("ToNextIds".to_string()).boxed())?;
+        leases.insert(sequence_name.to_string(), lease);
+        Ok(id)
+    }
+
+    /// Lease the next `count` ids from `sequence_name`, which no other caller will ever be given.
+    pub async fn lease_ids(&self, sequence_name: &str, count: u64) -> Result<Range<u64>> {
+        let request = ApiRequestEnvelope {
+            id: self.next_id(),
+            request: ApiRequest::NextIds {
+                sequence_name: sequence_name.to_string(),
+                count,
+            },
+        };
+        let response = self.write_to_leader(request).await?;
+        match response.response {
+            ApiResponse::ToNextIds { start, count } => Ok(start..start + count),
+            ApiResponse::ServerError { msg } => Err(ServerError(msg).boxed()),
+            ApiResponse::CommandDisabled { request_type } => {
+                Err(CommandDisabled(request_type).boxed())
+            }
+            ApiResponse::Starting { phase } => Err(NodeStarting(phase).boxed()),
+            ApiResponse::Redirect { leader_address, .. } => {
+                Err(LeaderRequired(leader_address).boxed())
+            }
+            _ => Err(BadResponse(response.response.display_type()).boxed()),
+        }
+    }
+
+    /// Ask the server to compare the values of up to `sample` randomly chosen keys starting with
+    /// `key_prefix` with those stored on every other replica, and report any that differ.
+    pub async fn verify_replicas(&self, key_prefix: &str, sample: usize) -> Result<ReplicaAudit> {
+        let request = ApiRequestEnvelope {
+            id: self.next_id(),
+            request: ApiRequest::VerifyReplicas {
+                key_prefix: key_prefix.to_string(),
+                sample,
+            },
+        };
+        let response = self.write(request).await?;
+        match response.response {
+            ApiResponse::ToVerifyReplicas { audit } => Ok(audit),
+            ApiResponse::ServerError { msg } => Err(ServerError(msg).boxed()),
+            ApiResponse::CommandDisabled { request_type } => {
+                Err(CommandDisabled(request_type).boxed())
+            }
+            ApiResponse::Starting { phase } => Err(NodeStarting(phase).boxed()),
+            _ => Err(BadResponse(response.response.display_type()).boxed()),
+        }
+    }
+
+    /// Remove `key` from the store, returning `true` if it was present.
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        let request = ApiRequestEnvelope {
+            id: self.next_id(),
+            request: ApiRequest::Delete {
+                key: key.to_string(),
+            },
+        };
+        let response = self.write_to_leader(request).await?;
+        match response.response {
+            ApiResponse::ToDelete { was_present } => Ok(was_present),
+            ApiResponse::ServerError { msg } => Err(ServerError(msg).boxed()),
+            ApiResponse::CommandDisabled { request_type } => {
+                Err(CommandDisabled(request_type).boxed())
+            }
+            ApiResponse::Starting { phase } => Err(NodeStarting(phase).boxed()),
+            ApiResponse::Redirect { leader_address, .. } => {
+                Err(LeaderRequired(leader_address).boxed())
+            }
+            _ => Err(BadResponse(response.response.display_type()).boxed()),
+        }
+    }
+
+    /// Issue each of `requests` (which may only be `Get`, `Put`, or `Delete`) in a single round trip,
+    /// to be handled by the leader in order as if each came after all those before it, with all of
+    /// their writes committed together. Returns the response to each request, in order.
+    pub async fn batch(&self, requests: Vec<ApiRequest>) -> Result<Vec<ApiResponse>> {
+        let request = ApiRequestEnvelope {
+            id: self.next_id(),
+            request: ApiRequest::Batch { requests },
+        };
+        let response = self.write_to_leader(request).await?;
+        match response.response {
+            ApiResponse::ToBatch { responses } => Ok(responses),
+            ApiResponse::ServerError { msg } => Err(ServerError(msg).boxed()),
+            ApiResponse::CommandDisabled { request_type } => {
+                Err(CommandDisabled(request_type).boxed())
+            }
+            ApiResponse::Starting { phase } => Err(NodeStarting(phase).boxed()),
+            ApiResponse::Redirect { leader_address, .. } => {
+                Err(LeaderRequired(leader_address).boxed())
+            }
+            _ => Err(BadResponse(response.response.display_type()).boxed()),
+        }
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        let request = ApiRequestEnvelope {
+            id: self.next_id(),
+            request: ApiRequest::Ping,
+        };
+        let response = self.write(request).await?;
+        match response.response {
+            ApiResponse::Pong => Ok(()),
+            ApiResponse::ServerError { msg } => Err(ServerError(msg).boxed()),
+            ApiResponse::CommandDisabled { request_type } => {
+                Err(CommandDisabled(request_type).boxed())
+            }
+            ApiResponse::Starting { phase } => Err(NodeStarting(phase).boxed()),
             _ => Err(BadResponse(response.response.display_type()).boxed()),
         }
     }
 
+    /// Ping the server until it answers (retrying every `READY_RETRY_INTERVAL_IN_MILLIS`), so that
+    /// callers can wait for it to be reachable before depending on it. Return a `RequestTimeout`
+    /// error if it has not answered within `timeout`.
+    pub async fn ready(&self, timeout: Duration) -> Result<()> {
+        let pinging = async {
+            while self.ping().await.is_err() {
+                time::sleep(Duration::from_millis(READY_RETRY_INTERVAL_IN_MILLIS)).await;
+            }
+        };
+        time::timeout(timeout, pinging)
+            .await
+            .map_err(|_| RequestTimeout.boxed())
+    }
+
+    /// Like `write`, but if the server redirects us to a leader whose api address it knows, move
+    /// our connection over to that leader and retry there (following up to `MAX_REDIRECTS`
+    /// redirects). Return the last response we get, whether or not it is a redirect.
+    async fn write_to_leader(&self, request: ApiRequestEnvelope) -> Result<ApiResponseEnvelope> {
+        let mut response = self.write(request.clone()).await?;
+        for _ in 0..MAX_REDIRECTS {
+            let leader_address = match &response.response {
+                ApiResponse::Redirect {
+                    leader_hint: Some(hint),
+                    ..
+                } => match hint.parse::<SocketAddr>() {
+                    Ok(address) => address,
+                    Err(_) => break,
+                },
+                _ => break,
+            };
+            self.follow_leader(leader_address).await?;
+            response = self
+                .write(ApiRequestEnvelope {
+                    id: self.next_id(),
+                    request: request.request.clone(),
+                })
+                .await?;
+        }
+        Ok(response)
+    }
+
+    /// Replace our connection with one to the leader at `address`, closing the old one (so any
+    /// requests still pending on it are left to time out).
+    async fn follow_leader(&self, address: SocketAddr) -> Result<()> {
+        let socket = self.transport.connect(address).await?;
+        let stream = tls::connect(socket, address, self.tls.as_ref()).await?;
+        let connection = Arc::new(ApiClientConnection::with_codec(stream, self.codec));
+        let previous =
+            std::mem::replace(&mut *self.connection.write().unwrap(), connection.clone());
+        ResponseListener {
+            connection: self.connection.clone(),
+            callbacks: self.on_response_callbacks.clone(),
+            events_tx: self.connection_events_tx.clone(),
+            reconnect: self.reconnect.clone(),
+            codec: self.codec,
+            tls: self.tls.clone(),
+            transport: self.transport.clone(),
+        }
+        .listen(address, connection);
+        let _ = previous.close().await;
+        Ok(())
+    }
+
     /// Write a `request` to a peer `connection` and register a one-shot sender to
     /// handle the peer's response in the shared `response_handlers` hash map owned by the `Client`.
     /// Then wait to either receive the response and return an `Ok<Response>` or timeout
-    /// and return an `Err`.
+    /// and return an `Err`. If `max_inflight_requests` are already pending, wait for one of them
+    /// to finish before writing. If the returned future is dropped early, the request's handler is
+    /// deregistered (so any late response is discarded).
     async fn write(&self, request: ApiRequestEnvelope) -> Result<ApiResponseEnvelope> {
-        let handlers = self.on_response_callbacks.clone();
+        let _permit = self.inflight_permits.acquire().await?;
         let (response_tx, response_rx) = oneshot::channel::<ApiResponseEnvelope>();
-        let _ = handlers.insert(request.id, response_tx);
-        self.connection.write(request).await?;
+        let mut guard = PendingRequestGuard {
+            id: request.id,
+            client: self,
+            is_settled: false,
+        };
+        let _ = self.on_response_callbacks.insert(
+            request.id,
+            PendingRequest {
+                callback: response_tx,
+                request_type: request.request.display_type(),
+                issued_at: Instant::now(),
+            },
+        );
 
-        return tokio::select! {
-            response = response_rx => {
-                response.map_err(|_| Box::new(ConnectionClosed) as AsyncError)
-            }
-            _ = time::sleep(Duration::from_millis(TIMEOUT_IN_MILLIS)) => {
-                Err(Box::new(RequestTimeout))
-            }
+        let connection = self.connection();
+        let result = match connection.write(request).await {
+            Ok(()) => tokio::select! {
+                response = response_rx => {
+                    response.map_err(|_| Box::new(ConnectionClosed) as AsyncError)
+                }
+                _ = time::sleep(Duration::from_millis(TIMEOUT_IN_MILLIS)) => {
+                    Err(RequestTimeout.boxed())
+                }
+            },
+            Err(e) => Err(e),
         };
+        guard.is_settled = true;
+        result
     }
 }
 
@@ -151,6 +652,7 @@ mod test_api_client {
 
     use crate::api::ApiServerConnection;
     use crate::test_support::gen::Gen;
+    use crate::transport::TcpTransport;
 
     use super::*;
 
@@ -162,11 +664,29 @@ mod test_api_client {
         static ref PUT_REQUEST: ApiRequest = ApiRequest::Put {
             key: "foo".to_string(),
             value: "bar".to_string(),
+            is_async: false,
+            min_replicas: None,
+            ttl_ms: None,
+        };
+        static ref ASYNC_PUT_REQUEST: ApiRequest = ApiRequest::Put {
+            key: "foo".to_string(),
+            value: "bar".to_string(),
+            is_async: true,
+            min_replicas: None,
+            ttl_ms: None,
         };
         static ref GET_RESPONSE: ApiResponse = ApiResponse::ToGet {
             value: Some("bar".to_string()),
         };
         static ref PUT_RESPONSE: ApiResponse = ApiResponse::ToPut { was_modified: true };
+        static ref PING_RESPONSE: ApiResponse = ApiResponse::Pong;
+        static ref NEXT_IDS_RESPONSE: ApiResponse = ApiResponse::ToNextIds {
+            start: 42,
+            count: ID_LEASE_SIZE,
+        };
+        static ref GET_JSON_RESPONSE: ApiResponse = ApiResponse::ToGet {
+            value: Some(r#"{"bar":42}"#.to_string()),
+        };
     }
 
     struct Context {
@@ -175,11 +695,15 @@ mod test_api_client {
     }
 
     impl Context {
-        async fn setup(response: Option<ApiResponse>, fuzzed_id: Option<u64>) -> Self {
+        async fn setup(
+            response: Option<ApiResponse>,
+            fuzzed_id: Option<u64>,
+            max_inflight_requests: usize,
+        ) -> Self {
             let buf_size = 1;
             let server_address = Gen::socket_addr();
             let (req_tx, request_rx) = mpsc::channel::<ApiRequestEnvelope>(buf_size);
-            let listener = TcpListener::bind(server_address.clone()).await.unwrap();
+            let listener = TcpListener::bind(server_address).await.unwrap();
             // println!("> Test server listening at {:?}", server_address);
 
             tokio::spawn(async move {
@@ -198,13 +722,25 @@ mod test_api_client {
                         id: fuzzed_id.unwrap_or(req.id),
                         response: body,
                     };
-                    let _ = conn.write(env).await.unwrap();
-                    let _ = conn.close().await.unwrap();
+                    conn.write(env).await.unwrap();
+                    conn.close().await.unwrap();
                 };
             });
 
             Self {
-                client: ApiClientConfig { server_address }.run().await.unwrap(),
+                client: ApiClientConfig {
+                    server_address,
+                    max_inflight_requests,
+                    reconnect: ReconnectConfig::default(),
+                    codec: FrameCodec::default(),
+                    value_format: ValueFormat::default(),
+                    #[cfg(feature = "tls")]
+                    tls: None,
+                    transport: Arc::new(TcpTransport),
+                }
+                .run()
+                .await
+                .unwrap(),
                 request_rx,
             }
         }
@@ -215,7 +751,12 @@ mod test_api_client {
     #[async_trait::async_trait]
     impl AsyncTestContext for ClientReceivingGetResponse {
         async fn setup() -> Self {
-            let ctx = Context::setup(Some(GET_RESPONSE.clone()), None).await;
+            let ctx = Context::setup(
+                Some(GET_RESPONSE.clone()),
+                None,
+                DEFAULT_MAX_INFLIGHT_REQUESTS,
+            )
+            .await;
             Self(ctx)
         }
     }
@@ -224,7 +765,71 @@ mod test_api_client {
     #[async_trait::async_trait]
     impl AsyncTestContext for ClientReceivingPutResponse {
         async fn setup() -> Self {
-            let ctx = Context::setup(Some(PUT_RESPONSE.clone()), None).await;
+            let ctx = Context::setup(
+                Some(PUT_RESPONSE.clone()),
+                None,
+                DEFAULT_MAX_INFLIGHT_REQUESTS,
+            )
+            .await;
+            Self(ctx)
+        }
+    }
+
+    struct ClientReceivingGetJsonResponse(Context);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for ClientReceivingGetJsonResponse {
+        async fn setup() -> Self {
+            let ctx = Context::setup(
+                Some(GET_JSON_RESPONSE.clone()),
+                None,
+                DEFAULT_MAX_INFLIGHT_REQUESTS,
+            )
+            .await;
+            Self(ctx)
+        }
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+    struct Foo {
+        bar: usize,
+    }
+
+    struct ClientReceivingNextIdsResponse(Context);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for ClientReceivingNextIdsResponse {
+        async fn setup() -> Self {
+            let ctx = Context::setup(
+                Some(NEXT_IDS_RESPONSE.clone()),
+                None,
+                DEFAULT_MAX_INFLIGHT_REQUESTS,
+            )
+            .await;
+            Self(ctx)
+        }
+    }
+
+    struct ClientReceivingCommandDisabled(Context);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for ClientReceivingCommandDisabled {
+        async fn setup() -> Self {
+            let response = ApiResponse::CommandDisabled {
+                request_type: "Put".to_string(),
+            };
+            let ctx = Context::setup(Some(response), None, DEFAULT_MAX_INFLIGHT_REQUESTS).await;
+            Self(ctx)
+        }
+    }
+
+    struct ClientReceivingPingResponse(Context);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for ClientReceivingPingResponse {
+        async fn setup() -> Self {
+            let ctx = Context::setup(
+                Some(PING_RESPONSE.clone()),
+                None,
+                DEFAULT_MAX_INFLIGHT_REQUESTS,
+            )
+            .await;
             Self(ctx)
         }
     }
@@ -233,7 +838,30 @@ mod test_api_client {
     #[async_trait::async_trait]
     impl AsyncTestContext for ClientReceivingTimeout {
         async fn setup() -> Self {
-            let ctx = Context::setup(Some(Gen::api_response()), Some(Gen::u64())).await;
+            let ctx = Context::setup(
+                Some(Gen::api_response()),
+                Some(Gen::u64()),
+                DEFAULT_MAX_INFLIGHT_REQUESTS,
+            )
+            .await;
+            Self(ctx)
+        }
+    }
+
+    struct ClientReceivingNoResponse(Context);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for ClientReceivingNoResponse {
+        async fn setup() -> Self {
+            let ctx = Context::setup(None, None, DEFAULT_MAX_INFLIGHT_REQUESTS).await;
+            Self(ctx)
+        }
+    }
+
+    struct ClientAllowingOneInflightRequest(Context);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for ClientAllowingOneInflightRequest {
+        async fn setup() -> Self {
+            let ctx = Context::setup(None, None, 1).await;
             Self(ctx)
         }
     }
@@ -261,6 +889,204 @@ mod test_api_client {
         assert_eq!(actual_response, expected_response);
     }
 
+    #[test_context(ClientReceivingPutResponse)]
+    #[tokio::test]
+    async fn performs_async_put_request(mut ctx: ClientReceivingPutResponse) {
+        ctx.0.client.set_async(true);
+        let actual_response = ctx.0.client.put("foo", "bar").await.unwrap();
+        let actual_request = ctx.0.request_rx.recv().await.unwrap().request;
+
+        assert_eq!(actual_request, ASYNC_PUT_REQUEST.clone());
+        assert!(actual_response);
+    }
+
+    #[test_context(ClientReceivingGetResponse)]
+    #[tokio::test]
+    async fn reports_connection_stats(ctx: ClientReceivingGetResponse) {
+        let _ = ctx.0.client.get("foo").await.unwrap();
+        let stats = ctx.0.client.connection_stats();
+
+        assert_eq!(stats.frames_written, 1);
+        assert_eq!(stats.frames_read, 1);
+    }
+
+    #[tokio::test]
+    async fn reconnects_to_server_that_hung_up() {
+        let server_address = Gen::socket_addr();
+        let listener = TcpListener::bind(server_address).await.unwrap();
+        let client = ApiClientConfig {
+            server_address,
+            max_inflight_requests: DEFAULT_MAX_INFLIGHT_REQUESTS,
+            reconnect: ReconnectConfig::default(),
+            codec: FrameCodec::default(),
+            value_format: ValueFormat::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(TcpTransport),
+        }
+        .run()
+        .await
+        .unwrap();
+        let mut events = client.connection_events();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        drop(socket);
+        let (socket, _) = listener.accept().await.unwrap();
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Disconnected(server_address.to_string())
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Reconnected(server_address.to_string())
+        );
+
+        // requests go out over the new connection
+        let server_conn = ApiServerConnection::new(socket);
+        let (response, _) = tokio::join!(client.ping(), async {
+            let req = server_conn.read().await.unwrap();
+            server_conn
+                .write(ApiResponseEnvelope::of_ping(req.id))
+                .await
+                .unwrap();
+        });
+        assert!(response.is_ok());
+    }
+
+    #[tokio::test]
+    async fn retries_put_against_hinted_leader() {
+        let (follower_address, leader_address) = (Gen::socket_addr(), Gen::socket_addr());
+        let follower_listener = TcpListener::bind(follower_address).await.unwrap();
+        let leader_listener = TcpListener::bind(leader_address).await.unwrap();
+        let client = ApiClientConfig {
+            server_address: follower_address,
+            max_inflight_requests: DEFAULT_MAX_INFLIGHT_REQUESTS,
+            reconnect: ReconnectConfig::default(),
+            codec: FrameCodec::default(),
+            value_format: ValueFormat::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(TcpTransport),
+        }
+        .run()
+        .await
+        .unwrap();
+
+        let (response, redirected_request, retried_request) = tokio::join!(
+            client.put("foo", "bar"),
+            async {
+                let (socket, _) = follower_listener.accept().await.unwrap();
+                let conn = ApiServerConnection::new(socket);
+                let req = conn.read().await.unwrap();
+                let redirect = ApiResponseEnvelope::of_redirect(
+                    req.id,
+                    Gen::socket_addr().to_string(),
+                    Some(leader_address.to_string()),
+                );
+                conn.write(redirect).await.unwrap();
+                req.request
+            },
+            async {
+                let (socket, _) = leader_listener.accept().await.unwrap();
+                let conn = ApiServerConnection::new(socket);
+                let req = conn.read().await.unwrap();
+                conn.write(ApiResponseEnvelope::of_put(req.id, true))
+                    .await
+                    .unwrap();
+                req.request
+            },
+        );
+
+        assert!(response.unwrap());
+        assert_eq!(redirected_request, PUT_REQUEST.clone());
+        assert_eq!(retried_request, PUT_REQUEST.clone());
+    }
+
+    #[test_context(ClientReceivingGetJsonResponse)]
+    #[tokio::test]
+    async fn decodes_value_from_get(ctx: ClientReceivingGetJsonResponse) {
+        let value = ctx.0.client.get_as::<Foo>("foo").await.unwrap();
+        assert_eq!(value, Some(Foo { bar: 42 }));
+    }
+
+    #[test]
+    fn round_trips_values_in_each_format() {
+        for format in [
+            ValueFormat::Json,
+            ValueFormat::MessagePack,
+            ValueFormat::Bincode,
+        ] {
+            let encoded = format.encode(&Foo { bar: 42 }).unwrap();
+            assert_eq!(format.decode::<Foo>(&encoded).unwrap(), Foo { bar: 42 });
+        }
+    }
+
+    #[test]
+    fn fails_to_decode_value_written_in_another_format() {
+        let encoded = ValueFormat::Json.encode(&Foo { bar: 42 }).unwrap();
+        assert!(ValueFormat::MessagePack.decode::<Foo>(&encoded).is_err());
+        assert!(ValueFormat::Bincode.decode::<Foo>(&encoded).is_err());
+    }
+
+    #[test_context(ClientReceivingGetResponse)]
+    #[tokio::test]
+    async fn fails_to_decode_value_of_wrong_type(ctx: ClientReceivingGetResponse) {
+        let result = ctx.0.client.get_as::<Foo>("foo").await;
+        assert!(result.is_err());
+    }
+
+    #[test_context(ClientReceivingPutResponse)]
+    #[tokio::test]
+    async fn encodes_value_for_put(mut ctx: ClientReceivingPutResponse) {
+        let response = ctx
+            .0
+            .client
+            .put_from("foo", &Foo { bar: 42 })
+            .await
+            .unwrap();
+        let request = ctx.0.request_rx.recv().await.unwrap().request;
+
+        assert!(response);
+        assert_eq!(
+            request,
+            ApiRequest::Put {
+                key: "foo".to_string(),
+                value: r#"{"bar":42}"#.to_string(),
+                is_async: false,
+                min_replicas: None,
+                ttl_ms: None,
+            }
+        );
+    }
+
+    #[test_context(ClientReceivingNextIdsResponse)]
+    #[tokio::test]
+    async fn hands_out_unique_ids_from_a_leased_range(mut ctx: ClientReceivingNextIdsResponse) {
+        // the test server only ever answers one request, so the second id must come from the lease
+        let first_id = ctx.0.client.unique_id("ids").await.unwrap();
+        let second_id = ctx.0.client.unique_id("ids").await.unwrap();
+        let request = ctx.0.request_rx.recv().await.unwrap().request;
+
+        assert_eq!((first_id, second_id), (42, 43));
+        assert_eq!(
+            request,
+            ApiRequest::NextIds {
+                sequence_name: "ids".to_string(),
+                count: ID_LEASE_SIZE,
+            }
+        );
+    }
+
+    #[test_context(ClientReceivingCommandDisabled)]
+    #[tokio::test]
+    async fn handles_disabled_command(ctx: ClientReceivingCommandDisabled) {
+        let result = ctx.0.client.put("foo", "bar").await;
+        assert_eq!(
+            result.err().unwrap().downcast_ref(),
+            Some(&CommandDisabled("Put".to_string()))
+        );
+    }
+
     #[test_context(ClientReceivingTimeout)]
     #[tokio::test]
     async fn handles_timeout(mut ctx: ClientReceivingTimeout) {
@@ -269,5 +1095,65 @@ mod test_api_client {
 
         assert!(result.is_err());
         assert_eq!(result.err().unwrap().downcast_ref(), Some(&RequestTimeout));
+        assert_eq!(ctx.0.client.num_cancelled_requests(), 0);
+    }
+
+    #[test_context(ClientReceivingNoResponse)]
+    #[tokio::test]
+    async fn cancels_request_when_caller_stops_waiting(ctx: ClientReceivingNoResponse) {
+        let Context { client, request_rx } = &mut ctx.0;
+        tokio::select! {
+            _ = client.get("foo") => panic!("server should never respond"),
+            _ = request_rx.recv() => {}
+        }
+
+        assert!(client.inflight().is_empty());
+        assert_eq!(client.num_cancelled_requests(), 1);
+    }
+
+    #[test_context(ClientReceivingNoResponse)]
+    #[tokio::test]
+    async fn reports_inflight_requests(ctx: ClientReceivingNoResponse) {
+        let Context { client, request_rx } = &mut ctx.0;
+        let client = &*client;
+        let (_, inflight_while_pending) = tokio::join!(client.get("foo"), async {
+            request_rx.recv().await.unwrap();
+            client.inflight()
+        });
+
+        assert_eq!(inflight_while_pending.len(), 1);
+        assert_eq!(inflight_while_pending[0].request_type, "Get".to_string());
+        assert!(client.inflight().is_empty());
+    }
+
+    #[test_context(ClientAllowingOneInflightRequest)]
+    #[tokio::test]
+    async fn caps_inflight_requests(ctx: ClientAllowingOneInflightRequest) {
+        let Context { client, request_rx } = &mut ctx.0;
+        let client = &*client;
+        let (_, _, inflight_while_pending) =
+            tokio::join!(client.get("foo"), client.get("bar"), async {
+                request_rx.recv().await.unwrap();
+                client.inflight()
+            });
+
+        assert_eq!(inflight_while_pending.len(), 1);
+    }
+
+    #[test_context(ClientReceivingPingResponse)]
+    #[tokio::test]
+    async fn is_ready_once_server_answers_ping(mut ctx: ClientReceivingPingResponse) {
+        let result = ctx.0.client.ready(Duration::from_millis(100)).await;
+        let actual_request = ctx.0.request_rx.recv().await.unwrap().request;
+
+        assert!(result.is_ok());
+        assert_eq!(actual_request, ApiRequest::Ping);
+    }
+
+    #[test_context(ClientReceivingNoResponse)]
+    #[tokio::test]
+    async fn is_not_ready_if_server_never_answers(ctx: ClientReceivingNoResponse) {
+        let result = ctx.0.client.ready(Duration::from_millis(20)).await;
+        assert_eq!(result.err().unwrap().downcast_ref(), Some(&RequestTimeout));
     }
 }
@@ -17,11 +17,68 @@ tcp_serializable!(ApiRequestEnvelope);
 #[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Hash)]
 #[serde(tag = "type", deny_unknown_fields)]
 pub enum ApiRequest {
-    Get { key: String },
-    Put { key: String, value: String },
+    Get {
+        key: String,
+    },
+    Put {
+        key: String,
+        value: String,
+        /// If set, the leader acknowledges the put as soon as it is appended to its own log,
+        /// without waiting for the entry to be replicated and applied (omitted from the wire if unset)
+        #[serde(default, skip_serializing_if = "is_false")]
+        is_async: bool,
+        /// If set, the leader responds only once this many nodes (counting itself) have stored the
+        /// entry, which may be more than the bare majority needed to commit it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min_replicas: Option<usize>,
+        /// If set, the value expires (and reads no longer find it) this many millis after the
+        /// leader receives the put
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl_ms: Option<u64>,
+    },
+    Delete {
+        key: String,
+    },
+    /// Lease the next `count` ids in the named sequence
+    NextIds {
+        sequence_name: String,
+        count: u64,
+    },
+    /// (ADMIN) Compare the values of up to `sample` randomly chosen keys starting with `key_prefix`
+    /// on the node handling the request with those stored on every other replica
+    VerifyReplicas {
+        key_prefix: String,
+        sample: usize,
+    },
+    /// Handle each of `requests` (which may only be `Get`, `Put`, or `Delete`) in order, as if each
+    /// were handled after all those before it, committing all of their writes in a single log
+    /// entry. (Batched puts are replicated together, so they may not set `is_async` or
+    /// `min_replicas`.)
+    Batch {
+        requests: Vec<ApiRequest>,
+    },
+    Ping,
 }
 tcp_serializable!(ApiRequest);
 
+impl ApiRequest {
+    pub fn display_type(&self) -> String {
+        match self {
+            ApiRequest::Get { .. } => "Get".to_string(),
+            ApiRequest::Put { .. } => "Put".to_string(),
+            ApiRequest::Delete { .. } => "Delete".to_string(),
+            ApiRequest::NextIds { .. } => "NextIds".to_string(),
+            ApiRequest::VerifyReplicas { .. } => "VerifyReplicas".to_string(),
+            ApiRequest::Batch { .. } => "Batch".to_string(),
+            ApiRequest::Ping => "Ping".to_string(),
+        }
+    }
+}
+
+fn is_false(flag: &bool) -> bool {
+    !flag
+}
+
 #[cfg(test)]
 mod request_tests {
     use super::*;
@@ -55,6 +112,20 @@ mod request_tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn deserializing_delete_request() {
+        let input: Vec<u8> = r#"{"id":42,"request":{"type":"Delete","key":"foo"}}"#.into();
+        assert_eq!(
+            ApiRequestEnvelope::try_from(input).unwrap(),
+            ApiRequestEnvelope {
+                id: 42,
+                request: ApiRequest::Delete {
+                    key: "foo".to_string(),
+                },
+            }
+        );
+    }
+
     #[test]
     fn deserializing_put_request() {
         let input: Vec<u8> =
@@ -66,6 +137,9 @@ mod request_tests {
                 request: ApiRequest::Put {
                     key: "foo".to_string(),
                     value: "bar".to_string(),
+                    is_async: false,
+                    min_replicas: None,
+                    ttl_ms: None,
                 }
             }
         )
@@ -80,6 +154,9 @@ mod request_tests {
             request: ApiRequest::Put {
                 key: "foo".to_string(),
                 value: "bar".to_string(),
+                is_async: false,
+                min_replicas: None,
+                ttl_ms: None,
             },
         }
         .into();
@@ -87,6 +164,79 @@ mod request_tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn serializing_async_put_request() {
+        let expected: Vec<u8> =
+            r#"{"id":42,"request":{"type":"Put","key":"foo","value":"bar","is_async":true}}"#
+                .into();
+        let actual: Vec<u8> = ApiRequestEnvelope {
+            id: 42,
+            request: ApiRequest::Put {
+                key: "foo".to_string(),
+                value: "bar".to_string(),
+                is_async: true,
+                min_replicas: None,
+                ttl_ms: None,
+            },
+        }
+        .into();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn deserializing_put_request_with_min_replicas() {
+        let input: Vec<u8> =
+            r#"{"id":42,"request":{"type":"Put","key":"foo","value":"bar","min_replicas":3}}"#
+                .into();
+        assert_eq!(
+            ApiRequestEnvelope::try_from(input).unwrap(),
+            ApiRequestEnvelope {
+                id: 42,
+                request: ApiRequest::Put {
+                    key: "foo".to_string(),
+                    value: "bar".to_string(),
+                    is_async: false,
+                    min_replicas: Some(3),
+                    ttl_ms: None,
+                }
+            }
+        )
+    }
+
+    #[test]
+    fn deserializing_batch_request() {
+        let input: Vec<u8> = r#"{"id":42,"request":{"type":"Batch","requests":[{"type":"Get","key":"foo"},{"type":"Delete","key":"bar"}]}}"#.into();
+        assert_eq!(
+            ApiRequestEnvelope::try_from(input).unwrap(),
+            ApiRequestEnvelope {
+                id: 42,
+                request: ApiRequest::Batch {
+                    requests: vec![
+                        ApiRequest::Get {
+                            key: "foo".to_string()
+                        },
+                        ApiRequest::Delete {
+                            key: "bar".to_string()
+                        },
+                    ]
+                }
+            }
+        )
+    }
+
+    #[test]
+    fn serializing_ping_request() {
+        let expected: Vec<u8> = r#"{"id":42,"request":{"type":"Ping"}}"#.into();
+        let actual: Vec<u8> = ApiRequestEnvelope {
+            id: 42,
+            request: ApiRequest::Ping,
+        }
+        .into();
+
+        assert_eq!(expected, actual);
+    }
+
     #[test]
     fn deserializing_invalid_request() {
         let input: Vec<u8> = "foo".into();
@@ -4,6 +4,7 @@ use std::result::Result as StdResult;
 use serde::{Deserialize, Serialize};
 use serde_json;
 
+use crate::node::StartupPhase;
 use crate::tcp_serializable;
 
 #[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Hash)]
@@ -17,20 +18,74 @@ tcp_serializable!(ApiResponseEnvelope);
 #[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Hash)]
 #[serde(tag = "type", deny_unknown_fields)]
 pub enum ApiResponse {
-    ToGet { value: Option<String> },
-    ToPut { was_modified: bool },
-    Redirect { leader_address: String },
-    ServerError { msg: String },
+    ToGet {
+        value: Option<String>,
+    },
+    ToPut {
+        was_modified: bool,
+    },
+    ToDelete {
+        was_present: bool,
+    },
+    ToNextIds {
+        start: u64,
+        count: u64,
+    },
+    ToVerifyReplicas {
+        audit: ReplicaAudit,
+    },
+    /// The response to each request in a `Batch`, in the order they were given
+    ToBatch {
+        responses: Vec<ApiResponse>,
+    },
+    Redirect {
+        leader_address: String,
+        /// Address of the leader's api server, if known (so that clients may retry against it)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        leader_hint: Option<String>,
+    },
+    ServerError {
+        msg: String,
+    },
+    CommandDisabled {
+        request_type: String,
+    },
+    Starting {
+        phase: StartupPhase,
+    },
+    Pong,
 }
 tcp_serializable!(ApiResponse);
 
+/// The outcome of comparing a sample of keys across replicas (see `ApiRequest::VerifyReplicas`)
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Hash, Default)]
+pub struct ReplicaAudit {
+    pub keys_checked: Vec<String>,
+    pub divergences: Vec<ReplicaDivergence>,
+    pub unresponsive_replicas: Vec<String>, // replicas that did not report their values in time
+}
+
+/// A key whose value on some replica differs from that on the node that ran the audit
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Hash)]
+pub struct ReplicaDivergence {
+    pub key: String,
+    pub replica: String,
+}
+
 impl ApiResponse {
     pub fn display_type(&self) -> String {
         match self {
             ApiResponse::ToGet { .. } => "ToGet".to_string(),
             ApiResponse::ToPut { .. } => "ToPut".to_string(),
+            ApiResponse::ToDelete { .. } => "ToDelete".to_string(),
+            ApiResponse::ToNextIds { .. } => "ToNextIds".to_string(),
+            ApiResponse::ToVerifyReplicas { .. } => "ToVerifyReplicas".to_string(),
+            ApiResponse::ToBatch { .. } => "ToBatch".to_string(),
             ApiResponse::Redirect { .. } => "Redirect".to_string(),
             ApiResponse::ServerError { .. } => "ServerError".to_string(),
+            ApiResponse::CommandDisabled { .. } => "CommandDisabled".to_string(),
+            ApiResponse::Starting { .. } => "Starting".to_string(),
+            ApiResponse::Pong => "Pong".to_string(),
         }
     }
 }
@@ -54,10 +109,61 @@ impl ApiResponseEnvelope {
             response: { ApiResponse::ToPut { was_modified } },
         }
     }
-    pub fn of_redirect(id: u64, leader_address: String) -> ApiResponseEnvelope {
+    pub fn of_delete(id: u64, was_present: bool) -> ApiResponseEnvelope {
+        ApiResponseEnvelope {
+            id,
+            response: { ApiResponse::ToDelete { was_present } },
+        }
+    }
+    pub fn of_next_ids(id: u64, start: u64, count: u64) -> ApiResponseEnvelope {
+        ApiResponseEnvelope {
+            id,
+            response: { ApiResponse::ToNextIds { start, count } },
+        }
+    }
+    pub fn of_verify_replicas(id: u64, audit: ReplicaAudit) -> ApiResponseEnvelope {
+        ApiResponseEnvelope {
+            id,
+            response: ApiResponse::ToVerifyReplicas { audit },
+        }
+    }
+    pub fn of_batch(id: u64, responses: Vec<ApiResponse>) -> ApiResponseEnvelope {
+        ApiResponseEnvelope {
+            id,
+            response: ApiResponse::ToBatch { responses },
+        }
+    }
+    pub fn of_starting(id: u64, phase: StartupPhase) -> ApiResponseEnvelope {
+        ApiResponseEnvelope {
+            id,
+            response: ApiResponse::Starting { phase },
+        }
+    }
+    pub fn of_disabled(id: u64, request_type: String) -> ApiResponseEnvelope {
+        ApiResponseEnvelope {
+            id,
+            response: ApiResponse::CommandDisabled { request_type },
+        }
+    }
+    pub fn of_ping(id: u64) -> ApiResponseEnvelope {
         ApiResponseEnvelope {
             id,
-            response: { ApiResponse::Redirect { leader_address } },
+            response: ApiResponse::Pong,
+        }
+    }
+    pub fn of_redirect(
+        id: u64,
+        leader_address: String,
+        leader_hint: Option<String>,
+    ) -> ApiResponseEnvelope {
+        ApiResponseEnvelope {
+            id,
+            response: {
+                ApiResponse::Redirect {
+                    leader_address,
+                    leader_hint,
+                }
+            },
         }
     }
 }
@@ -94,6 +200,20 @@ mod response_tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn deserializing_delete_response() {
+        let input: Vec<u8> =
+            r#"{"id":42,"response":{"type":"ToDelete","was_present":true}}"#.into();
+
+        assert_eq!(
+            ApiResponseEnvelope::try_from(input).unwrap(),
+            ApiResponseEnvelope {
+                id: 42,
+                response: ApiResponse::ToDelete { was_present: true },
+            }
+        );
+    }
+
     #[test]
     fn deserializing_put_response() {
         let input: Vec<u8> = r#"{"id":42,"response":{"type":"ToPut","was_modified":true}}"#.into();
@@ -133,4 +253,15 @@ mod response_tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn deserializing_redirect_without_leader_hint() {
+        let input: Vec<u8> =
+            r#"{"id":42,"response":{"type":"Redirect","leader_address":"127.0.0.1:3000"}}"#.into();
+
+        assert_eq!(
+            ApiResponseEnvelope::try_from(input).unwrap(),
+            ApiResponseEnvelope::of_redirect(42, "127.0.0.1:3000".to_string(), None),
+        );
+    }
 }
@@ -1,38 +1,142 @@
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use tokio::net::{TcpListener, TcpStream};
+use rand::Rng;
+use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
-use tokio::sync::oneshot::Sender as OneShotSender;
+use tokio::sync::oneshot::{Receiver as OneShotReceiver, Sender as OneShotSender};
+use tokio::time::{sleep, Duration};
 
-use crate::api::request::ApiRequestEnvelope;
+use crate::api::request::{ApiRequest, ApiRequestEnvelope};
 use crate::api::response::{ApiResponse, ApiResponseEnvelope};
 use crate::api::ApiServerConnection;
-use crate::error::Result;
+use crate::error::ConfigError::InvalidInjectedErrorRate;
+#[cfg(feature = "tls")]
+use crate::error::ConfigError::TlsOverEncryptedTransport;
+use crate::error::{NetworkError, Result};
+use crate::tcp::BoxedStream;
+use crate::tls;
+#[cfg(feature = "tls")]
+use crate::tls::TlsServerConfig;
+use crate::transport::Transport;
+use crate::CHAN_BUF_SIZE;
 
 pub type RespondableApiRequest = (ApiRequestEnvelope, ApiResponder);
 pub type ApiResponder = OneShotSender<ApiResponseEnvelope>;
 
 pub struct ApiServerConfig {
     pub address: SocketAddr,
+    // if set, write responses on each connection in the order their requests arrived (otherwise
+    // write each response as soon as it is ready)
+    pub ordered_responses: bool,
+    // faults to inject into requests of each type (as given by `ApiRequest::display_type`), for
+    // exercising clients' timeout and retry handling in staging environments (empty in production!)
+    pub injected_faults: HashMap<String, InjectedFault>,
+    // which types of request this listener accepts (eg: to expose only reads on a public listener)
+    pub command_filter: CommandFilter,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsServerConfig>, // if set, only accept clients that connect over TLS
+    pub transport: Arc<dyn Transport>,
 }
+
+/// Which types of request (as given by `ApiRequest::display_type`) an `ApiServer` accepts. Others
+/// are answered with `CommandDisabled` before they reach the node.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum CommandFilter {
+    #[default]
+    AllowAll,
+    Allow(HashSet<String>), // accept only these
+    Deny(HashSet<String>),  // accept all but these
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InjectedFault {
+    pub latency: Duration, // delay before handling each request
+    pub error_rate: f64,   // fraction of requests (from 0 to 1) to fail with a `ServerError`
+}
+
+impl InjectedFault {
+    /// Check that `error_rate` is a fraction we can actually fail requests of `request_type` at.
+    pub fn validate(&self, request_type: &str) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.error_rate) {
+            return Err(
+                InvalidInjectedErrorRate(request_type.to_string(), self.error_rate).boxed(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl CommandFilter {
+    /// Whether we accept `request` (and, if it is a `Batch`, every request in it)
+    pub fn accepts_request(&self, request: &ApiRequest) -> bool {
+        self.accepts(&request.display_type())
+            && match request {
+                ApiRequest::Batch { requests } => requests.iter().all(|r| self.accepts_request(r)),
+                _ => true,
+            }
+    }
+
+    pub fn accepts(&self, request_type: &str) -> bool {
+        match self {
+            CommandFilter::AllowAll => true,
+            CommandFilter::Allow(allowed) => allowed.contains(request_type),
+            CommandFilter::Deny(denied) => !denied.contains(request_type),
+        }
+    }
+}
+
 pub struct ApiServer {
     pub address: SocketAddr, // TODO: use this to issue `stop()`
 }
 
 impl ApiServerConfig {
+    /// Start listening for requests, emitting them over `request_tx` (failing fast if any of the
+    /// `injected_faults` is invalid).
     pub async fn run_with(self, request_tx: Sender<RespondableApiRequest>) -> Result<ApiServer> {
-        let tcp_listener = TcpListener::bind(self.address.clone()).await?;
+        for (request_type, fault) in &self.injected_faults {
+            fault.validate(request_type)?;
+        }
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() && self.transport.encrypts() {
+            return Err(TlsOverEncryptedTransport.boxed());
+        }
+        #[cfg(feature = "tls")]
+        let acceptor = self.tls.as_ref().map(TlsServerConfig::load).transpose()?;
+        #[cfg(not(feature = "tls"))]
+        let acceptor: Option<tls::TlsAcceptor> = None;
+        let mut listener = self.transport.bind(self.address).await?;
         println!("> ApiServer listening on {:?}", &self.address);
 
+        let ordered_responses = self.ordered_responses;
+        let injected_faults = Arc::new(self.injected_faults);
+        let command_filter = Arc::new(self.command_filter);
         tokio::spawn(async move {
             // TODO: use select loop here to handle poison pill for shutdown
             loop {
-                let (socket, client_addr) = tcp_listener.accept().await.unwrap();
+                let (socket, client_addr) = listener.accept().await.unwrap();
                 println!("> ApiServer got connection on {}", &client_addr);
                 let request_tx = request_tx.clone();
-                tokio::spawn(async move { ApiServer::handle_messages(socket, request_tx).await });
+                let injected_faults = injected_faults.clone();
+                let command_filter = command_filter.clone();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    match tls::accept(socket, acceptor.as_ref()).await {
+                        Ok(stream) => {
+                            ApiServer::handle_messages(
+                                stream,
+                                request_tx,
+                                ordered_responses,
+                                injected_faults,
+                                command_filter,
+                            )
+                            .await
+                        }
+                        Err(e) => eprintln!("> ApiServer rejected {}: {}", &client_addr, e),
+                    }
+                });
             }
         });
 
@@ -47,17 +151,51 @@ impl ApiServer {
     /// over a `request_tx` to a subscriber (to whom we delegate the business logic of determining
     /// how to respond), then issue whatever `ApiResponse` is received from the responder back to
     /// the `ApiClient` from whom we received the request.
-    async fn handle_messages(socket: TcpStream, request_tx: Sender<RespondableApiRequest>) {
-        let connection = Arc::new(ApiServerConnection::new(socket));
+    ///
+    /// If `ordered_responses` is set, queue each responder's receiving end for a single writer task
+    /// that awaits them one at a time, so that a response never overtakes one to an earlier request.
+    ///
+    /// If an `InjectedFault` is configured for a request's type, apply it before emitting the request.
+    ///
+    /// If the `command_filter` does not accept a request's type, answer it with `CommandDisabled`
+    /// instead of emitting it.
+    async fn handle_messages(
+        socket: BoxedStream,
+        request_tx: Sender<RespondableApiRequest>,
+        ordered_responses: bool,
+        injected_faults: Arc<HashMap<String, InjectedFault>>,
+        command_filter: Arc<CommandFilter>,
+    ) {
+        let connection = Arc::new(ApiServerConnection::negotiated(socket));
+        let ordered_response_tx = if ordered_responses {
+            Some(Self::write_in_order(connection.clone()))
+        } else {
+            None
+        };
 
         tokio::spawn(async move {
             loop {
                 let (response_tx, response_rx) = oneshot::channel::<ApiResponseEnvelope>();
 
                 match connection.read().await {
-                    Ok(req) => {
-                        let _ = request_tx.send((req, response_tx)).await;
+                    Ok(req) if !command_filter.accepts_request(&req.request) => {
+                        let response =
+                            ApiResponseEnvelope::of_disabled(req.id, req.request.display_type());
+                        let _ = response_tx.send(response);
                     }
+                    Ok(req) => match injected_faults.get(&req.request.display_type()) {
+                        Some(fault) => {
+                            let (fault, request_tx) = (fault.clone(), request_tx.clone());
+                            tokio::spawn(async move {
+                                Self::inject_fault(fault, req, response_tx, request_tx).await
+                            });
+                        }
+                        None => {
+                            let _ = request_tx.send((req, response_tx)).await;
+                        }
+                    },
+                    // stop listening if connection to client is no longer usable
+                    Err(e) if NetworkError::is_disconnect(&e) => return,
                     Err(e) => {
                         let _ = response_tx.send(ApiResponseEnvelope {
                             id: 0,
@@ -66,6 +204,10 @@ impl ApiServer {
                     }
                 }
 
+                if let Some(ordered_response_tx) = &ordered_response_tx {
+                    let _ = ordered_response_tx.send(response_rx).await;
+                    continue;
+                }
                 let write_connection = connection.clone();
                 tokio::spawn(async move {
                     // TODO: insert timeout here?
@@ -77,6 +219,39 @@ impl ApiServer {
         });
     }
 
+    /// Wait out the `fault`'s latency, then either fail the request (with probability given by its
+    /// error rate) or emit it as usual.
+    async fn inject_fault(
+        fault: InjectedFault,
+        req: ApiRequestEnvelope,
+        response_tx: ApiResponder,
+        request_tx: Sender<RespondableApiRequest>,
+    ) {
+        sleep(fault.latency).await;
+        if rand::thread_rng().gen_bool(fault.error_rate) {
+            let msg = format!("injected fault in {}", req.request.display_type());
+            let _ = response_tx.send(ApiResponseEnvelope::error_of(req.id, msg));
+        } else {
+            let _ = request_tx.send((req, response_tx)).await;
+        }
+    }
+
+    /// Spawn a task that writes responses to `connection` in the order their receivers are sent
+    /// over the returned channel, and stops when the channel is dropped.
+    fn write_in_order(
+        connection: Arc<ApiServerConnection>,
+    ) -> Sender<OneShotReceiver<ApiResponseEnvelope>> {
+        let (tx, mut rx) = mpsc::channel::<OneShotReceiver<ApiResponseEnvelope>>(CHAN_BUF_SIZE);
+        tokio::spawn(async move {
+            while let Some(response_rx) = rx.recv().await {
+                if let Ok(response) = response_rx.await {
+                    let _ = connection.write(response).await;
+                }
+            }
+        });
+        tx
+    }
+
     // async fn stop(&self) {
     // TODO: shut down gracefully
     //  - strategy: pass a poison pill to loop spawned in `run()` (which should become a select)!
@@ -89,12 +264,16 @@ impl ApiServer {
 #[cfg(test)]
 mod api_server_tests {
     use test_context::{test_context, AsyncTestContext};
+    use tokio::net::TcpStream;
     use tokio::sync::mpsc;
     use tokio::sync::mpsc::Receiver;
 
+    use crate::api::request::ApiRequest;
     use crate::api::ApiClientConnection;
     use crate::test_support::gen::Gen;
-    use crate::CHAN_BUF_SIZE;
+    #[cfg(feature = "quic")]
+    use crate::transport::QuicTransport;
+    use crate::transport::TcpTransport;
 
     use super::*;
 
@@ -107,17 +286,28 @@ mod api_server_tests {
         client_conn: ApiClientConnection,
     }
 
-    #[async_trait::async_trait]
-    impl AsyncTestContext for RunningServer {
-        async fn setup() -> Self {
+    impl RunningServer {
+        async fn start(
+            ordered_responses: bool,
+            injected_faults: HashMap<String, InjectedFault>,
+            command_filter: CommandFilter,
+        ) -> Self {
             let address = Gen::socket_addr();
             let (request_tx, request_rx) = mpsc::channel::<RespondableApiRequest>(CHAN_BUF_SIZE);
 
             // TODO: hold onto this assignment to test shutdown...
-            let _ = ApiServerConfig { address }
-                .run_with(request_tx)
-                .await
-                .unwrap();
+            let _ = ApiServerConfig {
+                address,
+                ordered_responses,
+                injected_faults,
+                command_filter,
+                #[cfg(feature = "tls")]
+                tls: None,
+                transport: Arc::new(TcpTransport),
+            }
+            .run_with(request_tx)
+            .await
+            .unwrap();
 
             let socket = TcpStream::connect(address).await.unwrap();
             let client_conn = ApiClientConnection::new(socket);
@@ -127,13 +317,93 @@ mod api_server_tests {
                 client_conn,
             }
         }
+
+        /// Issue two requests, then respond to the second before the first, returning the ids of
+        /// the responses in the order the client received them.
+        async fn respond_out_of_order(&mut self) -> (u64, u64) {
+            for id in 0..2 {
+                let request = ApiRequestEnvelope {
+                    id,
+                    request: Gen::api_request(),
+                };
+                self.client_conn.write(request).await.unwrap();
+            }
+            let (_, first_responder) = self.request_rx.recv().await.unwrap();
+            let (_, second_responder) = self.request_rx.recv().await.unwrap();
+            second_responder
+                .send(ApiResponseEnvelope::of_get(1, None))
+                .unwrap();
+            first_responder
+                .send(ApiResponseEnvelope::of_get(0, None))
+                .unwrap();
+
+            let first_received = self.client_conn.read().await.unwrap();
+            let second_received = self.client_conn.read().await.unwrap();
+            (first_received.id, second_received.id)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for RunningServer {
+        async fn setup() -> Self {
+            Self::start(false, HashMap::new(), CommandFilter::AllowAll).await
+        }
+    }
+
+    struct RunningOrderedServer(RunningServer);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for RunningOrderedServer {
+        async fn setup() -> Self {
+            Self(RunningServer::start(true, HashMap::new(), CommandFilter::AllowAll).await)
+        }
+    }
+
+    struct RunningFaultyServer(RunningServer);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for RunningFaultyServer {
+        async fn setup() -> Self {
+            let injected_faults = vec![
+                (
+                    "Get".to_string(),
+                    InjectedFault {
+                        latency: Duration::from_millis(20),
+                        error_rate: 0.0,
+                    },
+                ),
+                (
+                    "Ping".to_string(),
+                    InjectedFault {
+                        latency: Duration::from_millis(0),
+                        error_rate: 1.0,
+                    },
+                ),
+            ];
+            Self(
+                RunningServer::start(
+                    false,
+                    injected_faults.into_iter().collect(),
+                    CommandFilter::AllowAll,
+                )
+                .await,
+            )
+        }
+    }
+
+    struct RunningReadOnlyServer(RunningServer);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for RunningReadOnlyServer {
+        async fn setup() -> Self {
+            let allowed = vec!["Get".to_string(), "Ping".to_string()];
+            let command_filter = CommandFilter::Allow(allowed.into_iter().collect());
+            Self(RunningServer::start(false, HashMap::new(), command_filter).await)
+        }
     }
 
     #[test_context(RunningServer)]
     #[tokio::test]
     async fn listens_for_requests_from_client_and_puts_them_on_channel(mut ctx: RunningServer) {
         let expected_req = Gen::api_request_envelope();
-        let _ = ctx.client_conn.write(expected_req.clone()).await.unwrap();
+        ctx.client_conn.write(expected_req.clone()).await.unwrap();
         let (actual_req, _) = ctx.request_rx.recv().await.unwrap();
         assert_eq!(expected_req, actual_req);
     }
@@ -142,13 +412,278 @@ mod api_server_tests {
     #[tokio::test]
     async fn listens_for_responses_on_channel_and_writes_them_to_client(mut ctx: RunningServer) {
         let request = Gen::api_request_envelope();
-        let _ = ctx.client_conn.write(request.clone()).await.unwrap();
+        ctx.client_conn.write(request.clone()).await.unwrap();
 
         let expected_response = Gen::api_response_envelope();
         let (_, responder) = ctx.request_rx.recv().await.unwrap();
-        let _ = responder.send(expected_response.clone()).unwrap();
+        responder.send(expected_response.clone()).unwrap();
 
         let actual_response = ctx.client_conn.read().await.unwrap();
         assert_eq!(expected_response, actual_response);
     }
+
+    #[test_context(RunningServer)]
+    #[tokio::test]
+    async fn writes_responses_as_soon_as_they_are_ready(mut ctx: RunningServer) {
+        assert_eq!(ctx.respond_out_of_order().await, (1, 0));
+    }
+
+    #[test_context(RunningOrderedServer)]
+    #[tokio::test]
+    async fn writes_responses_in_request_order_if_configured(mut ctx: RunningOrderedServer) {
+        assert_eq!(ctx.0.respond_out_of_order().await, (0, 1));
+    }
+
+    #[test_context(RunningFaultyServer)]
+    #[tokio::test]
+    async fn delays_requests_with_injected_latency(mut ctx: RunningFaultyServer) {
+        let request = ApiRequestEnvelope {
+            id: 42,
+            request: ApiRequest::Get {
+                key: "foo".to_string(),
+            },
+        };
+        let started_at = tokio::time::Instant::now();
+        ctx.0.client_conn.write(request.clone()).await.unwrap();
+        let (actual_request, _) = ctx.0.request_rx.recv().await.unwrap();
+
+        assert_eq!(actual_request, request);
+        assert!(started_at.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_run_with_invalid_injected_error_rate() {
+        for error_rate in [-0.1, 1.1, f64::NAN, f64::INFINITY] {
+            let (request_tx, _) = mpsc::channel::<RespondableApiRequest>(CHAN_BUF_SIZE);
+            let fault = InjectedFault {
+                latency: Duration::from_millis(0),
+                error_rate,
+            };
+            let result = ApiServerConfig {
+                address: Gen::socket_addr(),
+                ordered_responses: false,
+                injected_faults: vec![("Get".to_string(), fault)].into_iter().collect(),
+                command_filter: CommandFilter::AllowAll,
+                #[cfg(feature = "tls")]
+                tls: None,
+                transport: Arc::new(TcpTransport),
+            }
+            .run_with(request_tx)
+            .await;
+
+            assert_eq!(
+                result.err().unwrap().to_string(),
+                InvalidInjectedErrorRate("Get".to_string(), error_rate).to_string(),
+            );
+        }
+    }
+
+    #[test_context(RunningFaultyServer)]
+    #[tokio::test]
+    async fn fails_requests_with_injected_errors(mut ctx: RunningFaultyServer) {
+        let request = ApiRequestEnvelope {
+            id: 42,
+            request: ApiRequest::Ping,
+        };
+        ctx.0.client_conn.write(request).await.unwrap();
+        let response = ctx.0.client_conn.read().await.unwrap();
+
+        assert_eq!(
+            response,
+            ApiResponseEnvelope::error_of(42, "injected fault in Ping".to_string())
+        );
+        assert!(ctx.0.request_rx.try_recv().is_err());
+    }
+
+    #[test_context(RunningReadOnlyServer)]
+    #[tokio::test]
+    async fn rejects_requests_the_listener_does_not_accept(mut ctx: RunningReadOnlyServer) {
+        let request = ApiRequestEnvelope {
+            id: 42,
+            request: ApiRequest::Delete {
+                key: "foo".to_string(),
+            },
+        };
+        ctx.0.client_conn.write(request).await.unwrap();
+        let response = ctx.0.client_conn.read().await.unwrap();
+
+        assert_eq!(
+            response,
+            ApiResponseEnvelope::of_disabled(42, "Delete".to_string())
+        );
+        assert!(ctx.0.request_rx.try_recv().is_err());
+    }
+
+    #[test_context(RunningReadOnlyServer)]
+    #[tokio::test]
+    async fn passes_on_requests_the_listener_accepts(mut ctx: RunningReadOnlyServer) {
+        let request = ApiRequestEnvelope {
+            id: 42,
+            request: ApiRequest::Get {
+                key: "foo".to_string(),
+            },
+        };
+        ctx.0.client_conn.write(request.clone()).await.unwrap();
+        let (actual_request, _) = ctx.0.request_rx.recv().await.unwrap();
+        assert_eq!(actual_request, request);
+    }
+
+    /// Start a server at `address` accepting only clients that connect over TLS per `server_tls`
+    #[cfg(feature = "tls")]
+    async fn start_tls_server(
+        address: SocketAddr,
+        server_tls: TlsServerConfig,
+    ) -> Receiver<RespondableApiRequest> {
+        let (request_tx, request_rx) = mpsc::channel::<RespondableApiRequest>(CHAN_BUF_SIZE);
+        let _ = ApiServerConfig {
+            address,
+            ordered_responses: false,
+            injected_faults: HashMap::new(),
+            command_filter: CommandFilter::AllowAll,
+            tls: Some(server_tls),
+            transport: Arc::new(TcpTransport),
+        }
+        .run_with(request_tx)
+        .await
+        .unwrap();
+        request_rx
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn handles_requests_from_client_connected_over_tls() {
+        let path = format!("test_data/tls_{}", Gen::usize());
+        let (server_tls, client_tls) = Gen::tls_configs(&path).await;
+        let address = Gen::socket_addr();
+        let mut request_rx = start_tls_server(address, server_tls).await;
+
+        let socket = TcpTransport.connect(address).await.unwrap();
+        let connector = client_tls.load().unwrap();
+        let stream = tls::connect(socket, address, Some(&connector))
+            .await
+            .unwrap();
+        let client_conn = ApiClientConnection::new(stream);
+        let request = ApiRequestEnvelope {
+            id: 42,
+            request: ApiRequest::Ping,
+        };
+        client_conn.write(request.clone()).await.unwrap();
+        let (actual_request, responder) = request_rx.recv().await.unwrap();
+        responder.send(ApiResponseEnvelope::of_ping(42)).unwrap();
+        let response = client_conn.read().await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(actual_request, request);
+        assert_eq!(response.unwrap(), ApiResponseEnvelope::of_ping(42));
+    }
+
+    #[cfg(feature = "quic")]
+    #[tokio::test]
+    async fn handles_requests_from_client_connected_over_quic() {
+        let path = format!("test_data/quic_{}", Gen::usize());
+        let (server_tls, client_tls) = Gen::tls_configs(&path).await;
+        let transport = QuicTransport::new(Some(&server_tls), &client_tls).unwrap();
+        let address = Gen::socket_addr();
+        let (request_tx, mut request_rx) = mpsc::channel::<RespondableApiRequest>(CHAN_BUF_SIZE);
+        let _ = ApiServerConfig {
+            address,
+            ordered_responses: false,
+            injected_faults: HashMap::new(),
+            command_filter: CommandFilter::AllowAll,
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(transport.clone()),
+        }
+        .run_with(request_tx)
+        .await
+        .unwrap();
+
+        let stream = transport.connect(address).await.unwrap();
+        let client_conn = ApiClientConnection::new(stream);
+        let request = ApiRequestEnvelope {
+            id: 42,
+            request: ApiRequest::Ping,
+        };
+        client_conn.write(request.clone()).await.unwrap();
+        let (actual_request, responder) = request_rx.recv().await.unwrap();
+        responder.send(ApiResponseEnvelope::of_ping(42)).unwrap();
+        let response = client_conn.read().await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(actual_request, request);
+        assert_eq!(response.unwrap(), ApiResponseEnvelope::of_ping(42));
+    }
+
+    #[cfg(feature = "quic")]
+    #[tokio::test]
+    async fn refuses_to_run_with_tls_over_quic() {
+        let path = format!("test_data/quic_{}", Gen::usize());
+        let (server_tls, client_tls) = Gen::tls_configs(&path).await;
+        let transport = QuicTransport::new(Some(&server_tls), &client_tls).unwrap();
+        let (request_tx, _) = mpsc::channel::<RespondableApiRequest>(CHAN_BUF_SIZE);
+        let result = ApiServerConfig {
+            address: Gen::socket_addr(),
+            ordered_responses: false,
+            injected_faults: HashMap::new(),
+            command_filter: CommandFilter::AllowAll,
+            tls: Some(server_tls),
+            transport: Arc::new(transport),
+        }
+        .run_with(request_tx)
+        .await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            TlsOverEncryptedTransport.to_string()
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn drops_client_that_does_not_connect_over_tls() {
+        let path = format!("test_data/tls_{}", Gen::usize());
+        let (server_tls, _) = Gen::tls_configs(&path).await;
+        let address = Gen::socket_addr();
+        let mut request_rx = start_tls_server(address, server_tls).await;
+
+        let socket = TcpStream::connect(address).await.unwrap();
+        let client_conn = ApiClientConnection::new(socket);
+        let request = ApiRequestEnvelope {
+            id: 42,
+            request: ApiRequest::Ping,
+        };
+        client_conn.write(request).await.unwrap();
+        let response = client_conn.read().await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert!(response.is_err());
+        assert!(request_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn denies_listed_commands() {
+        let filter = CommandFilter::Deny(vec!["Put".to_string()].into_iter().collect());
+        assert!(!filter.accepts("Put"));
+        assert!(filter.accepts("Get"));
+    }
+
+    #[test]
+    fn denies_batches_containing_listed_commands() {
+        let filter = CommandFilter::Deny(vec!["Put".to_string()].into_iter().collect());
+        let batch_of = |requests| ApiRequest::Batch { requests };
+        let get = ApiRequest::Get {
+            key: "foo".to_string(),
+        };
+        let put = ApiRequest::Put {
+            key: "foo".to_string(),
+            value: "bar".to_string(),
+            is_async: false,
+            min_replicas: None,
+            ttl_ms: None,
+        };
+
+        assert!(filter.accepts_request(&batch_of(vec![get.clone()])));
+        assert!(!filter.accepts_request(&batch_of(vec![get, put])));
+    }
 }
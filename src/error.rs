@@ -1,12 +1,9 @@
-#![allow(unused_imports)]
+// `err_derive` expands to impls nested inside anonymous consts
+#![allow(non_local_definitions)]
 
-#[cfg(feature = "std")]
-use std::error::Error;
-#[cfg(not(feature = "std"))]
-use std::fmt::Display;
-
-use crate::rpc::request::AppendEntriesRequest;
+use crate::node::StartupPhase;
 use err_derive::Error;
+use std::io::ErrorKind;
 
 pub type AsyncError = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T> = std::result::Result<T, AsyncError>;
@@ -26,8 +23,16 @@ pub enum NetworkError {
     NoPeerAtAddress(String),
     #[error(display = "peer connection closed")]
     ConnectionClosed,
+    #[error(display = "peer connection reset")]
+    ConnectionReset,
+    #[error(display = "peer connection closed mid-frame after {} bytes", _0)]
+    PartialFrame(usize),
+    #[error(display = "frame of {} bytes exceeds maximum frame size", _0)]
+    FrameTooLarge(usize),
     #[error(display = "request timed out")]
     RequestTimeout,
+    #[error(display = "TLS handshake timed out")]
+    HandshakeTimeout,
     #[error(display = "broadcast failed to receive successful response from majority of peers")]
     BroadcastFailure,
     #[error(display = "parallel requests failed to join")]
@@ -37,6 +42,37 @@ pub enum NetworkError {
 }
 boxed_async_err!(NetworkError);
 
+impl NetworkError {
+    /// Whether an error returned from `Connection::read` means the connection is no longer usable
+    /// (in which case callers should stop reading rather than retry): the peer closed or reset it,
+    /// it ended mid-frame, or a frame was too large for us to find where the next one starts. Io
+    /// errors count only if they are of a kind that means the stream is gone (or, for
+    /// `InvalidData`, cannot be resynchronized, as after a corrupt TLS record), so that eg: a read
+    /// that times out may simply be retried.
+    pub fn is_disconnect(err: &AsyncError) -> bool {
+        if let Some(err) = err.downcast_ref::<NetworkError>() {
+            return matches!(
+                err,
+                NetworkError::ConnectionClosed
+                    | NetworkError::ConnectionReset
+                    | NetworkError::PartialFrame(_)
+                    | NetworkError::FrameTooLarge(_)
+            );
+        }
+        err.downcast_ref::<std::io::Error>().is_some_and(|err| {
+            matches!(
+                err.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::NotConnected
+                    | ErrorKind::UnexpectedEof
+                    | ErrorKind::InvalidData
+            )
+        })
+    }
+}
+
 #[derive(Debug, Error, PartialEq)]
 pub enum ProtocolError {
     #[error(display = "unexpected response type: {:?}", _0)]
@@ -54,6 +90,26 @@ pub enum ProtocolError {
     LogReplicationFailure,
     #[error(display = "AppendEntry failed. Retry with decremented index {}", _0)]
     RetryAppendEntry(usize),
+    #[error(display = "server does not accept {} requests on this listener", _0)]
+    CommandDisabled(String),
+    #[error(display = "async puts may not require a minimum number of replicas")]
+    AsyncPutWithMinReplicas,
+    #[error(
+        display = "cannot store on {} replicas in a cluster of {} nodes",
+        _0,
+        _1
+    )]
+    TooManyReplicasRequested(usize, usize),
+    #[error(display = "must lease at least one id")]
+    EmptyIdAllocation,
+    #[error(display = "not enough ids left in sequence {} to lease {}", _0, _1)]
+    SequenceExhausted(String, u64),
+    #[error(display = "{} requests may not be batched", _0)]
+    UnbatchableRequest(String),
+    #[error(display = "batched puts may not be async or require a minimum number of replicas")]
+    UnbatchablePutOptions,
+    #[error(display = "server is still starting up (phase: {:?})", _0)]
+    NodeStarting(StartupPhase),
 }
 boxed_async_err!(ProtocolError);
 
@@ -76,5 +132,85 @@ pub enum PersistenceError {
     RemoveFromEmptyLogError,
     #[error(display = "could not parse metadata_for_test_node from stored value")]
     MetadataParseError,
+    #[error(display = "failed to deserialize snapshot: {:?}", _0)]
+    SnapshotDeserializationError(String),
 }
 boxed_async_err!(PersistenceError);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ConfigError {
+    #[error(display = "heartbeat interval must be greater than zero")]
+    ZeroHeartbeatInterval,
+    #[error(
+        display = "election timeout range is empty: min {}ms exceeds max {}ms",
+        _0,
+        _1
+    )]
+    EmptyElectionTimeoutRange(u64, u64),
+    #[error(
+        display = "election timeout of {}ms must be at least {} heartbeat intervals of {}ms",
+        _0,
+        _1,
+        _2
+    )]
+    ElectionTimeoutTooShort(u64, u64, u64),
+    #[error(display = "invalid TLS credentials: {}", _0)]
+    InvalidTlsCredentials(String),
+    #[error(display = "TLS for peers requires a CA bundle to verify their client certificates")]
+    MissingClientCaBundle,
+    #[error(
+        display = "injected error rate for {} requests must be between 0 and 1, got {}",
+        _0,
+        _1
+    )]
+    InvalidInjectedErrorRate(String, f64),
+    #[error(display = "reconnect jitter must be between 0 and 1, got {}", _0)]
+    InvalidReconnectJitter(f64),
+    #[error(display = "QUIC listeners require a TLS certificate and key to present to clients")]
+    MissingQuicServerCredentials,
+    #[error(display = "TLS may not be layered over a transport that already encrypts (eg: QUIC)")]
+    TlsOverEncryptedTransport,
+}
+boxed_async_err!(ConfigError);
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+    use NetworkError::{
+        ConnectionClosed, ConnectionReset, FrameTooLarge, MessageDeserializationError,
+        PartialFrame, RequestTimeout,
+    };
+
+    #[test]
+    fn deserialization_errors_are_not_disconnects() {
+        let err = MessageDeserializationError("foo".to_string()).boxed();
+        assert!(!NetworkError::is_disconnect(&err));
+    }
+
+    #[test]
+    fn connection_errors_are_disconnects() {
+        let errs: Vec<AsyncError> = vec![
+            ConnectionClosed.boxed(),
+            ConnectionReset.boxed(),
+            PartialFrame(42).boxed(),
+            FrameTooLarge(42).boxed(),
+            Box::new(std::io::Error::from(ErrorKind::BrokenPipe)),
+            Box::new(std::io::Error::from(ErrorKind::UnexpectedEof)),
+        ];
+        for err in errs {
+            assert!(NetworkError::is_disconnect(&err));
+        }
+    }
+
+    #[test]
+    fn other_errors_are_not_disconnects() {
+        let errs: Vec<AsyncError> = vec![
+            RequestTimeout.boxed(),
+            Box::new(std::io::Error::from(ErrorKind::TimedOut)),
+            Box::new(std::io::Error::from(ErrorKind::Interrupted)),
+        ];
+        for err in errs {
+            assert!(!NetworkError::is_disconnect(&err));
+        }
+    }
+}
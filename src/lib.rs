@@ -7,11 +7,18 @@ pub mod node;
 pub mod rpc;
 pub mod state;
 pub mod tcp;
+#[cfg(test)]
 mod test_support;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(not(feature = "tls"))]
+#[path = "no_tls.rs"]
+pub(crate) mod tls;
+pub mod transport;
 
 pub type NodeAddr = String;
 
-pub const NEWLINE: u8 = '\n' as u8;
+pub const NEWLINE: u8 = b'\n';
 
 pub fn hash(input: &Vec<u8>) -> u64 {
     use std::collections::hash_map::DefaultHasher;
@@ -21,6 +28,8 @@ pub fn hash(input: &Vec<u8>) -> u64 {
     hasher.finish()
 }
 
+pub const CHAN_BUF_SIZE: usize = 16;
+
 #[cfg(test)]
 mod hasher_tests {
     use super::*;
@@ -30,5 +39,3 @@ mod hasher_tests {
         assert_eq!(hash(&b"foo".to_vec()), hash(&b"foo".to_vec()))
     }
 }
-
-pub const CHAN_BUF_SIZE: usize = 16;
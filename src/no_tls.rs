@@ -0,0 +1,25 @@
+//! Stands in for the `tls` module in builds without the `tls` feature. There is no way to get a
+//! connector or acceptor here, so streams are always left as they are.
+
+use std::net::SocketAddr;
+
+use crate::error::Result;
+use crate::tcp::BoxedStream;
+
+#[derive(Clone)]
+pub enum TlsAcceptor {}
+
+#[derive(Clone)]
+pub enum TlsConnector {}
+
+pub async fn accept(socket: BoxedStream, _: Option<&TlsAcceptor>) -> Result<BoxedStream> {
+    Ok(socket)
+}
+
+pub async fn connect(
+    socket: BoxedStream,
+    _: SocketAddr,
+    _: Option<&TlsConnector>,
+) -> Result<BoxedStream> {
+    Ok(socket)
+}
@@ -1,21 +1,40 @@
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
+use futures::future;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{sleep, Duration};
 
 use crate::api::request::{ApiRequest, ApiRequestEnvelope};
-use crate::api::response::ApiResponseEnvelope;
-use crate::api::server::{ApiServer, ApiServerConfig, RespondableApiRequest};
-use crate::error::ProtocolError::LogReplicationFailure;
+use crate::api::response::{ApiResponse, ApiResponseEnvelope, ReplicaAudit, ReplicaDivergence};
+use crate::api::server::{
+    ApiServer, ApiServerConfig, CommandFilter, InjectedFault, RespondableApiRequest,
+};
+use crate::error::ConfigError::{
+    ElectionTimeoutTooShort, EmptyElectionTimeoutRange, ZeroHeartbeatInterval,
+};
+use crate::error::ProtocolError;
+use crate::error::ProtocolError::{
+    AsyncPutWithMinReplicas, LogReplicationFailure, TooManyReplicasRequested,
+    UnbatchablePutOptions, UnbatchableRequest,
+};
 use crate::error::Result;
 use crate::rpc::client::{RpcClient, RpcClientConfig, RpcResponseInContext};
-use crate::rpc::request::{RpcRequest, RpcRequestEnvelope};
-use crate::rpc::response::{RpcResponse, RpcResponseEnvelope};
+use crate::rpc::request::{DigestValuesRequest, RpcRequest, RpcRequestEnvelope};
+use crate::rpc::response::{DigestValuesResponse, RpcResponse, RpcResponseEnvelope};
 use crate::rpc::server::{RespondableRpcRequest, RpcServer, RpcServerConfig};
 use crate::state::log::Command;
+use crate::state::store::now_in_millis;
 use crate::state::{State, StateConfig};
+use crate::tcp::{ConnectionStats, FrameCodec, ReconnectConfig};
+#[cfg(feature = "tls")]
+use crate::tls::{TlsClientConfig, TlsServerConfig};
+use crate::transport::Transport;
 use crate::NodeAddr;
 use crate::CHAN_BUF_SIZE;
 
@@ -24,57 +43,158 @@ pub const HEARTBEAT_INTERVAL_IN_MILLIS: u64 = 200;
 #[cfg(test)]
 pub const HEARTBEAT_INTERVAL_IN_MILLIS: u64 = 2;
 #[cfg(not(test))]
+pub const ELECTION_TIMEOUT_RANGE_IN_MILLIS: (u64, u64) = (1500, 3000);
+#[cfg(test)]
+pub const ELECTION_TIMEOUT_RANGE_IN_MILLIS: (u64, u64) = (20, 40);
+// followers must wait this many missed heartbeats before timing out a leader
+pub const MIN_HEARTBEATS_PER_ELECTION_TIMEOUT: u64 = 5;
+#[cfg(not(test))]
 pub const API_PUT_TIMEOUT_IN_MILLIS: u64 = 5 * 1000 * 60; // 5 min
 #[cfg(test)]
 pub const API_PUT_TIMEOUT_IN_MILLIS: u64 = 50;
+#[cfg(not(test))]
+pub const EXPIRY_SWEEP_INTERVAL_IN_MILLIS: u64 = 1000;
+#[cfg(test)]
+pub const EXPIRY_SWEEP_INTERVAL_IN_MILLIS: u64 = 10;
+#[cfg(not(test))]
+pub const VERIFY_REPLICAS_TIMEOUT_IN_MILLIS: u64 = 5000;
+#[cfg(test)]
+pub const VERIFY_REPLICAS_TIMEOUT_IN_MILLIS: u64 = 50;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Role {
     Leader,
     Follower,
+    Candidate,
+}
+
+/// The steps a node takes on its way to serving clients (in the order it takes them). Until it
+/// reaches `Serving`, a node answers every api request with `ApiResponse::Starting`, rather than
+/// answering from state it has not finished loading or catching up on.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum StartupPhase {
+    LoadingSnapshot,
+    ReplayingLog,
+    JoiningCluster, // connecting to peers
+    CatchingUp,     // waiting to apply every entry the leader has committed
+    Serving,
 }
 
 pub struct NodeConfig {
-    role: Role,
+    role: Role, // role to start out in (nodes may win or lose leadership in later elections)
     api_address: SocketAddr, // TODO: make these strings that get converted to SocketAddr in `run`
     rpc_address: SocketAddr, // same
+    ordered_api_responses: bool,
+    injected_api_faults: HashMap<String, InjectedFault>,
+    api_command_filter: CommandFilter,
     leader_address: NodeAddr,
     peer_addresses: Vec<SocketAddr>,
+    rpc_codec: FrameCodec, // how we frame requests to peers (their servers follow our lead)
+    #[cfg(feature = "tls")]
+    api_tls: Option<TlsServerConfig>, // if set, clients must connect to us over TLS
+    #[cfg(feature = "tls")]
+    rpc_tls: Option<TlsServerConfig>, // if set, peers must connect to us over TLS (with certificates)
+    #[cfg(feature = "tls")]
+    peer_tls: Option<TlsClientConfig>, // if set, we connect to peers over TLS
+    reconnect: ReconnectConfig, // how we re-establish dropped peer connections (normally unlimited)
+    api_transport: Arc<dyn Transport>, // carries traffic from clients
+    rpc_transport: Arc<dyn Transport>, // carries traffic to and from peers
     log_path: String,
     metadata_path: String,
+    timing: TimingConfig,
+    snapshot_interval_in_millis: Option<u64>, // if unset, only snapshot when `Node::snapshot` is called
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimingConfig {
+    pub heartbeat_interval_in_millis: u64,
+    // followers pick an election timeout uniformly at random from this (inclusive) range
+    pub election_timeout_range_in_millis: (u64, u64),
 }
 
 #[allow(unused)]
 pub struct Node {
-    role: Arc<Role>, // TODO: eliminate `role` field in favor of `LeaderNode`/`FollowerNode`/`CandidateNode` variants
     api_server: Arc<ApiServer>,
     rpc_client: Arc<RpcClient>,
     rpc_server: Arc<RpcServer>,
     state: Arc<State>,
+    num_dropped_async_puts: Arc<AtomicU64>,
+    timing: TimingConfig,
 }
 
 impl Role {
     pub fn is_leader(&self) -> bool {
-        match self {
-            Role::Leader => true,
-            _ => false,
+        matches!(self, Role::Leader)
+    }
+}
+
+impl Default for TimingConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval_in_millis: HEARTBEAT_INTERVAL_IN_MILLIS,
+            election_timeout_range_in_millis: ELECTION_TIMEOUT_RANGE_IN_MILLIS,
         }
     }
 }
 
+impl TimingConfig {
+    /// Check that the timing knobs relate sensibly to one another: heartbeats must actually recur,
+    /// and followers must wait for `MIN_HEARTBEATS_PER_ELECTION_TIMEOUT` heartbeats to be missed
+    /// before timing out a leader (so that a healthy leader is never deposed by ordinary jitter).
+    pub fn validate(&self) -> Result<()> {
+        let heartbeat = self.heartbeat_interval_in_millis;
+        let (election_min, election_max) = self.election_timeout_range_in_millis;
+
+        if heartbeat == 0 {
+            return Err(ZeroHeartbeatInterval.boxed());
+        }
+        if election_min > election_max {
+            return Err(EmptyElectionTimeoutRange(election_min, election_max).boxed());
+        }
+        if election_min < heartbeat * MIN_HEARTBEATS_PER_ELECTION_TIMEOUT {
+            return Err(ElectionTimeoutTooShort(
+                election_min,
+                MIN_HEARTBEATS_PER_ELECTION_TIMEOUT,
+                heartbeat,
+            )
+            .boxed());
+        }
+        Ok(())
+    }
+}
+
 impl NodeConfig {
+    /// Create a live `Node` from an inert `NodeConfig` (failing fast if its `timing` is invalid).
     pub async fn run(self) -> Result<Node> {
+        self.timing.validate()?;
         let api_server_config = ApiServerConfig {
             address: self.api_address,
+            ordered_responses: self.ordered_api_responses,
+            injected_faults: self.injected_api_faults,
+            command_filter: self.api_command_filter,
+            #[cfg(feature = "tls")]
+            tls: self.api_tls,
+            transport: self.api_transport,
         };
         let rpc_server_config = RpcServerConfig {
-            address: self.rpc_address.clone(),
+            address: self.rpc_address,
+            #[cfg(feature = "tls")]
+            tls: self.rpc_tls,
+            transport: self.rpc_transport.clone(),
         };
         let rpc_client_config = RpcClientConfig {
             peer_addresses: self.peer_addresses.clone(),
+            reconnect: self.reconnect,
+            codec: self.rpc_codec,
+            #[cfg(feature = "tls")]
+            tls: self.peer_tls,
+            transport: self.rpc_transport,
         };
         let state_config = StateConfig {
+            role: self.role,
             leader_address: self.leader_address,
             node_address: self.rpc_address.to_string(),
+            api_address: self.api_address.to_string(),
             peer_addresses: self
                 .peer_addresses
                 .into_iter()
@@ -91,31 +211,48 @@ impl NodeConfig {
         let (api_request_tx, api_request_rx) =
             mpsc::channel::<RespondableApiRequest>(CHAN_BUF_SIZE);
 
-        let role = Arc::new(self.role);
         let state = Arc::new(state_config.run().await?);
         let rpc_server = Arc::new(rpc_server_config.run_with(rpc_request_tx).await?);
         let rpc_client = Arc::new(rpc_client_config.run_with(rpc_response_tx).await?);
+        // leaders are (by definition) caught up, but followers must hear from one to know they are
+        state.advance_startup_phase(match self.role {
+            Role::Leader => StartupPhase::Serving,
+            Role::Follower | Role::Candidate => StartupPhase::CatchingUp,
+        });
         let api_server = Arc::new(api_server_config.run_with(api_request_tx).await?);
+        let num_dropped_async_puts = Arc::new(AtomicU64::new(0));
 
-        Node::handle_rpc_requests(rpc_request_rx, role.clone(), state.clone());
-        Node::handle_rpc_responses(rpc_response_rx, state.clone());
+        Node::handle_rpc_requests(rpc_request_rx, state.clone());
+        Node::handle_rpc_responses(rpc_response_rx, rpc_client.clone(), state.clone());
         Node::handle_api_requests(
             api_request_rx,
             rpc_client.clone(),
-            role.clone(),
             state.clone(),
+            num_dropped_async_puts.clone(),
         );
 
-        if role.is_leader() {
-            Node::run_heartbeat(rpc_client.clone(), state.clone());
+        Node::run_heartbeat(
+            self.timing.heartbeat_interval_in_millis,
+            rpc_client.clone(),
+            state.clone(),
+        );
+        Node::run_election_timer(
+            self.timing.election_timeout_range_in_millis,
+            rpc_client.clone(),
+            state.clone(),
+        );
+        if let Some(interval_in_millis) = self.snapshot_interval_in_millis {
+            Node::run_snapshots(interval_in_millis, state.clone());
         }
+        Node::run_expiry_sweeper(EXPIRY_SWEEP_INTERVAL_IN_MILLIS, state.clone());
 
         Ok(Node {
-            role,
             api_server,
             rpc_client,
             rpc_server,
             state,
+            num_dropped_async_puts,
+            timing: self.timing,
         })
     }
 }
@@ -132,63 +269,484 @@ impl Node {
     /// responds with failure so client may retry. If it succeeds, we respond indicating whether the
     /// key that was put to the state machine modified a previous value or not.
     ///
-    /// Followers handle `Put` by redirecting to the leader so client may retry.
+    /// Leaders handle async `Put`s by responding as soon as the command has been appended to their
+    /// own log, then replicating it in the background, counting it in `num_dropped_async_puts` if
+    /// replication fails or times out.
+    ///
+    /// Leaders handle `Delete` like a synchronous `Put`, responding with whether the key was present.
+    ///
+    /// Leaders handle `NextIds` by appending an allocation of the next range of ids in the sequence
+    /// and responding with that range once it has been replicated.
+    ///
+    /// Leaders handle a `Batch` by appending a single entry carrying the writes of every request in
+    /// it (so that they are applied atomically), responding to each request once that entry has
+    /// been replicated as if each were handled after all those before it. All nodes answer batches
+    /// holding only `Get`s from their own store, as they do single `Get`s.
+    ///
+    /// Followers and candidates handle `Put`, `Delete`, `NextIds`, and `Batch`es holding writes by
+    /// redirecting to the (last known) leader so client may retry.
+    ///
+    /// All nodes handle `VerifyReplicas` by comparing a sample of their own values with those on
+    /// every peer (see `verify_replicas`).
+    ///
+    /// All nodes answer `Ping` with `Pong` (so clients can tell when a node is ready to serve).
+    ///
+    /// Nodes that are still starting up answer every request with their `StartupPhase`.
     pub fn handle_api_requests(
         mut api_request_rx: Receiver<RespondableApiRequest>,
         rpc_client: Arc<RpcClient>,
-        role: Arc<Role>,
         state: Arc<State>,
+        num_dropped_async_puts: Arc<AtomicU64>,
     ) {
         tokio::spawn(async move {
             while let Some((ApiRequestEnvelope { id, request }, responder)) =
                 api_request_rx.recv().await
             {
+                let phase = state.get_startup_phase();
                 let response: ApiResponseEnvelope = match request {
+                    _ if phase != StartupPhase::Serving => {
+                        ApiResponseEnvelope::of_starting(id, phase)
+                    }
                     ApiRequest::Get { key } => {
                         let value = state.fetch_from_store(&key).await;
                         ApiResponseEnvelope::of_get(id, value)
                     }
-                    ApiRequest::Put { key, value } => match role.as_ref() {
+                    ApiRequest::Ping => ApiResponseEnvelope::of_ping(id),
+                    ApiRequest::Batch { requests } => {
+                        match Self::plan_batch(requests, &state).await {
+                            Err(e) => ApiResponseEnvelope::error_of(id, e.to_string()),
+                            Ok((commands, responses)) if commands.is_empty() => {
+                                ApiResponseEnvelope::of_batch(id, responses)
+                            }
+                            Ok((commands, responses)) => match state.get_role().await {
+                                Role::Leader => {
+                                    match state.append_to_log(Command::Batch { commands }).await {
+                                        Ok(log_index)
+                                            if Self::replicate(
+                                                log_index,
+                                                None,
+                                                rpc_client.clone(),
+                                                state.clone(),
+                                            )
+                                            .await =>
+                                        {
+                                            ApiResponseEnvelope::of_batch(id, responses)
+                                        }
+                                        _ => ApiResponseEnvelope::error_of(
+                                            id,
+                                            LogReplicationFailure.to_string(),
+                                        ),
+                                    }
+                                }
+                                Role::Follower | Role::Candidate => {
+                                    Self::redirect(id, &state).await
+                                }
+                            },
+                        }
+                    }
+                    ApiRequest::VerifyReplicas { key_prefix, sample } => {
+                        // (in the background, since audits wait on every peer to answer)
+                        let (rpc_client, state) = (rpc_client.clone(), state.clone());
+                        tokio::spawn(async move {
+                            let audit =
+                                Self::verify_replicas(&key_prefix, sample, rpc_client, state).await;
+                            let _ =
+                                responder.send(ApiResponseEnvelope::of_verify_replicas(id, audit));
+                        });
+                        continue;
+                    }
+                    ApiRequest::NextIds {
+                        sequence_name,
+                        count,
+                    } => match state.get_role().await {
+                        Role::Leader => {
+                            match state.append_id_allocation(&sequence_name, count).await {
+                                Ok((log_index, start))
+                                    if Self::replicate(
+                                        log_index,
+                                        None,
+                                        rpc_client.clone(),
+                                        state.clone(),
+                                    )
+                                    .await =>
+                                {
+                                    ApiResponseEnvelope::of_next_ids(id, start, count)
+                                }
+                                Ok(_) => ApiResponseEnvelope::error_of(
+                                    id,
+                                    LogReplicationFailure.to_string(),
+                                ),
+                                Err(e) => ApiResponseEnvelope::error_of(id, e.to_string()),
+                            }
+                        }
+                        Role::Follower | Role::Candidate => Self::redirect(id, &state).await,
+                    },
+                    ApiRequest::Delete { key } => match state.get_role().await {
+                        Role::Leader => {
+                            let was_present = state.fetch_from_store(&key).await.is_some();
+                            match state.append_to_log(Command::Delete { key }).await {
+                                Ok(log_index)
+                                    if Self::replicate(
+                                        log_index,
+                                        None,
+                                        rpc_client.clone(),
+                                        state.clone(),
+                                    )
+                                    .await =>
+                                {
+                                    ApiResponseEnvelope::of_delete(id, was_present)
+                                }
+                                _ => ApiResponseEnvelope::error_of(
+                                    id,
+                                    LogReplicationFailure.to_string(),
+                                ),
+                            }
+                        }
+                        Role::Follower | Role::Candidate => Self::redirect(id, &state).await,
+                    },
+                    ApiRequest::Put {
+                        is_async: true,
+                        min_replicas: Some(_),
+                        ..
+                    } => ApiResponseEnvelope::error_of(id, AsyncPutWithMinReplicas.to_string()),
+                    ApiRequest::Put {
+                        min_replicas: Some(min_replicas),
+                        ..
+                    } if min_replicas > state.get_peer_addresses().len() + 1 => {
+                        let num_nodes = state.get_peer_addresses().len() + 1;
+                        ApiResponseEnvelope::error_of(
+                            id,
+                            TooManyReplicasRequested(min_replicas, num_nodes).to_string(),
+                        )
+                    }
+                    ApiRequest::Put {
+                        key,
+                        value,
+                        is_async,
+                        min_replicas,
+                        ttl_ms,
+                    } => match state.get_role().await {
                         Role::Leader => {
                             let is_modification =
                                 state.fetch_from_store(&key).await != Some(value.clone());
-                            if let Ok(log_index) =
-                                state.append_to_log(Command::Put { key, value }).await
-                            {
-                                // Register a callback that will be called in `State::apply_all_until`.
-                                // Trigger an attempt to sync logs, return when callback is triggered
-                                // (indicating this command has been successfully replicated) or times out.
-                                let (on_apply_tx, on_apply_rx) = oneshot::channel::<()>();
-                                state.register_on_apply_handler(log_index, on_apply_tx);
-                                let _ = Self::sync_logs(rpc_client.clone(), state.clone()).await;
-                                tokio::select! {
-                                    result = on_apply_rx => {
-                                        if result.is_ok() {
-                                            ApiResponseEnvelope::of_put(id, is_modification)
-                                        } else {
-                                            ApiResponseEnvelope::error_of(id, LogReplicationFailure.to_string())
+                            // (a ttl too long to represent never expires)
+                            let expires_at =
+                                ttl_ms.map(|ttl_ms| now_in_millis().saturating_add(ttl_ms));
+                            let command = Command::Put {
+                                key,
+                                value,
+                                expires_at,
+                            };
+                            match state.append_to_log(command).await {
+                                Ok(log_index) if is_async => {
+                                    let (rpc_client, state, num_dropped_async_puts) = (
+                                        rpc_client.clone(),
+                                        state.clone(),
+                                        num_dropped_async_puts.clone(),
+                                    );
+                                    tokio::spawn(async move {
+                                        if !Self::replicate(log_index, None, rpc_client, state)
+                                            .await
+                                        {
+                                            num_dropped_async_puts.fetch_add(1, Ordering::SeqCst);
                                         }
-                                    }
-                                    _ = sleep(Duration::from_millis(API_PUT_TIMEOUT_IN_MILLIS)) => {
-                                        ApiResponseEnvelope::error_of(id, LogReplicationFailure.to_string())
+                                    });
+                                    ApiResponseEnvelope::of_put(id, is_modification)
+                                }
+                                Ok(log_index) => {
+                                    if Self::replicate(
+                                        log_index,
+                                        min_replicas,
+                                        rpc_client.clone(),
+                                        state.clone(),
+                                    )
+                                    .await
+                                    {
+                                        ApiResponseEnvelope::of_put(id, is_modification)
+                                    } else {
+                                        ApiResponseEnvelope::error_of(
+                                            id,
+                                            LogReplicationFailure.to_string(),
+                                        )
                                     }
                                 }
-                            } else {
-                                ApiResponseEnvelope::error_of(id, LogReplicationFailure.to_string())
+                                Err(_) => ApiResponseEnvelope::error_of(
+                                    id,
+                                    LogReplicationFailure.to_string(),
+                                ),
                             }
                         }
-                        Role::Follower => {
-                            ApiResponseEnvelope::of_redirect(id, state.get_leader_address().await)
-                        }
+                        Role::Follower | Role::Candidate => Self::redirect(id, &state).await,
                     },
                 };
 
-                let _ = responder.send(response)?;
+                responder.send(response)?;
             }
             Ok::<(), ApiResponseEnvelope>(())
         });
     }
 
+    /// Work out the commands that carry out the writes in a batch of `requests`, and the response to
+    /// each request as if it were handled after all those before it. Fail on the first request that
+    /// may not be batched (or put that asks to be replicated on its own terms), if any.
+    async fn plan_batch(
+        requests: Vec<ApiRequest>,
+        state: &State,
+    ) -> std::result::Result<(Vec<Command>, Vec<ApiResponse>), ProtocolError> {
+        let now = now_in_millis();
+        // values written by earlier requests in the batch (`None` for keys they deleted)
+        let mut written: HashMap<String, Option<String>> = HashMap::new();
+        let mut commands = Vec::new();
+        let mut responses = Vec::new();
+        for request in requests {
+            match request {
+                ApiRequest::Get { key } => {
+                    let value = Self::batched_value(&key, &written, state).await;
+                    responses.push(ApiResponse::ToGet { value });
+                }
+                ApiRequest::Put { is_async: true, .. }
+                | ApiRequest::Put {
+                    min_replicas: Some(_),
+                    ..
+                } => return Err(UnbatchablePutOptions),
+                ApiRequest::Put {
+                    key, value, ttl_ms, ..
+                } => {
+                    let previous = Self::batched_value(&key, &written, state).await;
+                    responses.push(ApiResponse::ToPut {
+                        was_modified: previous.as_ref() != Some(&value),
+                    });
+                    written.insert(key.clone(), Some(value.clone()));
+                    commands.push(Command::Put {
+                        key,
+                        value,
+                        expires_at: ttl_ms.map(|ttl_ms| now.saturating_add(ttl_ms)),
+                    });
+                }
+                ApiRequest::Delete { key } => {
+                    let previous = Self::batched_value(&key, &written, state).await;
+                    responses.push(ApiResponse::ToDelete {
+                        was_present: previous.is_some(),
+                    });
+                    written.insert(key.clone(), None);
+                    commands.push(Command::Delete { key });
+                }
+                request => return Err(UnbatchableRequest(request.display_type())),
+            }
+        }
+        Ok((commands, responses))
+    }
+
+    /// The value of `key` once the earlier requests in a batch (which wrote `written`) have been handled
+    async fn batched_value(
+        key: &str,
+        written: &HashMap<String, Option<String>>,
+        state: &State,
+    ) -> Option<String> {
+        match written.get(key) {
+            Some(value) => value.clone(),
+            None => state.fetch_from_store(key).await,
+        }
+    }
+
+    /// (FOLLOWERS AND CANDIDATES ONLY)
+    /// Redirect a client to the (last known) leader, hinting at the address of the leader's api
+    /// server if the leader has told us what it is.
+    async fn redirect(id: u64, state: &State) -> ApiResponseEnvelope {
+        ApiResponseEnvelope::of_redirect(
+            id,
+            state.get_leader_address().await,
+            state.get_leader_api_address().await,
+        )
+    }
+
+    /// (LEADERS ONLY)
+    /// Register a callback that will be called in `State::apply_all_until` when the entry at
+    /// `log_index` is applied, then trigger an attempt to sync logs. Return `true` when the callback
+    /// is triggered (indicating the entry has been successfully replicated), or `false` if it is
+    /// dropped or times out. If `min_replicas` is given, also wait for that many nodes to store the
+    /// entry before returning `true`.
+    async fn replicate(
+        log_index: usize,
+        min_replicas: Option<usize>,
+        rpc_client: Arc<RpcClient>,
+        state: Arc<State>,
+    ) -> bool {
+        let (on_apply_tx, on_apply_rx) = oneshot::channel::<()>();
+        state.register_on_apply_handler(log_index, on_apply_tx);
+        let on_replicated_rx = min_replicas.map(|min_replicas| {
+            let (on_replicated_tx, on_replicated_rx) = oneshot::channel::<()>();
+            state.register_on_replicated_handler(log_index, min_replicas, on_replicated_tx);
+            on_replicated_rx
+        });
+        Self::sync_logs(rpc_client, state.clone()).await;
+
+        let applied = async { on_apply_rx.await.is_ok() };
+        let replicated = async {
+            match on_replicated_rx {
+                Some(rx) => rx.await.is_ok(),
+                None => true,
+            }
+        };
+        let is_replicated = tokio::select! {
+            (is_applied, is_replicated) = future::join(applied, replicated) => {
+                is_applied && is_replicated
+            }
+            _ = sleep(Duration::from_millis(API_PUT_TIMEOUT_IN_MILLIS)) => false,
+        };
+        state.on_replicated_callbacks.remove(&log_index);
+        is_replicated
+    }
+
+    /// (ALL NODES)
+    /// Sample up to `sample` of our keys starting with `key_prefix`, ask every peer for digests of
+    /// its values at those keys, and report each key whose digest on some peer differs from ours,
+    /// along with any peers that do not answer within `VERIFY_REPLICAS_TIMEOUT_IN_MILLIS`. (Since
+    /// followers may lag behind the leader, a divergence may be transient: re-run the audit to
+    /// tell lag apart from lasting damage.)
+    async fn verify_replicas(
+        key_prefix: &str,
+        sample: usize,
+        rpc_client: Arc<RpcClient>,
+        state: Arc<State>,
+    ) -> ReplicaAudit {
+        let keys = state.store.sample_keys(key_prefix, sample);
+        let own_digests = state.store.digest(&keys);
+        let peer_addresses = state.get_peer_addresses();
+
+        let audit_id = rpc_client.next_id();
+        let (digests_tx, mut digests_rx) = mpsc::unbounded_channel();
+        state.register_on_digest_handler(audit_id, digests_tx);
+        let requests = peer_addresses
+            .iter()
+            .map(|peer_address| {
+                let request = DigestValuesRequest {
+                    audit_id,
+                    keys: keys.clone(),
+                };
+                (peer_address.clone(), RpcRequest::DigestValues(request))
+            })
+            .collect();
+        let _ = rpc_client.send_many(requests).await;
+
+        let mut unresponsive_replicas: HashSet<NodeAddr> = peer_addresses.into_iter().collect();
+        let mut divergences = Vec::new();
+        let collecting = async {
+            while !unresponsive_replicas.is_empty() {
+                let (peer_address, digests) = match digests_rx.recv().await {
+                    Some(reported) => reported,
+                    None => break,
+                };
+                if !unresponsive_replicas.remove(&peer_address) {
+                    continue;
+                }
+                for (idx, key) in keys.iter().enumerate() {
+                    if digests.get(idx) != Some(&own_digests[idx]) {
+                        divergences.push(ReplicaDivergence {
+                            key: key.clone(),
+                            replica: peer_address.clone(),
+                        });
+                    }
+                }
+            }
+        };
+        let _ = tokio::time::timeout(
+            Duration::from_millis(VERIFY_REPLICAS_TIMEOUT_IN_MILLIS),
+            collecting,
+        )
+        .await;
+        state.on_digest_callbacks.remove(&audit_id);
+
+        let mut unresponsive_replicas = unresponsive_replicas.into_iter().collect::<Vec<_>>();
+        unresponsive_replicas.sort();
+        ReplicaAudit {
+            keys_checked: keys,
+            divergences,
+            unresponsive_replicas,
+        }
+    }
+
+    /// Number of times this node has seen two different leaders claim the same term.
+    pub fn num_split_brain_alarms(&self) -> u64 {
+        self.state.num_split_brain_alarms.load(Ordering::SeqCst)
+    }
+
+    /// Snapshot the store and compact the log up to the last applied entry (keeping any entries
+    /// some peer has not yet stored -- as leaders know, and followers learn from them -- so that no
+    /// node ever has to ship a snapshot to another to catch it up).
+    /// Return the index of the last entry captured in the snapshot.
+    pub async fn snapshot(&self) -> Result<usize> {
+        Self::take_snapshot(self.state.clone()).await
+    }
+
+    async fn take_snapshot(state: Arc<State>) -> Result<usize> {
+        let max_compacted_index = match state.get_role().await {
+            Role::Leader => state.get_min_match_index(),
+            Role::Follower | Role::Candidate => state.get_leader_compactable_index().await,
+        };
+        state.snapshot(max_compacted_index).await
+    }
+
+    /// (ALL NODES)
+    /// Snapshot the store and compact the log every `interval_in_millis`
+    pub fn run_snapshots(interval_in_millis: u64, state: Arc<State>) {
+        tokio::spawn(async move {
+            loop {
+                sleep(Duration::from_millis(interval_in_millis)).await;
+                if let Err(e) = Self::take_snapshot(state.clone()).await {
+                    eprintln!("> Failed to take snapshot: {}", e);
+                }
+            }
+        });
+    }
+
+    /// (ALL NODES)
+    /// Remove keys whose time to live has run out from the store every `interval_in_millis` (so
+    /// that keys no one reads after they expire do not linger forever)
+    pub fn run_expiry_sweeper(interval_in_millis: u64, state: Arc<State>) {
+        tokio::spawn(async move {
+            loop {
+                sleep(Duration::from_millis(interval_in_millis)).await;
+                state.remove_expired_from_store();
+            }
+        });
+    }
+
+    /// The role the node currently plays in the cluster.
+    pub async fn role(&self) -> Role {
+        self.state.get_role().await
+    }
+
+    /// The timing knobs the node is running with.
+    pub fn timing(&self) -> &TimingConfig {
+        &self.timing
+    }
+
+    /// Report traffic over the node's connection to each of its peers.
+    pub fn peer_connection_stats(&self) -> Vec<(NodeAddr, ConnectionStats)> {
+        self.rpc_client.connection_stats()
+    }
+
+    /// Connect to a peer at `address` that was not in our `NodeConfig`, then replicate to it (and
+    /// count it toward quorum) as we do every other peer.
+    pub async fn add_peer(&self, address: SocketAddr) -> Result<()> {
+        self.rpc_client.add_peer(address).await?;
+        self.state.add_peer(address.to_string()).await;
+        Ok(())
+    }
+
+    /// Stop replicating to (and counting toward quorum) the peer at `address`, then close our
+    /// connection to it.
+    pub async fn remove_peer(&self, address: &SocketAddr) -> Result<()> {
+        self.state.remove_peer(&address.to_string());
+        self.rpc_client.remove_peer(address).await
+    }
+
+    /// Number of async `Put`s acknowledged to clients that later failed to replicate.
+    pub fn num_dropped_async_puts(&self) -> u64 {
+        self.num_dropped_async_puts.load(Ordering::SeqCst)
+    }
+
     /// (LEADERS ONLY)
     /// Attempt to sync log entries with followers by issuing an `AppendEntryRequest`
     /// to each follower containing log entries ranging from the last index known to be committed by
@@ -200,39 +758,94 @@ impl Node {
     }
 
     /// (LEADERS ONLY)
-    /// Attempt to sync log entries with followers on a specified interval
-    pub fn run_heartbeat(rpc_client: Arc<RpcClient>, state: Arc<State>) {
+    /// Attempt to sync log entries with followers every `interval_in_millis` (for as long as we
+    /// are leader)
+    pub fn run_heartbeat(interval_in_millis: u64, rpc_client: Arc<RpcClient>, state: Arc<State>) {
         tokio::spawn(async move {
             loop {
-                sleep(Duration::from_millis(HEARTBEAT_INTERVAL_IN_MILLIS)).await;
-                let _ = Self::sync_logs(rpc_client.clone(), state.clone()).await;
+                sleep(Duration::from_millis(interval_in_millis)).await;
+                if state.get_role().await.is_leader() {
+                    let _ = Self::sync_logs(rpc_client.clone(), state.clone()).await;
+                }
             }
         });
     }
 
-    /// (ALL NODES)
-    /// Listen for `RespondableRpcRequest` tuples emitted from the `RpcServer` and handle them
-    /// appropriately according to the node's `role` to modify its current `state`.
-    /// For followers: handle `AppendEntries` requests from leaders, and issue reponses indicating
-    /// whether the call succeeded and the value of the follower's current term.
-    pub fn handle_rpc_requests(
-        mut request_rx: Receiver<RespondableRpcRequest>,
-        role: Arc<Role>,
+    /// (FOLLOWERS AND CANDIDATES ONLY)
+    /// Start an election whenever we go a randomly chosen election timeout (drawn afresh from the
+    /// inclusive `timeout_range_in_millis` each time, so that candidates rarely split the vote)
+    /// without hearing from a leader or granting a vote (§5.2).
+    pub fn run_election_timer(
+        timeout_range_in_millis: (u64, u64),
+        rpc_client: Arc<RpcClient>,
         state: Arc<State>,
     ) {
+        let (min_timeout, max_timeout) = timeout_range_in_millis;
+        tokio::spawn(async move {
+            loop {
+                let timeout =
+                    Duration::from_millis(rand::thread_rng().gen_range(min_timeout..=max_timeout));
+                let elapsed = state.time_since_leader_contact();
+                if elapsed < timeout {
+                    sleep(timeout - elapsed).await;
+                    continue;
+                }
+                if state.get_role().await.is_leader() {
+                    sleep(timeout).await;
+                    continue;
+                }
+                match state.begin_election().await {
+                    Ok(requests) if requests.is_empty() => {
+                        Self::assume_leadership(rpc_client.clone(), state.clone()).await
+                    }
+                    Ok(requests) => {
+                        let _ = rpc_client.send_many(requests).await;
+                    }
+                    Err(e) => eprintln!("> Failed to start election: {}", e),
+                }
+            }
+        });
+    }
+
+    /// (LEADERS ONLY)
+    /// Upon winning an election, append a `NoOp` entry for the new term and replicate it right
+    /// away, both to assert leadership and to commit any entries left over from earlier terms
+    /// (which leaders may only commit indirectly, by committing an entry from their own term).
+    async fn assume_leadership(rpc_client: Arc<RpcClient>, state: Arc<State>) {
+        if state.append_to_log(Command::NoOp).await.is_ok() {
+            Self::sync_logs(rpc_client, state).await;
+        }
+    }
+
+    /// (ALL NODES)
+    /// Listen for `RespondableRpcRequest` tuples emitted from the `RpcServer` and handle them
+    /// to modify the node's current `state`:
+    /// - handle `AppendEntries` requests from leaders, and issue reponses indicating whether the
+    ///   call succeeded and the value of our current term (any node may receive these, since a
+    ///   leader or candidate must step down on hearing from a leader with a newer term)
+    /// - handle `RequestVote` requests from candidates, and issue responses indicating whether we
+    ///   granted our vote and the value of our current term
+    /// - handle `DigestValues` requests from peers auditing replicas, and issue responses with
+    ///   digests of our values at the requested keys
+    pub fn handle_rpc_requests(mut request_rx: Receiver<RespondableRpcRequest>, state: Arc<State>) {
         tokio::spawn(async move {
             while let Some((request_envelope, responder)) = request_rx.recv().await {
                 let RpcRequestEnvelope { id, request } = request_envelope;
-                match request {
-                    RpcRequest::AppendEntries(req) => match role.as_ref() {
-                        Role::Follower => {
-                            let response = state.handle_append_entries_request(req).await;
-                            let _ =
-                                responder.send(RpcResponseEnvelope::of_append_entry(id, response));
-                        }
-                        Role::Leader => {}
-                    },
-                }
+                let response = match request {
+                    RpcRequest::AppendEntries(req) => {
+                        let response = state.handle_append_entries_request(req).await;
+                        RpcResponseEnvelope::of_append_entry(id, response)
+                    }
+                    RpcRequest::RequestVote(req) => {
+                        let response = state.handle_request_vote_request(req).await;
+                        RpcResponseEnvelope::of_request_vote(id, response)
+                    }
+                    RpcRequest::DigestValues(req) => {
+                        let digests = state.store.digest(&req.keys);
+                        RpcResponseEnvelope::of_digest_values(id, DigestValuesResponse { digests })
+                    }
+                };
+                let _ = responder.send(response);
             }
         });
     }
@@ -242,6 +855,7 @@ impl Node {
     /// modify the node's `state`.
     fn handle_rpc_responses(
         mut rpc_response_rx: Receiver<RpcResponseInContext>,
+        rpc_client: Arc<RpcClient>,
         state: Arc<State>,
     ) {
         tokio::spawn(async move {
@@ -258,6 +872,19 @@ impl Node {
                             .handle_append_entry_response(peer_addr, req, resp)
                             .await;
                     }
+                    (RpcRequest::RequestVote(req), RpcResponse::ToRequestVote(resp)) => {
+                        if let Ok(true) = state
+                            .handle_request_vote_response(peer_addr, req, resp)
+                            .await
+                        {
+                            Self::assume_leadership(rpc_client.clone(), state.clone()).await;
+                        }
+                    }
+                    (RpcRequest::DigestValues(req), RpcResponse::ToDigestValues(resp)) => {
+                        state.notify_digests(req.audit_id, peer_addr, resp.digests);
+                    }
+                    // the peer answered a different kind of call than we made
+                    _ => {}
                 }
             }
         });
@@ -268,15 +895,21 @@ impl Node {
 mod test_node {
     use test_context::{test_context, AsyncTestContext};
     use tokio::fs;
-    use tokio::net::TcpListener;
+    use tokio::time::Instant;
 
-    use crate::api::client::{ApiClient, ApiClientConfig};
-    use crate::error::ProtocolError::{LeaderRequired, ServerError};
+    use crate::api::client::{
+        ApiClient, ApiClientConfig, ValueFormat, DEFAULT_MAX_INFLIGHT_REQUESTS,
+    };
+    use crate::error::ProtocolError::{LeaderRequired, NodeStarting, ServerError};
     use crate::rpc::request::AppendEntriesRequest;
-    use crate::rpc::response::{AppendEntriesResponse, RpcResponse};
+    use crate::rpc::response::{
+        AppendEntriesResponse, DigestValuesResponse, RequestVoteResponse, RpcResponse,
+    };
     use crate::rpc::RpcServerConnection;
     use crate::state::log::LogEntry;
+    use crate::tcp::WireFormat;
     use crate::test_support::gen::Gen;
+    use crate::transport::MemoryTransport;
 
     use super::*;
 
@@ -286,7 +919,8 @@ mod test_node {
         static ref MAJORITY: usize = *NUM_PEERS / 2 + *NUM_PEERS % 2;
         static ref PUT_CMD: Command = Command::Put {
             key: "foo".to_string(),
-            value: "bar".to_string()
+            value: "bar".to_string(),
+            expires_at: None,
         };
         static ref APPEND_SUCCESS: RpcResponse =
             RpcResponse::ToAppendEntries(AppendEntriesResponse {
@@ -299,37 +933,52 @@ mod test_node {
                 success: false,
             });
         static ref APPEND_SUCCESS_FROM_ALL_PEERS: Vec<RpcResponse> =
-            std::iter::repeat(APPEND_SUCCESS.clone())
-                .take(*NUM_PEERS)
-                .collect::<Vec<RpcResponse>>();
+            std::iter::repeat_n(APPEND_SUCCESS.clone(), *NUM_PEERS).collect::<Vec<RpcResponse>>();
         static ref APPEND_FAILURE_FROM_ALL_PEERS: Vec<RpcResponse> =
-            std::iter::repeat(APPEND_FAILURE.clone())
-                .take(*NUM_PEERS)
+            std::iter::repeat_n(APPEND_FAILURE.clone(), *NUM_PEERS).collect::<Vec<RpcResponse>>();
+        static ref VOTE_GRANTED: RpcResponse = RpcResponse::ToRequestVote(RequestVoteResponse {
+            peer_term: 0,
+            vote_granted: true,
+        });
+        static ref DIGEST_OF_MISSING_VALUE: RpcResponse =
+            RpcResponse::ToDigestValues(DigestValuesResponse {
+                digests: vec![None],
+            });
+        static ref DIGEST_OF_MISSING_VALUE_FROM_ALL_PEERS: Vec<RpcResponse> =
+            std::iter::repeat_n(DIGEST_OF_MISSING_VALUE.clone(), *NUM_PEERS)
                 .collect::<Vec<RpcResponse>>();
+        static ref VOTE_GRANTED_FROM_ALL_PEERS: Vec<RpcResponse> =
+            std::iter::repeat_n(VOTE_GRANTED.clone(), *NUM_PEERS).collect::<Vec<RpcResponse>>();
     }
 
     struct Context {
         client: ApiClient,
+        node: Node,
+        node_address: NodeAddr,
+        api_address: SocketAddr,
         leader_address: NodeAddr,
         log_path: String,
         metadata_path: String,
+        transport: MemoryTransport, // connecting the node, its client, and its (fake) peers
     }
 
     impl Context {
         async fn setup(role: Role, responses: Vec<RpcResponse>, entries: Vec<LogEntry>) -> Context {
             let responses = Arc::new(responses);
-            let node_addresses = (0..*NUM_NODES)
-                .map(|_| Gen::socket_addr())
+            // nothing binds these to real sockets, so they need only be distinct from one another
+            let node_addresses = (1..=*NUM_NODES)
+                .map(|port| SocketAddr::from(([127, 0, 0, 1], port as u16)))
                 .collect::<Vec<SocketAddr>>();
-            let own_address = node_addresses[0].clone();
+            let own_address = node_addresses[0];
             let peer_addresses = node_addresses[1..node_addresses.len()].to_vec();
             let leader_address = match role {
                 Role::Leader => own_address.to_string(),
                 _ => peer_addresses[0].clone().to_string(),
             };
 
+            let transport = MemoryTransport::default();
             for (peer_idx, peer_addr) in peer_addresses.clone().into_iter().enumerate() {
-                let listener = TcpListener::bind(peer_addr).await.unwrap();
+                let mut listener = transport.bind(peer_addr).await.unwrap();
                 let responses = responses.clone();
 
                 tokio::spawn(async move {
@@ -357,54 +1006,88 @@ mod test_node {
                 });
             }
 
-            let log_path = format!("test_data/log_{}", Gen::usize().to_string());
-            let metadata_path = format!("test_data/metadata_{}", Gen::usize().to_string());
-            fs::create_dir(metadata_path.clone()).await.unwrap();
+            let log_path = format!("test_data/log_{}", Gen::usize());
+            let metadata_path = format!("test_data/metadata_{}", Gen::usize());
+            fs::create_dir_all(metadata_path.clone()).await.unwrap();
 
-            let api_address = Gen::socket_addr();
+            let api_address = SocketAddr::from(([127, 0, 0, 1], 0));
             let node_config = NodeConfig {
                 role,
-                api_address: api_address.clone(),
+                api_address,
                 rpc_address: own_address,
+                ordered_api_responses: false,
+                injected_api_faults: HashMap::new(),
+                api_command_filter: CommandFilter::AllowAll,
                 leader_address: leader_address.clone(),
                 peer_addresses,
+                rpc_codec: FrameCodec::default(),
+                #[cfg(feature = "tls")]
+                api_tls: None,
+                #[cfg(feature = "tls")]
+                rpc_tls: None,
+                #[cfg(feature = "tls")]
+                peer_tls: None,
+                reconnect: ReconnectConfig::unlimited(),
+                api_transport: Arc::new(transport.clone()),
+                rpc_transport: Arc::new(transport.clone()),
                 log_path: log_path.clone(),
                 metadata_path: metadata_path.clone(),
+                timing: TimingConfig::default(),
+                snapshot_interval_in_millis: None,
             };
             let client_config = ApiClientConfig {
                 server_address: api_address,
+                max_inflight_requests: DEFAULT_MAX_INFLIGHT_REQUESTS,
+                reconnect: ReconnectConfig::default(),
+                codec: FrameCodec::default(),
+                value_format: ValueFormat::default(),
+                #[cfg(feature = "tls")]
+                tls: None,
+                transport: Arc::new(transport.clone()),
             };
 
             let node = node_config.run().await.unwrap();
-            if !entries.is_empty() {
-                let leader_commit = entries.len();
-                let _ = node
-                    .state
-                    .handle_append_entries_request(AppendEntriesRequest {
-                        entries,
-                        leader_address: leader_address.clone(),
-                        leader_commit,
-                        leader_term: 0,
-                        prev_log_index: 0,
-                        prev_log_term: 0,
-                    })
-                    .await;
-            }
-
             let client = client_config.run().await.unwrap();
 
-            return Context {
+            let ctx = Context {
                 client,
+                node,
+                node_address: own_address.to_string(),
+                api_address,
                 leader_address,
                 log_path,
                 metadata_path,
+                transport,
             };
+            if !entries.is_empty() {
+                ctx.hear_from_leader(entries).await;
+            }
+            ctx
+        }
+
+        /// Have the leader replicate `entries` to the node (and tell it they are all committed)
+        async fn hear_from_leader(&self, entries: Vec<LogEntry>) {
+            let leader_commit = entries.len();
+            let _ = self
+                .node
+                .state
+                .handle_append_entries_request(AppendEntriesRequest {
+                    entries,
+                    leader_address: self.leader_address.clone(),
+                    leader_api_address: None,
+                    leader_commit,
+                    leader_compactable_index: 0,
+                    leader_term: 0,
+                    prev_log_index: 0,
+                    prev_log_term: 0,
+                })
+                .await;
         }
 
         pub async fn teardown(self) {
             // TODO: shut down node server/clients
-            let _ = tokio::fs::remove_file(self.log_path).await.unwrap();
-            let _ = tokio::fs::remove_dir_all(self.metadata_path).await.unwrap();
+            tokio::fs::remove_file(self.log_path).await.unwrap();
+            tokio::fs::remove_dir_all(self.metadata_path).await.unwrap();
         }
     }
 
@@ -440,6 +1123,26 @@ mod test_node {
         }
     }
 
+    struct LeaderWithEntriesMissingFromAllPeers(Context);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for LeaderWithEntriesMissingFromAllPeers {
+        async fn setup() -> Self {
+            let ctx = Context::setup(
+                Role::Leader,
+                DIGEST_OF_MISSING_VALUE_FROM_ALL_PEERS.clone(),
+                vec![LogEntry {
+                    term: 0,
+                    command: PUT_CMD.clone(),
+                }],
+            )
+            .await;
+            LeaderWithEntriesMissingFromAllPeers(ctx)
+        }
+        async fn teardown(self) {
+            self.0.teardown().await
+        }
+    }
+
     struct LeaderWithSuccessFromAllPeers(Context);
     #[async_trait::async_trait]
     impl AsyncTestContext for LeaderWithSuccessFromAllPeers {
@@ -471,6 +1174,7 @@ mod test_node {
     impl AsyncTestContext for Follower {
         async fn setup() -> Self {
             let ctx = Context::setup(Role::Follower, vec![], vec![]).await;
+            ctx.hear_from_leader(vec![]).await;
             Follower(ctx)
         }
         async fn teardown(self) {
@@ -478,6 +1182,18 @@ mod test_node {
         }
     }
 
+    struct FollowerAwaitingLeader(Context);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for FollowerAwaitingLeader {
+        async fn setup() -> Self {
+            let ctx = Context::setup(Role::Follower, vec![], vec![]).await;
+            FollowerAwaitingLeader(ctx)
+        }
+        async fn teardown(self) {
+            self.0.teardown().await
+        }
+    }
+
     struct FollowerWithEntries(Context);
     #[async_trait::async_trait]
     impl AsyncTestContext for FollowerWithEntries {
@@ -498,6 +1214,19 @@ mod test_node {
         }
     }
 
+    struct FollowerWithVotesFromAllPeers(Context);
+    #[async_trait::async_trait]
+    impl AsyncTestContext for FollowerWithVotesFromAllPeers {
+        async fn setup() -> Self {
+            let ctx =
+                Context::setup(Role::Follower, VOTE_GRANTED_FROM_ALL_PEERS.clone(), vec![]).await;
+            FollowerWithVotesFromAllPeers(ctx)
+        }
+        async fn teardown(self) {
+            self.0.teardown().await
+        }
+    }
+
     #[cfg(test)]
     mod leader {
         use super::*;
@@ -509,6 +1238,13 @@ mod test_node {
             assert_eq!(response, None);
         }
 
+        #[test_context(Leader)]
+        #[tokio::test]
+        async fn answers_readiness_probe(ctx: Leader) {
+            let result = ctx.0.client.ready(Duration::from_millis(100)).await;
+            assert!(result.is_ok());
+        }
+
         #[test_context(LeaderWithEntries)]
         #[tokio::test]
         async fn handles_get_of_previously_replicated_value(ctx: LeaderWithEntries) {
@@ -516,11 +1252,192 @@ mod test_node {
             assert_eq!(response, Some("bar".to_string()));
         }
 
+        #[test_context(LeaderWithEntries)]
+        #[tokio::test]
+        async fn handles_get_from_client_speaking_message_pack(ctx: LeaderWithEntries) {
+            let client = ApiClientConfig {
+                server_address: ctx.0.api_address,
+                max_inflight_requests: DEFAULT_MAX_INFLIGHT_REQUESTS,
+                reconnect: ReconnectConfig::default(),
+                codec: FrameCodec::LengthPrefixed(WireFormat::MessagePack),
+                value_format: ValueFormat::default(),
+                #[cfg(feature = "tls")]
+                tls: None,
+                transport: Arc::new(ctx.0.transport.clone()),
+            }
+            .run()
+            .await
+            .unwrap();
+
+            let response = client.get("foo").await.unwrap();
+            assert_eq!(response, Some("bar".to_string()));
+        }
+
         #[test_context(LeaderWithSuccessFromAllPeers)]
         #[tokio::test]
         async fn handles_successfully_replicated_put(ctx: LeaderWithSuccessFromAllPeers) {
             let response = ctx.0.client.put("foo", "bar").await.unwrap();
-            assert_eq!(response, true);
+            assert!(response);
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn handles_batch_as_if_each_request_followed_the_last(
+            ctx: LeaderWithSuccessFromAllPeers,
+        ) {
+            let (get, put, delete) = (
+                ApiRequest::Get {
+                    key: "foo".to_string(),
+                },
+                ApiRequest::Put {
+                    key: "foo".to_string(),
+                    value: "bar".to_string(),
+                    is_async: false,
+                    min_replicas: None,
+                    ttl_ms: None,
+                },
+                ApiRequest::Delete {
+                    key: "baz".to_string(),
+                },
+            );
+            let responses = ctx
+                .0
+                .client
+                .batch(vec![get.clone(), put.clone(), get, put, delete])
+                .await
+                .unwrap();
+            let after_batch = ctx.0.client.get("foo").await.unwrap();
+
+            assert_eq!(
+                responses,
+                vec![
+                    ApiResponse::ToGet { value: None },
+                    ApiResponse::ToPut { was_modified: true },
+                    ApiResponse::ToGet {
+                        value: Some("bar".to_string())
+                    },
+                    ApiResponse::ToPut {
+                        was_modified: false
+                    },
+                    ApiResponse::ToDelete { was_present: false },
+                ]
+            );
+            assert_eq!(after_batch, Some("bar".to_string()));
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn rejects_batch_holding_unbatchable_request(ctx: LeaderWithSuccessFromAllPeers) {
+            let put = ApiRequest::Put {
+                key: "foo".to_string(),
+                value: "bar".to_string(),
+                is_async: false,
+                min_replicas: None,
+                ttl_ms: None,
+            };
+            let response = ctx.0.client.batch(vec![put, ApiRequest::Ping]).await;
+            let after_batch = ctx.0.client.get("foo").await.unwrap();
+
+            assert_eq!(
+                response.err().unwrap().to_string(),
+                ServerError(UnbatchableRequest("Ping".to_string()).to_string()).to_string(),
+            );
+            assert_eq!(after_batch, None);
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn rejects_batch_holding_put_with_its_own_replication_options(
+            ctx: LeaderWithSuccessFromAllPeers,
+        ) {
+            let put = |is_async, min_replicas| ApiRequest::Put {
+                key: "foo".to_string(),
+                value: "bar".to_string(),
+                is_async,
+                min_replicas,
+                ttl_ms: None,
+            };
+            let async_response = ctx.0.client.batch(vec![put(true, None)]).await;
+            let min_replicas_response = ctx.0.client.batch(vec![put(false, Some(1))]).await;
+            let after_batches = ctx.0.client.get("foo").await.unwrap();
+
+            for response in [async_response, min_replicas_response] {
+                assert_eq!(
+                    response.err().unwrap().to_string(),
+                    ServerError(UnbatchablePutOptions.to_string()).to_string(),
+                );
+            }
+            assert_eq!(after_batches, None);
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn expires_value_put_with_ttl(ctx: LeaderWithSuccessFromAllPeers) {
+            let put_response = ctx.0.client.put_with_ttl("foo", "bar", 100).await.unwrap();
+            let before_expiry = ctx.0.client.get("foo").await.unwrap();
+            sleep(Duration::from_millis(150)).await;
+            let after_expiry = ctx.0.client.get("foo").await.unwrap();
+
+            assert!(put_response);
+            assert_eq!(before_expiry, Some("bar".to_string()));
+            assert_eq!(after_expiry, None);
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn keeps_value_put_with_ttl_too_long_to_represent(
+            ctx: LeaderWithSuccessFromAllPeers,
+        ) {
+            let put_response = ctx
+                .0
+                .client
+                .put_with_ttl("foo", "bar", u64::MAX)
+                .await
+                .unwrap();
+            let get_response = ctx.0.client.get("foo").await.unwrap();
+
+            assert!(put_response);
+            assert_eq!(get_response, Some("bar".to_string()));
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn handles_put_stored_on_min_replicas(ctx: LeaderWithSuccessFromAllPeers) {
+            let response = ctx
+                .0
+                .client
+                .put_with_min_replicas("foo", "bar", *NUM_NODES)
+                .await;
+            assert!(response.unwrap());
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn rejects_put_requiring_more_replicas_than_nodes(
+            ctx: LeaderWithSuccessFromAllPeers,
+        ) {
+            let response = ctx
+                .0
+                .client
+                .put_with_min_replicas("foo", "bar", *NUM_NODES + 1)
+                .await;
+            assert_eq!(
+                response.err().unwrap().to_string(),
+                ServerError(TooManyReplicasRequested(*NUM_NODES + 1, *NUM_NODES).to_string())
+                    .to_string(),
+            );
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn rejects_async_put_requiring_min_replicas(ctx: LeaderWithSuccessFromAllPeers) {
+            ctx.0.client.set_async(true);
+            let response = ctx.0.client.put_with_min_replicas("foo", "bar", 1).await;
+            assert_eq!(
+                response.err().unwrap().to_string(),
+                ServerError(AsyncPutWithMinReplicas.to_string()).to_string(),
+            );
+            assert_eq!(ctx.0.client.get("foo").await.unwrap(), None);
         }
 
         #[test_context(LeaderWithFailureFromAllPeers)]
@@ -546,6 +1463,17 @@ mod test_node {
             );
         }
 
+        #[test_context(Leader)]
+        #[tokio::test]
+        async fn acknowledges_async_put_before_replication(ctx: Leader) {
+            ctx.0.client.set_async(true);
+            let put_response = ctx.0.client.put("foo", "bar").await;
+            sleep(Duration::from_millis(API_PUT_TIMEOUT_IN_MILLIS * 2)).await;
+
+            assert!(put_response.unwrap());
+            assert_eq!(ctx.0.node.num_dropped_async_puts(), 1);
+        }
+
         #[test_context(LeaderWithSuccessFromAllPeers)]
         #[tokio::test]
         async fn handles_get_of_put_value(ctx: LeaderWithSuccessFromAllPeers) {
@@ -560,8 +1488,82 @@ mod test_node {
             let put_response_1 = ctx.0.client.put("foo", "bar").await.unwrap();
             let put_response_2 = ctx.0.client.put("foo", "bar").await.unwrap();
 
-            assert_eq!(put_response_1, true);
-            assert_eq!(put_response_2, false);
+            assert!(put_response_1);
+            assert!(!put_response_2);
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn handles_delete_of_put_value(ctx: LeaderWithSuccessFromAllPeers) {
+            let _ = ctx.0.client.put("foo", "bar").await;
+            let delete_response_1 = ctx.0.client.delete("foo").await.unwrap();
+            let delete_response_2 = ctx.0.client.delete("foo").await.unwrap();
+            let get_response = ctx.0.client.get("foo").await.unwrap();
+
+            assert!(delete_response_1);
+            assert!(!delete_response_2);
+            assert_eq!(get_response, None);
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn compacts_log_into_snapshot(ctx: LeaderWithSuccessFromAllPeers) {
+            let _ = ctx.0.client.put("foo", "bar").await.unwrap();
+            let _ = ctx.0.client.put("foo", "baz").await.unwrap();
+            let last_index = ctx.0.node.state.get_last_appended_index().await;
+
+            let snapshot_index = ctx.0.node.snapshot().await.unwrap();
+            let log = ctx.0.node.state.log.lock().await;
+
+            assert_eq!(snapshot_index, last_index);
+            assert_eq!(log.first_index, last_index);
+            assert_eq!(log.get_last_index(), last_index);
+            assert_eq!(
+                ctx.0.client.get("foo").await.unwrap(),
+                Some("baz".to_string())
+            );
+        }
+
+        #[test_context(LeaderWithSuccessFromAllPeers)]
+        #[tokio::test]
+        async fn leases_consecutive_id_ranges(ctx: LeaderWithSuccessFromAllPeers) {
+            let first_lease = ctx.0.client.lease_ids("ids", 10).await.unwrap();
+            let second_lease = ctx.0.client.lease_ids("ids", 10).await.unwrap();
+            assert_eq!((first_lease, second_lease), (0..10, 10..20));
+        }
+
+        #[test_context(LeaderWithEntriesMissingFromAllPeers)]
+        #[tokio::test]
+        async fn reports_keys_that_diverge_across_replicas(
+            ctx: LeaderWithEntriesMissingFromAllPeers,
+        ) {
+            let audit = ctx.0.client.verify_replicas("f", 10).await.unwrap();
+            assert_eq!(audit.keys_checked, vec!["foo".to_string()]);
+            assert_eq!(audit.divergences.len(), *NUM_PEERS);
+            assert!(audit.divergences.iter().all(|d| d.key == "foo"));
+            assert!(audit.unresponsive_replicas.is_empty());
+        }
+
+        #[test_context(LeaderWithEntries)]
+        #[tokio::test]
+        async fn reports_replicas_that_do_not_answer_audit(ctx: LeaderWithEntries) {
+            let audit = ctx.0.client.verify_replicas("f", 10).await.unwrap();
+            assert_eq!(audit.keys_checked, vec!["foo".to_string()]);
+            assert!(audit.divergences.is_empty());
+            assert_eq!(audit.unresponsive_replicas.len(), *NUM_PEERS);
+        }
+
+        #[test_context(LeaderWithEntries)]
+        #[tokio::test]
+        async fn serves_other_requests_while_awaiting_audit(ctx: LeaderWithEntries) {
+            let started_at = Instant::now();
+            let (audit, get_elapsed) = tokio::join!(ctx.0.client.verify_replicas("f", 10), async {
+                ctx.0.client.get("foo").await.unwrap();
+                started_at.elapsed()
+            });
+
+            assert_eq!(audit.unwrap().unresponsive_replicas.len(), *NUM_PEERS);
+            assert!(get_elapsed < Duration::from_millis(VERIFY_REPLICAS_TIMEOUT_IN_MILLIS));
         }
 
         #[test_context(LeaderWithSuccessFromAllPeers)]
@@ -572,8 +1574,8 @@ mod test_node {
 
             let get_response = ctx.0.client.get("foo").await.unwrap();
 
-            assert_eq!(put_response_1, true);
-            assert_eq!(put_response_2, true);
+            assert!(put_response_1);
+            assert!(put_response_2);
             assert_eq!(get_response, Some("baz".to_string()));
         }
     }
@@ -600,5 +1602,102 @@ mod test_node {
                 LeaderRequired(ctx.0.leader_address.clone()).to_string(),
             );
         }
+
+        #[test_context(Follower)]
+        #[tokio::test]
+        async fn redirects_delete(ctx: Follower) {
+            let delete_response = ctx.0.client.delete("foo").await;
+            assert_eq!(
+                delete_response.err().unwrap().to_string(),
+                LeaderRequired(ctx.0.leader_address.clone()).to_string(),
+            );
+        }
+
+        #[test_context(FollowerAwaitingLeader)]
+        #[tokio::test]
+        async fn reports_startup_phase_until_caught_up_with_leader(ctx: FollowerAwaitingLeader) {
+            let before_catching_up = ctx.0.client.get("foo").await;
+            ctx.0.hear_from_leader(vec![]).await;
+            let after_catching_up = ctx.0.client.get("foo").await;
+
+            assert_eq!(
+                before_catching_up.err().unwrap().downcast_ref(),
+                Some(&NodeStarting(StartupPhase::CatchingUp))
+            );
+            assert_eq!(after_catching_up.unwrap(), None);
+            assert!(ctx.0.client.ready(Duration::from_millis(100)).await.is_ok());
+        }
+
+        #[test_context(FollowerAwaitingLeader)]
+        #[tokio::test]
+        async fn stands_for_election_after_hearing_nothing_from_leader(
+            ctx: FollowerAwaitingLeader,
+        ) {
+            let (_, max_timeout) = ctx.0.node.timing().election_timeout_range_in_millis;
+            sleep(Duration::from_millis(2 * max_timeout)).await;
+            assert_eq!(ctx.0.node.role().await, Role::Candidate);
+        }
+
+        #[test_context(FollowerWithVotesFromAllPeers)]
+        #[tokio::test]
+        async fn becomes_leader_on_winning_election(ctx: FollowerWithVotesFromAllPeers) {
+            let (_, max_timeout) = ctx.0.node.timing().election_timeout_range_in_millis;
+            sleep(Duration::from_millis(2 * max_timeout)).await;
+            assert_eq!(ctx.0.node.role().await, Role::Leader);
+            assert_eq!(
+                ctx.0.node.state.get_leader_address().await,
+                ctx.0.node_address
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod timing {
+        use super::*;
+
+        #[test]
+        fn accepts_default_timing() {
+            assert!(TimingConfig::default().validate().is_ok());
+        }
+
+        #[test]
+        fn rejects_zero_heartbeat_interval() {
+            let timing = TimingConfig {
+                heartbeat_interval_in_millis: 0,
+                ..TimingConfig::default()
+            };
+            assert_eq!(
+                timing.validate().err().unwrap().downcast_ref(),
+                Some(&ZeroHeartbeatInterval)
+            );
+        }
+
+        #[test]
+        fn rejects_empty_election_timeout_range() {
+            let timing = TimingConfig {
+                heartbeat_interval_in_millis: 10,
+                election_timeout_range_in_millis: (200, 100),
+            };
+            assert_eq!(
+                timing.validate().err().unwrap().downcast_ref(),
+                Some(&EmptyElectionTimeoutRange(200, 100))
+            );
+        }
+
+        #[test]
+        fn rejects_election_timeout_close_to_heartbeat_interval() {
+            let timing = TimingConfig {
+                heartbeat_interval_in_millis: 100,
+                election_timeout_range_in_millis: (200, 400),
+            };
+            assert_eq!(
+                timing.validate().err().unwrap().downcast_ref(),
+                Some(&ElectionTimeoutTooShort(
+                    200,
+                    MIN_HEARTBEATS_PER_ELECTION_TIMEOUT,
+                    100
+                ))
+            );
+        }
     }
 }
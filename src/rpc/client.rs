@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
@@ -6,34 +7,56 @@ use dashmap::DashMap;
 use futures::future;
 use futures::stream;
 use futures::StreamExt;
-use tokio::net::TcpStream;
 
-use crate::error::NetworkError::{BroadcastFailure, ConnectionClosed, NoPeerAtAddress};
+#[cfg(feature = "tls")]
+use crate::error::ConfigError::TlsOverEncryptedTransport;
+use crate::error::NetworkError;
+use crate::error::NetworkError::{BroadcastFailure, NoPeerAtAddress};
 use crate::error::Result;
 use crate::rpc::request::{RpcRequest, RpcRequestEnvelope};
 use crate::rpc::response::{RpcResponse, RpcResponseEnvelope};
 use crate::rpc::RpcClientConnection;
+use crate::tcp::{ConnectionEvent, ConnectionStats, FrameCodec, ReconnectConfig};
+#[cfg(feature = "tls")]
+use crate::tls::TlsClientConfig;
+use crate::tls::{self, TlsConnector};
+use crate::transport::Transport;
 
-use crate::NodeAddr;
+use crate::{NodeAddr, CHAN_BUF_SIZE};
 
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::Sender;
 
 pub type RpcResponseInContext = (NodeAddr, RpcRequest, RpcResponse);
 
 pub struct Peer {
-    address: SocketAddr, // TODO: should this be a String?
+    // identifies `connection` (each connection we register for a peer gets a higher generation
+    // than any before it, so that tasks holding a superseded one can tell it is stale)
+    generation: u64,
     connection: Arc<RpcClientConnection>,
 }
 
 #[derive(Clone)]
 pub struct RpcClientConfig {
     pub peer_addresses: Vec<SocketAddr>,
+    pub reconnect: ReconnectConfig,
+    pub codec: FrameCodec,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsClientConfig>, // if set, connect to peers over TLS
+    pub transport: Arc<dyn Transport>,
 }
 
 pub struct RpcClient {
     peers_by_address: Arc<DashMap<NodeAddr, Peer>>,
+    next_generation: Arc<AtomicU64>,
     request_id: AtomicU64,
     requests_by_id: Arc<DashMap<u64, RpcRequest>>,
+    response_tx: Sender<RpcResponseInContext>,
+    reconnect: ReconnectConfig,
+    codec: FrameCodec,
+    tls: Option<TlsConnector>,
+    transport: Arc<dyn Transport>,
+    connection_events_tx: broadcast::Sender<ConnectionEvent>,
 }
 
 impl RpcClientConfig {
@@ -42,70 +65,50 @@ impl RpcClientConfig {
     /// listen for responses on it, forwarding any responses to one-shot-receiver handlers registered
     /// in `Client::write`  and removing the handlers from the handler registry once they are used.
     pub async fn run_with(self, response_tx: Sender<RpcResponseInContext>) -> Result<RpcClient> {
-        let peers_by_address = Arc::new(DashMap::new());
-        let request_id = AtomicU64::new(0);
-        let requests_by_id = Arc::new(DashMap::new());
-
-        // connect to each peer in parallel, returning an Err if any connection fails
-        let peers: Vec<Peer> = future::try_join_all(
-            self.peer_addresses
-                .iter()
-                .map(|&address| async move {
-                    let stream = TcpStream::connect(address).await?;
-                    let connection = Arc::new(RpcClientConnection::new(stream));
-                    Ok(Peer {
-                        address,
-                        connection,
-                    })
-                })
+        // connect to each distinct peer in parallel (so that listing a peer twice never leaves us
+        // holding a redundant socket to it), returning an Err if any connection fails
+        self.reconnect.validate()?;
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() && self.transport.encrypts() {
+            return Err(TlsOverEncryptedTransport.boxed());
+        }
+        let codec = self.codec;
+        #[cfg(feature = "tls")]
+        let tls = self.tls.as_ref().map(TlsClientConfig::load).transpose()?;
+        #[cfg(not(feature = "tls"))]
+        let tls: Option<TlsConnector> = None;
+        let transport = self.transport.clone();
+        let peer_addresses: HashSet<SocketAddr> = self.peer_addresses.into_iter().collect();
+        let connections: Vec<(SocketAddr, Arc<RpcClientConnection>)> = future::try_join_all(
+            peer_addresses
+                .into_iter()
+                .map(|address| RpcClient::connect(address, codec, tls.clone(), transport.clone()))
                 .map(tokio::spawn),
         )
         .await?
         .into_iter()
-        .collect::<Result<Vec<Peer>>>()?;
+        .collect::<Result<Vec<_>>>()?;
+
+        let (connection_events_tx, _) = broadcast::channel(CHAN_BUF_SIZE);
+        let client = RpcClient {
+            peers_by_address: Arc::new(DashMap::new()),
+            next_generation: Arc::new(AtomicU64::new(0)),
+            request_id: AtomicU64::new(0),
+            requests_by_id: Arc::new(DashMap::new()),
+            response_tx,
+            reconnect: self.reconnect,
+            codec: self.codec,
+            tls,
+            transport: self.transport,
+            connection_events_tx,
+        };
 
         // store connections to peers keyed by their address and handle responses on each connection
-        let num_peers = peers.len();
-        let _ = stream::iter(peers)
-            .for_each_concurrent(num_peers, |peer| async {
-                // store reference to peer in hashmap (cloning values needed for response-handling before moving it)
-                let connection = peer.connection.clone();
-                let peer_address = peer.address.to_string();
-                peers_by_address.insert(peer_address.clone(), peer);
-                // listen for responses from each peer in a separate task
-                let requests_by_id = requests_by_id.clone();
-                let response_tx = response_tx.clone();
-                tokio::spawn(async move {
-                    loop {
-                        match connection.read().await {
-                            // on read, emit `ResponseInContext` tuple to `Node::handle_rpc_responses`
-                            Ok(response_env) => {
-                                let RpcResponseEnvelope { id, response } = response_env;
-                                if let Some((_, request)) = requests_by_id.remove(&id) {
-                                    let _ = response_tx
-                                        .send((peer_address.clone(), request, response))
-                                        .await;
-                                }
-                            }
-                            // stop listening if client has closed connection
-                            Err(e) => {
-                                if e.downcast_ref() == Some(&ConnectionClosed) {
-                                    return;
-                                } else {
-                                    eprintln!("{}", e);
-                                }
-                            }
-                        }
-                    }
-                });
-            })
-            .await;
+        for (address, connection) in connections {
+            client.register(address, connection).await;
+        }
 
-        Ok(RpcClient {
-            peers_by_address,
-            request_id,
-            requests_by_id,
-        })
+        Ok(client)
     }
 }
 
@@ -117,6 +120,63 @@ impl RpcClient {
         self.request_id.fetch_add(1, Ordering::SeqCst)
     }
 
+    /// Connect to a peer at `address` that was not included in the `RpcClientConfig`, and begin
+    /// handling its responses as we do for all other peers. If we are already connected to the
+    /// peer, this is a no-op. (If a concurrent call connects to the same peer first, the later
+    /// connection supersedes it, so we still hold only one.)
+    pub async fn add_peer(&self, address: SocketAddr) -> Result<()> {
+        if self.peers_by_address.contains_key(&address.to_string()) {
+            return Ok(());
+        }
+        let (_, connection) = RpcClient::connect(
+            address,
+            self.codec,
+            self.tls.clone(),
+            self.transport.clone(),
+        )
+        .await?;
+        self.register(address, connection).await;
+        Ok(())
+    }
+
+    /// Make `connection` the one we use to reach the peer at `address` (under a new generation) and
+    /// handle responses on it, closing whichever connection it supersedes.
+    async fn register(&self, address: SocketAddr, connection: Arc<RpcClientConnection>) {
+        let generation = self.next_generation.fetch_add(1, Ordering::SeqCst);
+        let peer = Peer {
+            generation,
+            connection: connection.clone(),
+        };
+        let superseded = self.peers_by_address.insert(address.to_string(), peer);
+        self.handle_responses(address, connection, generation);
+        if let Some(stale) = superseded {
+            let _ = stale.connection.close().await;
+        }
+    }
+
+    /// Subscribe to changes in the state of our connections to peers (as they drop and we try to
+    /// re-establish them).
+    pub fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.connection_events_tx.subscribe()
+    }
+
+    /// Report traffic over the connection to each peer.
+    pub fn connection_stats(&self) -> Vec<(NodeAddr, ConnectionStats)> {
+        self.peers_by_address
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.connection.stats()))
+            .collect()
+    }
+
+    /// Stop routing requests to the peer at `address` and close our end of the connection to it
+    /// (the task listening for its responses stops once the peer hangs up in turn).
+    pub async fn remove_peer(&self, address: &SocketAddr) -> Result<()> {
+        match self.peers_by_address.remove(&address.to_string()) {
+            Some((_, peer)) => peer.connection.close().await,
+            None => Err(NoPeerAtAddress(address.to_string()).boxed()),
+        }
+    }
+
     /// TODO: docs!
     pub async fn send_many(&self, requests_by_peer: Vec<(NodeAddr, RpcRequest)>) -> Result<()> {
         let num_peers = requests_by_peer.len();
@@ -149,6 +209,114 @@ impl RpcClient {
         }
     }
 
+    /// Open a connection to the peer at `address` over `transport` (and TLS if given a
+    /// `connector`), framing messages per `codec`.
+    async fn connect(
+        address: SocketAddr,
+        codec: FrameCodec,
+        connector: Option<TlsConnector>,
+        transport: Arc<dyn Transport>,
+    ) -> Result<(SocketAddr, Arc<RpcClientConnection>)> {
+        let socket = transport.connect(address).await?;
+        let stream = tls::connect(socket, address, connector.as_ref()).await?;
+        Ok((
+            address,
+            Arc::new(RpcClientConnection::with_codec(stream, codec)),
+        ))
+    }
+
+    /// Listen for responses from the peer at `address` on the connection of a given `generation` in
+    /// a separate task, emitting each of them (along with the request that prompted them) over
+    /// `response_tx`. If the peer drops the connection, try to re-establish it (per our
+    /// `ReconnectConfig`) and keep listening on the new one, unless the peer has since been removed
+    /// or the connection superseded.
+    fn handle_responses(
+        &self,
+        address: SocketAddr,
+        mut connection: Arc<RpcClientConnection>,
+        mut generation: u64,
+    ) {
+        let peer_address = address.to_string();
+        let peers_by_address = self.peers_by_address.clone();
+        let next_generation = self.next_generation.clone();
+        let requests_by_id = self.requests_by_id.clone();
+        let response_tx = self.response_tx.clone();
+        let reconnect = self.reconnect.clone();
+        let codec = self.codec;
+        let tls = self.tls.clone();
+        let transport = self.transport.clone();
+        let connection_events_tx = self.connection_events_tx.clone();
+
+        tokio::spawn(async move {
+            loop {
+                match connection.read().await {
+                    // on read, emit `ResponseInContext` tuple to `Node::handle_rpc_responses`
+                    Ok(response_env) => {
+                        let RpcResponseEnvelope { id, response } = response_env;
+                        if let Some((_, request)) = requests_by_id.remove(&id) {
+                            let _ = response_tx
+                                .send((peer_address.clone(), request, response))
+                                .await;
+                        }
+                    }
+                    // replace the connection if it is no longer usable
+                    Err(e) if NetworkError::is_disconnect(&e) => {
+                        if !Self::is_current(&peers_by_address, &peer_address, generation) {
+                            return; // peer was removed (or its connection superseded)
+                        }
+                        let _ = connection_events_tx
+                            .send(ConnectionEvent::Disconnected(peer_address.clone()));
+
+                        let stream = match reconnect
+                            .reconnect(address, &*transport, tls.as_ref())
+                            .await
+                        {
+                            Some(stream) => stream,
+                            None => {
+                                let _ = connection_events_tx
+                                    .send(ConnectionEvent::Abandoned(peer_address.clone()));
+                                return;
+                            }
+                        };
+                        let new_connection =
+                            Arc::new(RpcClientConnection::with_codec(stream, codec));
+                        let new_generation = match peers_by_address.get_mut(&peer_address) {
+                            Some(mut peer) if peer.generation == generation => {
+                                peer.generation = next_generation.fetch_add(1, Ordering::SeqCst);
+                                peer.connection = new_connection.clone();
+                                Some(peer.generation)
+                            }
+                            _ => None,
+                        };
+                        match new_generation {
+                            Some(new_generation) => generation = new_generation,
+                            None => {
+                                let _ = new_connection.close().await;
+                                return;
+                            }
+                        }
+                        connection = new_connection;
+                        let _ = connection_events_tx
+                            .send(ConnectionEvent::Reconnected(peer_address.clone()));
+                    }
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        });
+    }
+
+    /// Whether the connection of a given `generation` is still the one we use to reach the peer at
+    /// `peer_address`.
+    fn is_current(
+        peers_by_address: &DashMap<NodeAddr, Peer>,
+        peer_address: &str,
+        generation: u64,
+    ) -> bool {
+        peers_by_address
+            .get(peer_address)
+            .is_some_and(|peer| peer.generation == generation)
+    }
+
     /// TODO: docs!
     async fn write(
         request_env: RpcRequestEnvelope,
@@ -157,7 +325,7 @@ impl RpcClient {
         peers_by_address: Arc<DashMap<NodeAddr, Peer>>,
     ) -> Result<()> {
         if let Some(peer) = peers_by_address.get(&peer_address) {
-            let _ = requests_by_id.insert(request_env.id.clone(), request_env.request.clone());
+            let _ = requests_by_id.insert(request_env.id, request_env.request.clone());
             // TODO: timeout requests here (as in legacy rpc client)...
             peer.connection.write(request_env).await
         } else {
@@ -172,13 +340,13 @@ impl RpcClient {
 
 #[cfg(test)]
 mod test_rpc_client {
-    use std::collections::HashSet;
     use std::iter::FromIterator;
     use test_context::{test_context, AsyncTestContext};
 
-    use tokio::net::TcpListener;
+    use tokio::net::{TcpListener, TcpStream};
     use tokio::sync::mpsc;
     use tokio::sync::mpsc::Receiver;
+    use tokio::time::Duration;
 
     use crate::test_support::gen::Gen;
 
@@ -187,6 +355,7 @@ mod test_rpc_client {
     use crate::rpc::request::AppendEntriesRequest;
     use crate::rpc::response::AppendEntriesResponse;
     use crate::rpc::RpcServerConnection;
+    use crate::transport::TcpTransport;
     use crate::CHAN_BUF_SIZE;
 
     lazy_static! {
@@ -195,7 +364,9 @@ mod test_rpc_client {
         static ref APPEND_REQ: RpcRequest = RpcRequest::AppendEntries(AppendEntriesRequest {
             entries: Gen::log_entries(3),
             leader_address: Gen::socket_addr().to_string(),
+            leader_api_address: None,
             leader_commit: 0,
+            leader_compactable_index: 0,
             leader_term: 0,
             prev_log_index: 0,
             prev_log_term: 0,
@@ -206,9 +377,7 @@ mod test_rpc_client {
                 success: true,
             });
         static ref APPEND_SUCCESSES_FROM_ALL_PEERS: Vec<RpcResponse> =
-            std::iter::repeat(APPEND_SUCCESS.clone())
-                .take(*NUM_PEERS)
-                .collect();
+            std::iter::repeat_n(APPEND_SUCCESS.clone(), *NUM_PEERS).collect();
     }
 
     struct Context {
@@ -269,11 +438,16 @@ mod test_rpc_client {
 
             let client_config = RpcClientConfig {
                 peer_addresses: peer_addresses.clone(),
+                reconnect: ReconnectConfig::default(),
+                codec: FrameCodec::default(),
+                #[cfg(feature = "tls")]
+                tls: None,
+                transport: Arc::new(TcpTransport),
             };
             let (response_tx, response_rx) = mpsc::channel::<RpcResponseInContext>(CHAN_BUF_SIZE);
             let client = client_config.run_with(response_tx).await.unwrap();
 
-            return Self {
+            Self {
                 client,
                 expected_responses,
                 peer_addresses: peer_addresses.clone(),
@@ -288,7 +462,7 @@ mod test_rpc_client {
                     .collect(),
                 request_rx,
                 response_rx,
-            };
+            }
         }
     }
 
@@ -334,18 +508,253 @@ mod test_rpc_client {
         );
     }
 
+    #[tokio::test]
+    async fn connects_only_once_to_duplicated_peer() {
+        let peer_address = Gen::socket_addr();
+        let listener = TcpListener::bind(peer_address).await.unwrap();
+        let (response_tx, _response_rx) = mpsc::channel::<RpcResponseInContext>(CHAN_BUF_SIZE);
+        let client = RpcClientConfig {
+            peer_addresses: vec![peer_address, peer_address],
+            reconnect: ReconnectConfig::default(),
+            codec: FrameCodec::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(TcpTransport),
+        }
+        .run_with(response_tx)
+        .await
+        .unwrap();
+        assert_eq!(client.peers_by_address.len(), 1);
+
+        // the listener accepts queued connections in the order they were made, so every socket
+        // accepted before this sentinel was opened by the client
+        let sentinel = TcpStream::connect(peer_address).await.unwrap();
+        let mut num_client_sockets = 0;
+        loop {
+            let (_, address) = listener.accept().await.unwrap();
+            if address == sentinel.local_addr().unwrap() {
+                break;
+            }
+            num_client_sockets += 1;
+        }
+        assert_eq!(num_client_sockets, 1);
+    }
+
     #[test_context(RunningClient)]
     #[tokio::test]
-    async fn sends_requests_to_peers(mut ctx: RunningClient) {
-        let _ = ctx
+    async fn adds_peer(ctx: RunningClient) {
+        let new_peer_address = Gen::socket_addr();
+        let listener = TcpListener::bind(new_peer_address).await.unwrap();
+
+        ctx.0.client.add_peer(new_peer_address).await.unwrap();
+        let accept = listener.accept().await;
+
+        assert!(accept.is_ok());
+        assert_eq!(ctx.0.client.peers_by_address.len(), *NUM_PEERS + 1);
+        assert!(ctx
             .0
+            .client
+            .peers_by_address
+            .contains_key(&new_peer_address.to_string()));
+    }
+
+    #[tokio::test]
+    async fn closes_connection_superseded_by_newer_one_to_same_peer() {
+        let peer_address = Gen::socket_addr();
+        let listener = TcpListener::bind(peer_address).await.unwrap();
+        let (response_tx, _response_rx) = mpsc::channel::<RpcResponseInContext>(CHAN_BUF_SIZE);
+        let client = RpcClientConfig {
+            peer_addresses: vec![peer_address],
+            reconnect: ReconnectConfig::default(),
+            codec: FrameCodec::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(TcpTransport),
+        }
+        .run_with(response_tx)
+        .await
+        .unwrap();
+        let (first_socket, _) = listener.accept().await.unwrap();
+        let first_peer_conn = RpcServerConnection::new(first_socket);
+        let first_generation = client
+            .peers_by_address
+            .get(&peer_address.to_string())
+            .unwrap()
+            .generation;
+
+        let (_, newer_connection) = RpcClient::connect(
+            peer_address,
+            FrameCodec::default(),
+            None,
+            Arc::new(TcpTransport),
+        )
+        .await
+        .unwrap();
+        client
+            .register(peer_address, newer_connection.clone())
+            .await;
+        let first_peer_read = first_peer_conn.read().await;
+
+        let peer = client
+            .peers_by_address
+            .get(&peer_address.to_string())
+            .unwrap();
+        assert_eq!(client.peers_by_address.len(), 1);
+        assert!(peer.generation > first_generation);
+        assert!(Arc::ptr_eq(&peer.connection, &newer_connection));
+        assert_eq!(
+            first_peer_read.err().unwrap().downcast_ref(),
+            Some(&NetworkError::ConnectionClosed)
+        );
+    }
+
+    #[test_context(RunningClient)]
+    #[tokio::test]
+    async fn reports_connection_stats_for_each_peer(ctx: RunningClient) {
+        let stats = ctx.0.client.connection_stats();
+        assert_eq!(
+            stats
+                .iter()
+                .map(|(address, _)| address.clone())
+                .collect::<HashSet<String>>(),
+            HashSet::from_iter(ctx.0.recipient_addresses.clone()),
+        );
+        assert!(stats.iter().all(|(_, stats)| stats.frames_written == 0));
+    }
+
+    #[test_context(RunningClient)]
+    #[tokio::test]
+    async fn ignores_adding_existing_peer(ctx: RunningClient) {
+        let existing_peer_address = ctx.0.peer_addresses[0];
+        ctx.0.client.add_peer(existing_peer_address).await.unwrap();
+        assert_eq!(ctx.0.client.peers_by_address.len(), *NUM_PEERS);
+    }
+
+    #[tokio::test]
+    async fn removes_peer() {
+        let peer_address = Gen::socket_addr();
+        let listener = TcpListener::bind(peer_address).await.unwrap();
+        let (response_tx, _response_rx) = mpsc::channel::<RpcResponseInContext>(CHAN_BUF_SIZE);
+        let client = RpcClientConfig {
+            peer_addresses: vec![peer_address],
+            reconnect: ReconnectConfig::default(),
+            codec: FrameCodec::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(TcpTransport),
+        }
+        .run_with(response_tx)
+        .await
+        .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let peer_conn = RpcServerConnection::new(socket);
+
+        client.remove_peer(&peer_address).await.unwrap();
+        let _ = client
+            .send_many(vec![(peer_address.to_string(), APPEND_REQ.clone())])
+            .await;
+        let peer_read = peer_conn.read().await;
+        let reconnect = tokio::time::timeout(Duration::from_millis(50), listener.accept()).await;
+
+        // the peer sees its connection close without ever receiving the request (or a new one)
+        assert!(client.peers_by_address.is_empty());
+        assert_eq!(
+            peer_read.err().unwrap().downcast_ref(),
+            Some(&NetworkError::ConnectionClosed)
+        );
+        assert!(reconnect.is_err());
+    }
+
+    #[tokio::test]
+    async fn reconnects_to_peer_that_hung_up() {
+        let peer_address = Gen::socket_addr();
+        let listener = TcpListener::bind(peer_address).await.unwrap();
+        let (response_tx, _response_rx) = mpsc::channel::<RpcResponseInContext>(CHAN_BUF_SIZE);
+        let client = RpcClientConfig {
+            peer_addresses: vec![peer_address],
+            reconnect: ReconnectConfig::default(),
+            codec: FrameCodec::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(TcpTransport),
+        }
+        .run_with(response_tx)
+        .await
+        .unwrap();
+        let mut events = client.connection_events();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        drop(socket);
+        let reaccept = listener.accept().await;
+
+        assert!(reaccept.is_ok());
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Disconnected(peer_address.to_string())
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Reconnected(peer_address.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn abandons_peer_after_max_reconnect_attempts() {
+        let peer_address = Gen::socket_addr();
+        let listener = TcpListener::bind(peer_address).await.unwrap();
+        let (response_tx, _response_rx) = mpsc::channel::<RpcResponseInContext>(CHAN_BUF_SIZE);
+        let client = RpcClientConfig {
+            peer_addresses: vec![peer_address],
+            reconnect: ReconnectConfig {
+                max_attempts: Some(2),
+                ..ReconnectConfig::default()
+            },
+            codec: FrameCodec::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(TcpTransport),
+        }
+        .run_with(response_tx)
+        .await
+        .unwrap();
+        let mut events = client.connection_events();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        drop(listener);
+        drop(socket);
+
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Disconnected(peer_address.to_string())
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Abandoned(peer_address.to_string())
+        );
+    }
+
+    #[test_context(RunningClient)]
+    #[tokio::test]
+    async fn fails_to_remove_unknown_peer(ctx: RunningClient) {
+        let unknown_address = Gen::socket_addr();
+        let result = ctx.0.client.remove_peer(&unknown_address).await;
+        assert_eq!(
+            result.err().unwrap().downcast_ref(),
+            Some(&NoPeerAtAddress(unknown_address.to_string()))
+        );
+    }
+
+    #[test_context(RunningClient)]
+    #[tokio::test]
+    async fn sends_requests_to_peers(mut ctx: RunningClient) {
+        ctx.0
             .client
             .send_many(ctx.0.requests_by_peer.clone())
             .await
             .unwrap();
 
         let (expected_receiving_peers, expected_received_requests) = (
-            HashSet::from_iter(ctx.0.peer_addresses.clone().into_iter()),
+            HashSet::from_iter(ctx.0.peer_addresses.clone()),
             HashSet::from_iter((0..5).map(|id| RpcRequestEnvelope {
                 id,
                 request: APPEND_REQ.clone(),
@@ -369,15 +778,17 @@ mod test_rpc_client {
     #[test_context(ClientReceivingAppendSuccess)]
     #[tokio::test]
     async fn emits_responses_from_peers_onto_channel(mut ctx: ClientReceivingAppendSuccess) {
-        let _ = ctx
-            .0
+        ctx.0
             .client
             .send_many(ctx.0.requests_by_peer.clone())
             .await
             .unwrap();
 
+        // the client keeps its `response_tx` open (to hand to peers added later), so read exactly as
+        // many responses as we expect rather than waiting for the channel to close
         let mut responses = Vec::new();
-        while let Some((_, _, resp)) = ctx.0.response_rx.recv().await {
+        for _ in 0..ctx.0.expected_responses.len() {
+            let (_, _, resp) = ctx.0.response_rx.recv().await.unwrap();
             responses.push(resp);
         }
         assert_eq!(responses, ctx.0.expected_responses.clone());
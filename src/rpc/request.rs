@@ -17,6 +17,8 @@ tcp_serializable!(RpcRequestEnvelope);
 #[serde(tag = "type", deny_unknown_fields)]
 pub enum RpcRequest {
     AppendEntries(AppendEntriesRequest),
+    RequestVote(RequestVoteRequest),
+    DigestValues(DigestValuesRequest),
 }
 tcp_serializable!(RpcRequest);
 
@@ -24,8 +26,29 @@ tcp_serializable!(RpcRequest);
 pub struct AppendEntriesRequest {
     pub entries: Vec<LogEntry>, // log entries to store (empty for heartbeat; may send more than one for efficiency)
     pub leader_address: String, // so follower can redirect clients
-    pub leader_commit: usize,   // index of last log entry leader has committed
-    pub leader_term: usize,     // leader’s term
-    pub prev_log_index: usize,  // index of log entry immediately preceding new ones
-    pub prev_log_term: usize,   // term of prevLogIndex entry
+    // so follower can tell redirected clients where to retry (omitted from the wire if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leader_api_address: Option<String>,
+    pub leader_commit: usize, // index of last log entry leader has committed
+    // index up to which every node has stored (and the leader committed) the leader's log, so
+    // followers may compact it without leaving any peer unable to catch up should they lead later
+    #[serde(default)]
+    pub leader_compactable_index: usize,
+    pub leader_term: usize,    // leader’s term
+    pub prev_log_index: usize, // index of log entry immediately preceding new ones
+    pub prev_log_term: usize,  // term of prevLogIndex entry
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Hash)]
+pub struct RequestVoteRequest {
+    pub candidate_address: String, // candidate requesting vote
+    pub candidate_term: usize,     // candidate's term
+    pub last_log_index: usize,     // index of candidate's last log entry
+    pub last_log_term: usize,      // term of candidate's last log entry
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Hash)]
+pub struct DigestValuesRequest {
+    pub audit_id: u64,     // so that the auditing node can match responses to the audit
+    pub keys: Vec<String>, // keys whose values we want digests of
 }
@@ -17,6 +17,8 @@ tcp_serializable!(RpcResponseEnvelope);
 #[serde(tag = "type", deny_unknown_fields)]
 pub enum RpcResponse {
     ToAppendEntries(AppendEntriesResponse),
+    ToRequestVote(RequestVoteResponse),
+    ToDigestValues(DigestValuesResponse),
 }
 tcp_serializable!(RpcResponse);
 
@@ -26,6 +28,17 @@ pub struct AppendEntriesResponse {
     pub success: bool,    // true if follower contained entry matching prevLogIndex and prevLogTerm
 }
 
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Hash)]
+pub struct RequestVoteResponse {
+    pub peer_term: usize,   // currentTerm, for candidate to update itself
+    pub vote_granted: bool, // true means candidate received vote
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, Hash)]
+pub struct DigestValuesResponse {
+    pub digests: Vec<Option<u64>>, // digest of value at each requested key (`None` if absent)
+}
+
 impl RpcResponseEnvelope {
     pub fn of_append_entry(id: u64, response: AppendEntriesResponse) -> RpcResponseEnvelope {
         Self {
@@ -33,4 +46,16 @@ impl RpcResponseEnvelope {
             response: RpcResponse::ToAppendEntries(response),
         }
     }
+    pub fn of_request_vote(id: u64, response: RequestVoteResponse) -> RpcResponseEnvelope {
+        Self {
+            id,
+            response: RpcResponse::ToRequestVote(response),
+        }
+    }
+    pub fn of_digest_values(id: u64, response: DigestValuesResponse) -> RpcResponseEnvelope {
+        Self {
+            id,
+            response: RpcResponse::ToDigestValues(response),
+        }
+    }
 }
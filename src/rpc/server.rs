@@ -1,15 +1,23 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
 use tokio::sync::oneshot::Sender as OneShotSender;
 
-use crate::error::Result;
+#[cfg(feature = "tls")]
+use crate::error::ConfigError::MissingClientCaBundle;
+#[cfg(feature = "tls")]
+use crate::error::ConfigError::TlsOverEncryptedTransport;
+use crate::error::{NetworkError, Result};
 use crate::rpc::request::RpcRequestEnvelope;
 use crate::rpc::response::RpcResponseEnvelope;
 use crate::rpc::RpcServerConnection;
+use crate::tcp::BoxedStream;
+use crate::tls;
+#[cfg(feature = "tls")]
+use crate::tls::TlsServerConfig;
+use crate::transport::Transport;
 
 pub type RespondableRpcRequest = (RpcRequestEnvelope, RpcResponder);
 
@@ -17,6 +25,11 @@ pub type RpcResponder = OneShotSender<RpcResponseEnvelope>;
 
 pub struct RpcServerConfig {
     pub address: SocketAddr,
+    // if set, only accept peers that connect over TLS, presenting certificates signed by a CA in its
+    // (required) `client_ca_path`
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsServerConfig>,
+    pub transport: Arc<dyn Transport>,
 }
 
 #[allow(unused)]
@@ -26,16 +39,38 @@ pub struct RpcServer {
 
 impl RpcServerConfig {
     pub async fn run_with(self, request_tx: Sender<RespondableRpcRequest>) -> Result<RpcServer> {
-        let tcp_listener = TcpListener::bind(&self.address).await?;
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() && self.transport.encrypts() {
+            return Err(TlsOverEncryptedTransport.boxed());
+        }
+        #[cfg(feature = "tls")]
+        if self
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.client_ca_path.is_none())
+        {
+            return Err(MissingClientCaBundle.boxed());
+        }
+        #[cfg(feature = "tls")]
+        let acceptor = self.tls.as_ref().map(TlsServerConfig::load).transpose()?;
+        #[cfg(not(feature = "tls"))]
+        let acceptor: Option<tls::TlsAcceptor> = None;
+        let mut listener = self.transport.bind(self.address).await?;
         println!("> RpcServer listening on {:?}", &self.address);
 
         tokio::spawn(async move {
             // TODO: use select here to insert kill switch for shutdown
             loop {
-                let (socket, client_addr) = tcp_listener.accept().await.unwrap();
+                let (socket, client_addr) = listener.accept().await.unwrap();
                 println!("> RpcServer got connection on {}", &client_addr);
                 let request_tx = request_tx.clone();
-                tokio::spawn(async move { RpcServer::handle_messages(socket, request_tx).await });
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    match tls::accept(socket, acceptor.as_ref()).await {
+                        Ok(stream) => RpcServer::handle_messages(stream, request_tx).await,
+                        Err(e) => eprintln!("> RpcServer rejected {}: {}", &client_addr, e),
+                    }
+                });
             }
         });
 
@@ -48,22 +83,28 @@ impl RpcServerConfig {
 impl RpcServer {
     /// Process data from a socket connection
     async fn handle_messages(
-        socket: TcpStream,
+        socket: BoxedStream,
         request_tx: Sender<(RpcRequestEnvelope, OneShotSender<RpcResponseEnvelope>)>,
     ) {
-        let connection = Arc::new(RpcServerConnection::new(socket));
+        let connection = Arc::new(RpcServerConnection::negotiated(socket));
         tokio::spawn(async move {
             loop {
-                if let Ok(req) = connection.read().await {
-                    let (response_tx, response_rx) = oneshot::channel::<RpcResponseEnvelope>();
-                    let _ = request_tx.send((req, response_tx)).await;
-                    let write_connection = connection.clone();
-                    tokio::spawn(async move {
-                        // TODO: insert timeout here?
-                        if let Ok(response) = response_rx.await {
-                            let _ = write_connection.write(response).await;
-                        }
-                    });
+                match connection.read().await {
+                    Ok(req) => {
+                        let (response_tx, response_rx) = oneshot::channel::<RpcResponseEnvelope>();
+                        let _ = request_tx.send((req, response_tx)).await;
+                        let write_connection = connection.clone();
+                        tokio::spawn(async move {
+                            // TODO: insert timeout here?
+                            if let Ok(response) = response_rx.await {
+                                let _ = write_connection.write(response).await;
+                            }
+                        });
+                    }
+                    // stop listening if connection to client is no longer usable
+                    Err(e) if NetworkError::is_disconnect(&e) => return,
+                    // skip any frame we could not deserialize
+                    Err(_) => {}
                 }
             }
         });
@@ -81,6 +122,7 @@ impl RpcServer {
 #[cfg(test)]
 mod rpc_server_tests {
     use test_context::{test_context, AsyncTestContext};
+    use tokio::net::TcpStream;
     use tokio::sync::mpsc;
     use tokio::sync::mpsc::Receiver;
 
@@ -89,6 +131,7 @@ mod rpc_server_tests {
 
     use super::*;
     use crate::rpc::RpcClientConnection;
+    use crate::transport::TcpTransport;
 
     lazy_static! {
         static ref BUF_SIZE: usize = 10;
@@ -106,10 +149,15 @@ mod rpc_server_tests {
             let (request_tx, request_rx) = mpsc::channel::<RespondableRpcRequest>(CHAN_BUF_SIZE);
 
             // TODO: hold onto this assignment to test shutdown...
-            let _ = RpcServerConfig { address }
-                .run_with(request_tx)
-                .await
-                .unwrap();
+            let _ = RpcServerConfig {
+                address,
+                #[cfg(feature = "tls")]
+                tls: None,
+                transport: Arc::new(TcpTransport),
+            }
+            .run_with(request_tx)
+            .await
+            .unwrap();
 
             let socket = TcpStream::connect(address).await.unwrap();
             let client_conn = RpcClientConnection::new(socket);
@@ -125,7 +173,7 @@ mod rpc_server_tests {
     #[tokio::test]
     async fn listens_for_requests_from_client_and_puts_them_on_channel(mut ctx: RunningServer) {
         let expected_req = Gen::rpc_request_envelope();
-        let _ = ctx.client_conn.write(expected_req.clone()).await.unwrap();
+        ctx.client_conn.write(expected_req.clone()).await.unwrap();
         let (actual_req, _) = ctx.request_rx.recv().await.unwrap();
         assert_eq!(expected_req, actual_req);
     }
@@ -134,13 +182,34 @@ mod rpc_server_tests {
     #[tokio::test]
     async fn listens_for_responses_on_channel_and_writes_them_to_client(mut ctx: RunningServer) {
         let request = Gen::rpc_request_envelope();
-        let _ = ctx.client_conn.write(request.clone()).await.unwrap();
+        ctx.client_conn.write(request.clone()).await.unwrap();
 
         let expected_response = Gen::rpc_response_envelope();
         let (_, responder) = ctx.request_rx.recv().await.unwrap();
-        let _ = responder.send(expected_response.clone()).unwrap();
+        responder.send(expected_response.clone()).unwrap();
 
         let actual_response = ctx.client_conn.read().await.unwrap();
         assert_eq!(expected_response, actual_response);
     }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn refuses_to_run_with_tls_that_does_not_verify_peers() {
+        let path = format!("test_data/tls_{}", Gen::usize());
+        let (server_tls, _) = Gen::tls_configs(&path).await;
+        let (request_tx, _) = mpsc::channel::<RespondableRpcRequest>(CHAN_BUF_SIZE);
+        let result = RpcServerConfig {
+            address: Gen::socket_addr(),
+            tls: Some(server_tls),
+            transport: Arc::new(TcpTransport),
+        }
+        .run_with(request_tx)
+        .await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            MissingClientCaBundle.to_string()
+        );
+    }
 }
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_json;
-use tokio::fs::metadata;
+use tokio::fs::{metadata, rename};
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter, ErrorKind};
 use tokio_stream::wrappers::LinesStream;
@@ -33,12 +33,42 @@ pub struct LogEntry {
 #[serde(tag = "type", deny_unknown_fields)]
 pub enum Command {
     NoOp,
-    Put { key: String, value: String },
+    Put {
+        key: String,
+        value: String,
+        /// When the value expires (in millis since the unix epoch), if ever. (The leader picks this
+        /// when it appends the entry, so that every node agrees on the deadline. Each node checks
+        /// it against its own clock, though, so nodes only stop serving the value at the same
+        /// moment if their clocks are synchronized.)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+    },
+    Delete {
+        key: String,
+    },
+    /// Lease ids `start..start + count` in the named sequence (the leader picks `start` when it
+    /// appends the entry, so that every node applies the same allocation)
+    AllocateIds {
+        sequence_name: String,
+        start: u64,
+        count: u64,
+    },
+    /// Apply each of `commands` in order as a single entry (so that no other entry is ever applied
+    /// between them, and every node applies either all of them or none)
+    Batch {
+        commands: Vec<Command>,
+    },
+    /// Stands in for the entry at `index` (and all entries before it) once they have been
+    /// compacted into a snapshot. Only ever found at the head of a log.
+    Compacted {
+        index: usize,
+    },
 }
 
 pub struct Log {
     pub path: String,
     pub entries: Vec<LogEntry>,
+    pub first_index: usize, // index of `entries[0]` (nonzero once the log has been compacted)
 }
 
 impl LogEntry {
@@ -46,17 +76,14 @@ impl LogEntry {
         serde_json::from_str(&line)
             .map_err(|e| Box::new(LogDeserializationError(e.to_string())) as AsyncError)
     }
-    fn to_string(self) -> String {
-        serde_json::to_string(&self).unwrap()
-    }
-    pub(crate) fn to_bytes(self) -> Vec<u8> {
-        serde_json::to_vec(&self).unwrap()
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
     }
 }
 
-impl Into<String> for LogEntry {
-    fn into(self) -> String {
-        self.to_string()
+impl From<LogEntry> for String {
+    fn from(entry: LogEntry) -> Self {
+        serde_json::to_string(&entry).unwrap()
     }
 }
 
@@ -65,6 +92,7 @@ impl Log {
         Log {
             path,
             entries: Vec::new(),
+            first_index: 0,
         }
     }
 
@@ -75,24 +103,32 @@ impl Log {
     /// (and thus pollute the entire codebase with Option<usize> to prevent integer overflows that
     /// are only ever a threat on the first time we append an entry).
     pub async fn load_from(path: &str) -> Result<Log> {
-        let _ = Self::initialize_if_empty(path).await?;
+        Self::initialize_if_empty(path).await?;
 
         let entries = LinesStream::new(BufReader::new(File::open(path).await?).lines())
             .filter_map(|line| line.ok())
             .filter_map(|ok_line| LogEntry::from(ok_line).ok())
             .collect::<Vec<LogEntry>>()
             .await;
+        let first_index = match entries.first() {
+            Some(LogEntry {
+                command: Command::Compacted { index },
+                ..
+            }) => *index,
+            _ => 0,
+        };
 
         Ok(Log {
             entries,
             path: path.to_string(),
+            first_index,
         })
     }
 
     pub async fn initialize_if_empty(path: &str) -> Result<()> {
         match metadata(&path).await {
             Err(e) if e.kind() == ErrorKind::NotFound => {
-                let _ = File::create(path).await?.write_all(&INIT_LOG_LINE).await?;
+                File::create(path).await?.write_all(&INIT_LOG_LINE).await?;
                 Ok(())
             }
             _ => Ok(()),
@@ -103,12 +139,16 @@ impl Log {
         self.entries.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
     pub async fn append(&mut self, entry: &LogEntry) -> Result<()> {
         let file = OpenOptions::new().append(true).open(&self.path).await?;
 
         let mut writer = BufWriter::new(file);
-        writer.write(&entry.clone().to_bytes()).await?;
-        writer.write(&[NEWLINE]).await?;
+        writer.write_all(&entry.to_bytes()).await?;
+        writer.write_all(&[NEWLINE]).await?;
         writer.flush().await?;
 
         self.entries.push(entry.clone());
@@ -129,7 +169,7 @@ impl Log {
         let last_entry = self.entries.last().ok_or(err())?.clone();
         let file_len = file.metadata().await?.len();
         let bytes_to_truncate = last_entry.to_bytes().len() as u64 + 1; // +1 for \n
-        let _ = file.set_len(file_len - bytes_to_truncate).await?;
+        file.set_len(file_len - bytes_to_truncate).await?;
 
         let entry = self.entries.pop().ok_or(err())?;
         Ok(entry)
@@ -142,34 +182,70 @@ impl Log {
         Ok(())
     }
 
+    /// Retrieve the entry at `index`, if it is in the log (and has not been compacted away).
+    pub fn get(&self, index: usize) -> Option<&LogEntry> {
+        index
+            .checked_sub(self.first_index)
+            .and_then(|offset| self.entries.get(offset))
+    }
+
+    /// Retrieve the entries from index `from` through index `to` (inclusive). Panic if any of them
+    /// have been compacted away or were never appended (as with `get_term_at`).
+    pub fn get_range(&self, from: usize, to: usize) -> &[LogEntry] {
+        &self.entries[from - self.first_index..=to - self.first_index]
+    }
+
     pub fn has_matching(&self, index: usize, term: usize) -> bool {
-        self.entries
-            .get(index)
-            .map_or(false, |entry| entry.term == term)
+        self.get(index).is_some_and(|entry| entry.term == term)
     }
 
     pub fn find_conflict(
         &self,
-        new_entries: &Vec<LogEntry>,
+        new_entries: &[LogEntry],
         first_idx_to_compare: usize,
     ) -> Option<usize> {
         for (i, new_entry) in new_entries.iter().enumerate() {
             let idx_to_compare = first_idx_to_compare + i;
             let has_conflict = self
-                .entries
                 .get(idx_to_compare)
-                .map_or(false, |entry| entry.term != new_entry.term);
+                .is_some_and(|entry| entry.term != new_entry.term);
             if has_conflict {
                 return Some(idx_to_compare);
             }
         }
-        return None;
+        None
     }
 
     /// Remove backwards from tail of the log until reaching the entry at `idx`, which will now be
     /// the last entry in the truncated log.
     pub async fn remove_until(&mut self, idx: usize) -> Result<()> {
-        self.remove_many(self.entries.len() - idx - 1).await
+        self.remove_many(self.get_last_index() - idx).await
+    }
+
+    /// Discard all entries before `index` (which must already be captured in a snapshot), replacing
+    /// the entry at `index` with a `Compacted` marker that keeps its term (so we can still check
+    /// requests that follow it for consistency). We rewrite the log to a temporary file and then
+    /// move it into place, so a crash part way through leaves the uncompacted log intact.
+    pub async fn compact_until(&mut self, index: usize) -> Result<()> {
+        let marker = LogEntry {
+            term: self.get_term_at(index),
+            command: Command::Compacted { index },
+        };
+        let mut entries = vec![marker];
+        entries.extend_from_slice(&self.entries[index - self.first_index + 1..]);
+
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut writer = BufWriter::new(File::create(&tmp_path).await?);
+        for entry in &entries {
+            writer.write_all(&entry.to_bytes()).await?;
+            writer.write_all(&[NEWLINE]).await?;
+        }
+        writer.flush().await?;
+        rename(&tmp_path, &self.path).await?;
+
+        self.entries = entries;
+        self.first_index = index;
+        Ok(())
     }
 
     /// Retrieve the index of the last entry in a log. We do not check against overflow
@@ -177,15 +253,14 @@ impl Log {
     /// entry in an empty log of length 0) b/c we insert a NoOp command into an empty log to
     /// guarantee all logs have at least length 1.
     pub fn get_last_index(&self) -> usize {
-        self.entries.len() - 1
+        self.first_index + self.entries.len() - 1
     }
 
     /// Retrieve the term of a log entry at the given index. Panic if we try to retrieve
     /// the term of an index not in the log. (Safe to do b/c it is a programming error if that
     /// ever happens.)
     pub fn get_term_at(&self, index: usize) -> usize {
-        assert!(self.entries.len() > index);
-        self.entries[index].term
+        self.get(index).unwrap().term
     }
 }
 
@@ -207,6 +282,7 @@ mod test_log {
             command: Command::Put {
                 key: "foo".to_string(),
                 value: "bar".to_string(),
+                expires_at: None,
             },
         };
         static ref LOG_ENTRIES: Vec<LogEntry> = vec![
@@ -219,6 +295,7 @@ mod test_log {
                 command: Command::Put {
                     key: "foo".to_string(),
                     value: "bar".to_string(),
+                    expires_at: None,
                 },
             },
             LogEntry {
@@ -226,6 +303,7 @@ mod test_log {
                 command: Command::Put {
                     key: "foo".to_string(),
                     value: "baz".to_string(),
+                    expires_at: None,
                 },
             },
             LogEntry {
@@ -233,6 +311,7 @@ mod test_log {
                 command: Command::Put {
                     key: "foo".to_string(),
                     value: "qux".to_string(),
+                    expires_at: None,
                 },
             },
             LogEntry {
@@ -240,6 +319,7 @@ mod test_log {
                 command: Command::Put {
                     key: "foo".to_string(),
                     value: "qux".to_string(),
+                    expires_at: None,
                 },
             },
         ];
@@ -252,7 +332,8 @@ mod test_log {
 
     impl Context {
         async fn setup(original_entries: Vec<LogEntry>) -> Self {
-            let log_path = format!("test_data/log_{}.txt", Gen::usize().to_string());
+            tokio::fs::create_dir_all("test_data").await.unwrap();
+            let log_path = format!("test_data/log_{}.txt", Gen::usize());
             if !original_entries.is_empty() {
                 let _ = Log::from_entries(log_path.clone(), original_entries.clone())
                     .await
@@ -264,7 +345,7 @@ mod test_log {
             }
         }
         async fn teardown(self) {
-            let _ = tokio::fs::remove_file(self.log_path).await.unwrap();
+            tokio::fs::remove_file(self.log_path).await.unwrap();
         }
     }
 
@@ -301,6 +382,7 @@ mod test_log {
             command: Command::Put {
                 key: "foo".to_string(),
                 value: "bar".to_string(),
+                expires_at: None,
             },
         };
         let expected_result = r#"{"term":1,"command":{"type":"Put","key":"foo","value":"bar"}}"#;
@@ -317,6 +399,7 @@ mod test_log {
             command: Command::Put {
                 key: "foo".to_string(),
                 value: "bar".to_string(),
+                expires_at: None,
             },
         };
         let actual_result = LogEntry::from(serialized_entry).unwrap();
@@ -343,7 +426,7 @@ mod test_log {
     #[tokio::test]
     async fn appends_entry_to_log(ctx: EmptyLog) {
         let mut log = Log::load_from(&ctx.0.log_path).await.unwrap();
-        let _ = log.append(&PUT_ENTRY).await.unwrap();
+        log.append(&PUT_ENTRY).await.unwrap();
         let persisted_log = Log::load_from(&log.path).await.unwrap();
 
         assert_eq!(log.entries, vec![NOOP_ENTRY.clone(), PUT_ENTRY.clone()]);
@@ -374,8 +457,7 @@ mod test_log {
     #[tokio::test]
     async fn appends_many_entries_to_log(ctx: EmptyLog) {
         let mut log = Log::load_from(&ctx.0.log_path).await.unwrap();
-        let _ = log
-            .append_many(&vec![PUT_ENTRY.clone(), PUT_ENTRY.clone()])
+        log.append_many(&vec![PUT_ENTRY.clone(), PUT_ENTRY.clone()])
             .await
             .unwrap();
         let persisted_log = Log::load_from(&ctx.0.log_path).await.unwrap();
@@ -395,7 +477,7 @@ mod test_log {
     async fn removes_many_entries_from_log(ctx: LogWithEntries) {
         let original_entries = ctx.0.original_entries.clone();
         let mut log = Log::load_from(&ctx.0.log_path).await.unwrap();
-        let _ = log.remove_many(2).await.unwrap();
+        log.remove_many(2).await.unwrap();
         let persisted_log = Log::load_from(&ctx.0.log_path).await.unwrap();
 
         assert_eq!(
@@ -416,11 +498,33 @@ mod test_log {
             original_entries,
         }) = &ctx;
 
-        let mut log = Log::load_from(&log_path).await.unwrap();
-        let _ = log.remove_until(1).await.unwrap();
-        let persisted_log = Log::load_from(&log_path).await.unwrap();
+        let mut log = Log::load_from(log_path).await.unwrap();
+        log.remove_until(1).await.unwrap();
+        let persisted_log = Log::load_from(log_path).await.unwrap();
 
         assert_eq!(log.entries, original_entries[0..2]);
         assert_eq!(persisted_log.entries, original_entries[0..2]);
     }
+
+    #[test_context(LogWithEntries)]
+    #[tokio::test]
+    async fn compacts_entries_before_a_given_index(ctx: LogWithEntries) {
+        let LogWithEntries(Context {
+            log_path,
+            original_entries,
+        }) = &ctx;
+
+        let mut log = Log::load_from(log_path).await.unwrap();
+        log.compact_until(2).await.unwrap();
+        let persisted_log = Log::load_from(log_path).await.unwrap();
+
+        for log in [&log, &persisted_log] {
+            assert_eq!(log.first_index, 2);
+            assert_eq!(log.get_last_index(), original_entries.len() - 1);
+            assert_eq!(log.get(2).unwrap().command, Command::Compacted { index: 2 });
+            assert_eq!(log.get_term_at(2), original_entries[2].term);
+            assert_eq!(log.get(1), None);
+            assert_eq!(log.get_range(3, 4), &original_entries[3..=4]);
+        }
+    }
 }
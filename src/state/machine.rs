@@ -12,11 +12,39 @@ impl StateMachine {
     }
 
     pub async fn apply(&self, entry: &LogEntry) {
-        match &entry.command {
-            Command::Put { key, value } => {
-                self.store.put(&key, &value).await;
+        let _batch_guard = match entry.command {
+            Command::Batch { .. } => Some(self.store.lock_for_batch().await),
+            _ => None,
+        };
+        self.apply_command(&entry.command).await;
+    }
+
+    async fn apply_command(&self, command: &Command) {
+        match command {
+            Command::Put {
+                key,
+                value,
+                expires_at,
+            } => {
+                self.store.put_expiring(key, value, *expires_at).await;
+            }
+            Command::Delete { key } => {
+                self.store.delete(key).await;
+            }
+            Command::AllocateIds {
+                sequence_name,
+                start,
+                count,
+            } => {
+                self.store
+                    .advance_sequence(sequence_name, start.saturating_add(*count));
+            }
+            Command::Batch { commands } => {
+                for command in commands {
+                    Box::pin(self.apply_command(command)).await;
+                }
             }
-            Command::NoOp => {}
+            Command::NoOp | Command::Compacted { .. } => {}
         };
     }
 
@@ -38,6 +66,7 @@ mod test_state_machine {
                 command: Command::Put {
                     key: "foo".to_string(),
                     value: "bar".to_string(),
+                    expires_at: None,
                 }
             },
             LogEntry {
@@ -45,6 +74,7 @@ mod test_state_machine {
                 command: Command::Put {
                     key: "foo".to_string(),
                     value: "baz".to_string(),
+                    expires_at: None,
                 }
             },
             LogEntry {
@@ -52,17 +82,51 @@ mod test_state_machine {
                 command: Command::Put {
                     key: "bar".to_string(),
                     value: "qux".to_string(),
+                    expires_at: None,
                 }
             },
         ];
     }
 
+    #[tokio::test]
+    async fn applies_batched_commands_in_order() {
+        let store = Arc::new(Store::new());
+        let state_machine = StateMachine::new(store.clone());
+        let entry = LogEntry {
+            term: 1,
+            command: Command::Batch {
+                commands: ENTRIES.iter().map(|entry| entry.command.clone()).collect(),
+            },
+        };
+        state_machine.apply(&entry).await;
+
+        assert_eq!(store.get("foo").await, Some("baz".to_string()));
+        assert_eq!(store.get("bar").await, Some("qux".to_string()));
+    }
+
     #[tokio::test]
     async fn applies_log_entries_to_a_store() {
         let store = Arc::new(Store::new());
         let state_machine = StateMachine::new(store.clone());
-        let _ = state_machine.apply_many(&*ENTRIES).await;
+        let _ = state_machine.apply_many(&ENTRIES).await;
         assert_eq!(store.get("foo").await, Some("baz".to_string()));
         assert_eq!(store.get("bar").await, Some("qux".to_string()));
     }
+
+    #[tokio::test]
+    async fn applies_delete_entries() {
+        let store = Arc::new(Store::new());
+        let state_machine = StateMachine::new(store.clone());
+        let delete = LogEntry {
+            term: 4,
+            command: Command::Delete {
+                key: "bar".to_string(),
+            },
+        };
+        let _ = state_machine.apply_many(&ENTRIES).await;
+        state_machine.apply(&delete).await;
+
+        assert_eq!(store.get("foo").await, Some("baz".to_string()));
+        assert_eq!(store.get("bar").await, None);
+    }
 }
@@ -1,43 +1,81 @@
 use crate::error::PersistenceError::MetadataParseError;
 use crate::error::Result;
 
-use tokio::fs::metadata;
 use tokio::fs::File;
+use tokio::fs::{metadata, rename};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, ErrorKind};
 
-const CURRENT_TERM_FILEPATH: &'static str = "/current_term.txt";
+// current term on the first line, and candidate voted for (if any) on the second
+const TERM_AND_VOTE_FILEPATH: &str = "/term_and_vote.txt";
+const TERM_AND_VOTE_TEMP_FILEPATH: &str = "/term_and_vote.txt.tmp";
 
 pub struct PersistentMetadata {
-    current_term_path: String,
+    dir_path: String,
+    path: String,
+    temp_path: String, // where we write each update before renaming it over `path`
     pub current_term: usize,
+    pub voted_for: Option<String>, // candidate this node voted for in the current term (if any)
 }
 
 impl PersistentMetadata {
-    /// Load persistent metadata from a given metadata directory, creating files for storing
-    /// each metadata item and initializing them if none already exist.
+    /// Load persistent metadata from a given metadata directory, creating the file that stores it
+    /// (and initializing it to term 0, with no vote) if none already exists.
     pub async fn load_from(path: String) -> Result<PersistentMetadata> {
-        let current_term_path: String = path.clone() + CURRENT_TERM_FILEPATH;
-        let _ = Self::initialize_if_empty(&current_term_path).await?;
-        let current_term = Self::read_usize(&current_term_path).await?;
-
-        Ok(Self {
-            current_term_path,
-            current_term,
-        })
+        let mut persisted = Self {
+            path: path.clone() + TERM_AND_VOTE_FILEPATH,
+            temp_path: path.clone() + TERM_AND_VOTE_TEMP_FILEPATH,
+            dir_path: path,
+            current_term: 0,
+            voted_for: None,
+        };
+        match metadata(&persisted.path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => persisted.save(0, None).await?,
+            _ => {
+                let contents = Self::read_value(&persisted.path).await?;
+                let contents = String::from_utf8_lossy(&contents);
+                let (term, vote) = contents
+                    .split_once('\n')
+                    .ok_or(MetadataParseError.boxed())?;
+                persisted.current_term = term.parse().map_err(|_| MetadataParseError.boxed())?;
+                persisted.voted_for = Some(vote.to_string()).filter(|vote| !vote.is_empty());
+            }
+        }
+        Ok(persisted)
     }
 
-    #[allow(unused)]
+    /// Move to a new `term`, forgetting whom we voted for in the previous one.
     pub async fn update_current_term(&mut self, term: usize) -> Result<()> {
-        let _ = File::create(&self.current_term_path)
-            .await?
-            .write_all(term.to_string().as_bytes())
-            .await?;
+        self.update_term_and_vote(term, None).await
+    }
+
+    pub async fn update_voted_for(&mut self, candidate: Option<String>) -> Result<()> {
+        self.update_term_and_vote(self.current_term, candidate)
+            .await
+    }
+
+    /// Move to a new `term` and vote in it at once (as candidates do for themselves).
+    pub async fn update_term_and_vote(
+        &mut self,
+        term: usize,
+        candidate: Option<String>,
+    ) -> Result<()> {
+        self.save(term, candidate.as_deref()).await?;
         self.current_term = term;
+        self.voted_for = candidate;
         Ok(())
     }
 
-    async fn read_usize(path: &str) -> Result<usize> {
-        atoi::atoi::<usize>(&Self::read_value(&path).await?).ok_or(MetadataParseError.boxed())
+    /// Write `term` and `candidate` to a temp file, fsync it, then rename it over the last ones we
+    /// saved (and fsync the directory holding them), so that a crash at any point leaves us with
+    /// either the old term and vote or the new ones -- never a mix of the two, or a torn write.
+    async fn save(&self, term: usize, candidate: Option<&str>) -> Result<()> {
+        let mut file = File::create(&self.temp_path).await?;
+        file.write_all(format!("{}\n{}", term, candidate.unwrap_or_default()).as_bytes())
+            .await?;
+        file.sync_all().await?;
+        rename(&self.temp_path, &self.path).await?;
+        File::open(&self.dir_path).await?.sync_all().await?;
+        Ok(())
     }
 
     pub async fn read_value(path: &str) -> Result<Vec<u8>> {
@@ -45,14 +83,58 @@ impl PersistentMetadata {
         let _ = File::open(path).await?.read_to_end(&mut buf).await?;
         Ok(buf)
     }
+}
 
-    pub async fn initialize_if_empty(path: &str) -> Result<()> {
-        match metadata(&path).await {
-            Err(e) if e.kind() == ErrorKind::NotFound => {
-                let _ = File::create(path).await?.write_all("0".as_bytes()).await?;
-                Ok(())
-            }
-            _ => Ok(()),
-        }
+#[cfg(test)]
+mod test_metadata {
+    use crate::test_support::gen::Gen;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn persists_term_and_vote() {
+        let path = format!("test_data/metadata_{}", Gen::usize());
+        tokio::fs::create_dir_all(&path).await.unwrap();
+
+        let mut metadata = PersistentMetadata::load_from(path.clone()).await.unwrap();
+        let (initial_term, initial_vote) = (metadata.current_term, metadata.voted_for.clone());
+        metadata.update_current_term(3).await.unwrap();
+        metadata
+            .update_voted_for(Some("foo".to_string()))
+            .await
+            .unwrap();
+        let reloaded = PersistentMetadata::load_from(path.clone()).await.unwrap();
+        metadata.update_current_term(4).await.unwrap();
+        let reloaded_after_new_term = PersistentMetadata::load_from(path.clone()).await.unwrap();
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!((initial_term, initial_vote), (0, None));
+        assert_eq!(
+            (reloaded.current_term, reloaded.voted_for),
+            (3, Some("foo".to_string()))
+        );
+        assert_eq!(reloaded_after_new_term.voted_for, None);
+    }
+
+    #[tokio::test]
+    async fn ignores_update_torn_before_it_was_renamed_into_place() {
+        let path = format!("test_data/metadata_{}", Gen::usize());
+        tokio::fs::create_dir_all(&path).await.unwrap();
+
+        let mut metadata = PersistentMetadata::load_from(path.clone()).await.unwrap();
+        metadata
+            .update_term_and_vote(3, Some("foo".to_string()))
+            .await
+            .unwrap();
+        tokio::fs::write(path.clone() + TERM_AND_VOTE_TEMP_FILEPATH, "4")
+            .await
+            .unwrap();
+        let reloaded = PersistentMetadata::load_from(path.clone()).await.unwrap();
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(
+            (reloaded.current_term, reloaded.voted_for),
+            (3, Some("foo".to_string()))
+        );
     }
 }
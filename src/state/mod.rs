@@ -1,29 +1,40 @@
-use crate::error::ProtocolError::RetryAppendEntry;
+use crate::error::ProtocolError::{EmptyIdAllocation, RetryAppendEntry, SequenceExhausted};
 use crate::error::Result;
-use crate::rpc::request::{AppendEntriesRequest, RpcRequest};
-use crate::rpc::response::AppendEntriesResponse;
+use crate::node::{Role, StartupPhase};
+use crate::rpc::request::{AppendEntriesRequest, RequestVoteRequest, RpcRequest};
+use crate::rpc::response::{AppendEntriesResponse, RequestVoteResponse};
 use crate::state::log::{Command, Log, LogEntry};
 use crate::state::machine::StateMachine;
 use crate::state::metadata::PersistentMetadata;
-use crate::state::store::Store;
+use crate::state::snapshot::Snapshot;
+use crate::state::store::{now_in_millis, Store};
 use crate::NodeAddr;
 
 use dashmap::DashMap;
 
-use std::cmp::min;
-
+use std::cmp::{max, min};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot::Sender as OneShotSender;
 use tokio::sync::{Mutex, MutexGuard};
 
 pub mod log;
 pub mod machine;
 pub mod metadata;
+pub mod snapshot;
 pub mod store;
 
+// receives the value digests each peer reports for an audit (see `Node::verify_replicas`)
+pub type DigestHandler = UnboundedSender<(NodeAddr, Vec<Option<u64>>)>;
+
 pub struct StateConfig {
+    pub role: Role, // role the node starts out in (before any elections)
     pub leader_address: NodeAddr,
     pub node_address: NodeAddr,
+    pub api_address: NodeAddr, // address of node's api server (so that followers may redirect to it)
     pub peer_addresses: Vec<NodeAddr>,
     pub log_path: String,
     pub metadata_path: String,
@@ -37,17 +48,33 @@ pub struct State {
     pub store: Arc<Store>,
     pub state_machine: Mutex<StateMachine>,
     pub on_apply_callbacks: Arc<DashMap<usize, OneShotSender<()>>>,
+    // keyed by log index, holding the number of replicas that must store the entry before notifying
+    pub on_replicated_callbacks: Arc<DashMap<usize, (usize, OneShotSender<()>)>>,
+    // keyed by audit id, forwarding the value digests each replica reports to the audit awaiting them
+    pub on_digest_callbacks: Arc<DashMap<u64, DigestHandler>>,
+    // number of times two different nodes have claimed leadership of the same term
+    pub num_split_brain_alarms: AtomicU64,
+    metadata_path: String, // directory in which we store snapshots
+    // when we last heard from a leader (or granted a vote, or started an election)
+    last_leader_contact: std::sync::Mutex<Instant>,
+    startup_phase: std::sync::Mutex<StartupPhase>,
 }
 
 pub struct LeaderMetadata {
     address: NodeAddr,
+    api_address: Option<NodeAddr>, // address of leader's api server (if leader has told us)
+    term: Option<usize>,           // term in which `address` was (last seen to be) leader (if any)
+    compactable_index: usize,      // index up to which leader has told us we may compact our log
 }
 
 pub struct NodeMetadata {
     pub address: String,           // node's (serialized) address
+    api_address: NodeAddr,         // address of node's api server
+    pub role: Role,                // whether node is currently leader, follower, or candidate
+    votes: HashSet<NodeAddr>, // nodes that have voted for us in the current term (if candidate)
     persisted: PersistentMetadata, // persisently stored values for current term & voted for
-    pub last_commit: usize,        // index of last committed log entry
-    pub last_applied: usize,       // index of last log entry applied to state machine
+    pub last_commit: usize,   // index of last committed log entry
+    pub last_applied: usize,  // index of last log entry applied to state machine
 }
 
 pub struct PeerMetadata {
@@ -59,14 +86,27 @@ pub struct PeerMetadata {
 
 impl LeaderMetadata {
     fn new(address: NodeAddr) -> LeaderMetadata {
-        Self { address }
+        Self {
+            address,
+            api_address: None,
+            term: None,
+            compactable_index: 0,
+        }
     }
 }
 
 impl NodeMetadata {
-    fn new(address: NodeAddr, persisted: PersistentMetadata) -> NodeMetadata {
+    fn new(
+        address: NodeAddr,
+        api_address: NodeAddr,
+        role: Role,
+        persisted: PersistentMetadata,
+    ) -> NodeMetadata {
         Self {
             address,
+            api_address,
+            role,
+            votes: HashSet::new(),
             persisted,
             last_commit: 0,
             last_applied: 0,
@@ -76,21 +116,25 @@ impl NodeMetadata {
     fn current_term(&self) -> usize {
         self.persisted.current_term
     }
+
+    /// Adopt a `term` higher than our own, reverting to follower (§5.1)
+    async fn step_down(&mut self, term: usize) -> Result<()> {
+        self.persisted.update_current_term(term).await?;
+        self.role = Role::Follower;
+        self.votes.clear();
+        Ok(())
+    }
 }
 
 impl PeerMetadata {
     fn new(peer_addresses: Vec<NodeAddr>, next_index: usize) -> PeerMetadata {
         Self {
-            next_indexes_by_peer: DashMap::from(
-                peer_addresses
-                    .clone()
-                    .into_iter()
-                    .map(|addr| (addr, next_index))
-                    .collect(),
-            ),
-            match_indexes_by_peer: DashMap::from(
-                peer_addresses.into_iter().map(|addr| (addr, 0)).collect(),
-            ),
+            next_indexes_by_peer: peer_addresses
+                .clone()
+                .into_iter()
+                .map(|addr| (addr, next_index))
+                .collect(),
+            match_indexes_by_peer: peer_addresses.into_iter().map(|addr| (addr, 0)).collect(),
         }
     }
 }
@@ -99,30 +143,55 @@ impl StateConfig {
     /// Create a live `State` wrapper from an inert `StateConfig` by loading the log and persistent
     /// metadata_for_test_node about node states from disk, initializing volatile node metadata_for_test_node, and initializing
     /// the store, state machine, and callback registry to notify subscribers when log entries
-    /// have been applied to the state machine.
+    /// have been applied to the state machine. If we have taken a snapshot, restore the store from
+    /// it and resume applying entries from the last one it captured.
     pub async fn run(self) -> Result<State> {
-        let log = Log::load_from(&self.log_path).await?;
-        let persisted = PersistentMetadata::load_from(self.metadata_path).await?;
+        let persisted = PersistentMetadata::load_from(self.metadata_path.clone()).await?;
         let store = Arc::new(Store::new());
+        let mut node = NodeMetadata::new(self.node_address, self.api_address, self.role, persisted);
+        log_startup_phase(StartupPhase::LoadingSnapshot);
+        if let Some(snapshot) = Snapshot::load_from(&self.metadata_path).await? {
+            store.restore(snapshot.data, snapshot.sequences, snapshot.expirations);
+            node.last_commit = snapshot.last_index;
+            node.last_applied = snapshot.last_index;
+        }
+        log_startup_phase(StartupPhase::ReplayingLog);
+        let log = Log::load_from(&self.log_path).await?;
+        log_startup_phase(StartupPhase::JoiningCluster);
 
         Ok(State {
             leader_metadata: Mutex::new(LeaderMetadata::new(self.leader_address)),
-            node_metadata: Mutex::new(NodeMetadata::new(self.node_address, persisted)),
-            peer_metadata: PeerMetadata::new(self.peer_addresses, log.len()),
+            node_metadata: Mutex::new(node),
+            peer_metadata: PeerMetadata::new(self.peer_addresses, log.get_last_index() + 1),
             log: Mutex::new(log),
             state_machine: Mutex::new(StateMachine::new(store.clone())),
             store,
             on_apply_callbacks: Arc::new(DashMap::new()),
+            on_replicated_callbacks: Arc::new(DashMap::new()),
+            on_digest_callbacks: Arc::new(DashMap::new()),
+            num_split_brain_alarms: AtomicU64::new(0),
+            metadata_path: self.metadata_path,
+            last_leader_contact: std::sync::Mutex::new(Instant::now()),
+            startup_phase: std::sync::Mutex::new(StartupPhase::JoiningCluster),
         })
     }
 }
 
+fn log_startup_phase(phase: StartupPhase) {
+    println!("> Node startup phase: {:?}", phase);
+}
+
 impl State {
     /// Attempt to fetch a `key`'s corresponding value from the `Store`. (Permits dirty reads)
     pub async fn fetch_from_store(&self, key: &str) -> Option<String> {
         self.store.get(key).await
     }
 
+    /// Remove every key in the store whose time to live has run out, returning how many were removed
+    pub fn remove_expired_from_store(&self) -> usize {
+        self.store.remove_expired(now_in_millis())
+    }
+
     /// Append a `Command` to the `Log`, return the log's new length
     pub async fn append_to_log(&self, command: Command) -> Result<usize> {
         let mut log = self.log.lock().await;
@@ -131,14 +200,280 @@ impl State {
             term: node.persisted.current_term,
             command,
         };
-        let _ = log.append(&entry).await?;
-        Ok(log.entries.len() - 1)
+        log.append(&entry).await?;
+        Ok(log.get_last_index())
+    }
+
+    /// (LEADERS ONLY)
+    /// Append a command leasing the next `count` ids in `sequence_name` to the `Log`, returning the
+    /// index of the new entry and the first leased id. We pick up where the last allocation in the
+    /// log left off, whether or not it has been applied yet (holding the log lock, so that no two
+    /// allocations can overlap). Fail without appending if `count` is zero, or would run the
+    /// sequence past `u64::MAX`.
+    pub async fn append_id_allocation(
+        &self,
+        sequence_name: &str,
+        count: u64,
+    ) -> Result<(usize, u64)> {
+        if count == 0 {
+            return Err(EmptyIdAllocation.boxed());
+        }
+        let mut log = self.log.lock().await;
+        let node = self.node_metadata.lock().await;
+
+        let applied_end = self.store.get_sequence_end(sequence_name);
+        let start = log
+            .get_range(node.last_applied + 1, log.get_last_index())
+            .iter()
+            .filter_map(|entry| match &entry.command {
+                Command::AllocateIds {
+                    sequence_name: name,
+                    start,
+                    count,
+                } if name == sequence_name => Some(start.saturating_add(*count)),
+                _ => None,
+            })
+            .fold(applied_end, u64::max);
+        if start.checked_add(count).is_none() {
+            return Err(SequenceExhausted(sequence_name.to_string(), count).boxed());
+        }
+
+        let entry = LogEntry {
+            term: node.persisted.current_term,
+            command: Command::AllocateIds {
+                sequence_name: sequence_name.to_string(),
+                start,
+                count,
+            },
+        };
+        log.append(&entry).await?;
+        Ok((log.get_last_index(), start))
     }
 
     /// Retrieve the index of the last entry in the `Log`
     pub async fn get_last_appended_index(&self) -> usize {
         let log = self.log.lock().await;
-        log.entries.len() - 1
+        log.get_last_index()
+    }
+
+    /// Retrieve the lowest index known to be replicated on every peer (or `usize::MAX` if we have
+    /// no peers).
+    pub fn get_min_match_index(&self) -> usize {
+        self.peer_metadata
+            .match_indexes_by_peer
+            .iter()
+            .map(|entry| *entry.value())
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Save a `Snapshot` of the store as of the last applied log entry, then compact the log up to
+    /// that entry -- or up to `max_compacted_index`, if lower (so that leaders can keep entries that
+    /// some peer still needs). Return the index of the last entry captured in the snapshot.
+    pub async fn snapshot(&self, max_compacted_index: usize) -> Result<usize> {
+        // hold the machine lock so that no entries are applied while we copy the store
+        let mut log = self.log.lock().await;
+        let _machine = self.state_machine.lock().await;
+        let node = self.node_metadata.lock().await;
+
+        let snapshot = Snapshot {
+            last_index: node.last_applied,
+            last_term: log.get_term_at(node.last_applied),
+            data: self.store.to_map(),
+            sequences: self.store.sequences_to_map(),
+            expirations: self.store.expirations_to_map(),
+        };
+        snapshot.save_to(&self.metadata_path).await?;
+
+        let compacted_index = min(node.last_applied, max_compacted_index);
+        if compacted_index > log.first_index {
+            log.compact_until(compacted_index).await?;
+        }
+        Ok(snapshot.last_index)
+    }
+
+    /// Retrieve the node's current `Role`
+    pub async fn get_role(&self) -> Role {
+        let node = self.node_metadata.lock().await;
+        node.role
+    }
+
+    /// Time elapsed since we last heard from a leader, granted a vote, or started an election
+    pub fn time_since_leader_contact(&self) -> Duration {
+        self.last_leader_contact.lock().unwrap().elapsed()
+    }
+
+    fn reset_election_timer(&self) {
+        *self.last_leader_contact.lock().unwrap() = Instant::now();
+    }
+
+    pub fn get_startup_phase(&self) -> StartupPhase {
+        *self.startup_phase.lock().unwrap()
+    }
+
+    /// Move on to a later `StartupPhase` (never back to an earlier one: once a node has caught up
+    /// it keeps serving, whatever later happens to its role)
+    pub fn advance_startup_phase(&self, phase: StartupPhase) {
+        let mut current = self.startup_phase.lock().unwrap();
+        if phase > *current {
+            *current = phase;
+            log_startup_phase(phase);
+        }
+    }
+
+    /// (FOLLOWERS AND CANDIDATES ONLY)
+    /// Start an election (§5.2) by incrementing the current term, voting for ourselves, and
+    /// generating a `RequestVote` call for every peer (returned in tuples with the peer's address
+    /// so that the caller may route them). If we have no peers, our own vote wins the election.
+    pub async fn begin_election(&self) -> Result<Vec<(NodeAddr, RpcRequest)>> {
+        let log = self.log.lock().await;
+        let mut node = self.node_metadata.lock().await;
+        self.reset_election_timer();
+
+        let term = node.current_term() + 1;
+        let address = node.address.clone();
+        node.persisted
+            .update_term_and_vote(term, Some(address.clone()))
+            .await?;
+        node.role = Role::Candidate;
+        node.votes = vec![address.clone()].into_iter().collect();
+
+        if self.has_majority_of_votes(&node) {
+            let mut leader = self.leader_metadata.lock().await;
+            self.become_leader(&log, &mut node, &mut leader);
+            return Ok(vec![]);
+        }
+
+        let last_log_index = log.get_last_index();
+        let request = RequestVoteRequest {
+            candidate_address: address,
+            candidate_term: term,
+            last_log_index,
+            last_log_term: log.get_term_at(last_log_index),
+        };
+        Ok(self
+            .peer_metadata
+            .next_indexes_by_peer
+            .iter()
+            .map(|entry| {
+                (
+                    entry.key().clone(),
+                    RpcRequest::RequestVote(request.clone()),
+                )
+            })
+            .collect())
+    }
+
+    /// (ALL NODES)
+    /// Handle a `RequestVoteRequest` from a candidate (§5.2, §5.4), adopting its term if it is
+    /// higher than ours, and granting our vote if:
+    ///
+    /// 1. the candidate's term is no lower than ours
+    /// 2. we have not already voted for a different candidate in this term
+    /// 3. the candidate's log is at least as up-to-date as ours (ie: its last entry has a higher
+    ///    term than ours, or the same term and an index at least as high)
+    pub async fn handle_request_vote_request(
+        &self,
+        request: RequestVoteRequest,
+    ) -> RequestVoteResponse {
+        let log = self.log.lock().await;
+        let mut node = self.node_metadata.lock().await;
+
+        if request.candidate_term > node.current_term() {
+            let _ = node.step_down(request.candidate_term).await;
+        }
+        let denial = RequestVoteResponse {
+            peer_term: node.current_term(),
+            vote_granted: false,
+        };
+
+        // (also catches failure to persist the candidate's higher term)
+        if request.candidate_term != node.current_term() {
+            return denial;
+        }
+        let has_other_vote = node
+            .persisted
+            .voted_for
+            .as_ref()
+            .is_some_and(|candidate| candidate != &request.candidate_address);
+        if has_other_vote {
+            return denial;
+        }
+        let last_log_index = log.get_last_index();
+        let is_up_to_date = (request.last_log_term, request.last_log_index)
+            >= (log.get_term_at(last_log_index), last_log_index);
+        if !is_up_to_date {
+            return denial;
+        }
+        if node
+            .persisted
+            .update_voted_for(Some(request.candidate_address))
+            .await
+            .is_err()
+        {
+            return denial;
+        }
+
+        self.reset_election_timer();
+        RequestVoteResponse {
+            peer_term: node.current_term(),
+            vote_granted: true,
+        }
+    }
+
+    /// (CANDIDATES ONLY)
+    /// Tally a vote from a peer, stepping down if it reports a higher term, and becoming leader
+    /// once a majority of the cluster (counting ourselves) has voted for us in the current term.
+    /// Return `true` iff this vote won us the election.
+    pub async fn handle_request_vote_response(
+        &self,
+        peer_address: NodeAddr,
+        req: RequestVoteRequest,
+        resp: RequestVoteResponse,
+    ) -> Result<bool> {
+        let log = self.log.lock().await;
+        let mut node = self.node_metadata.lock().await;
+
+        if resp.peer_term > node.current_term() {
+            node.step_down(resp.peer_term).await?;
+            return Ok(false);
+        }
+        if !resp.vote_granted
+            || node.role != Role::Candidate
+            || req.candidate_term != node.current_term()
+        {
+            return Ok(false);
+        }
+
+        node.votes.insert(peer_address);
+        if !self.has_majority_of_votes(&node) {
+            return Ok(false);
+        }
+        let mut leader = self.leader_metadata.lock().await;
+        self.become_leader(&log, &mut node, &mut leader);
+        Ok(true)
+    }
+
+    fn has_majority_of_votes(&self, node: &NodeMetadata) -> bool {
+        let num_nodes = self.peer_metadata.next_indexes_by_peer.len() + 1;
+        node.votes.len() > num_nodes / 2
+    }
+
+    /// Take over leadership of the current term, assuming (until followers tell us otherwise) that
+    /// every follower's log matches ours (§5.3)
+    fn become_leader(&self, log: &Log, node: &mut NodeMetadata, leader: &mut LeaderMetadata) {
+        node.role = Role::Leader;
+        node.votes.clear();
+        self.advance_startup_phase(StartupPhase::Serving);
+        leader.address = node.address.clone();
+        leader.api_address = Some(node.api_address.clone());
+        leader.term = Some(node.current_term());
+        for mut entry in self.peer_metadata.next_indexes_by_peer.iter_mut() {
+            *entry.value_mut() = log.get_last_index() + 1;
+        }
+        for mut entry in self.peer_metadata.match_indexes_by_peer.iter_mut() {
+            *entry.value_mut() = 0;
+        }
     }
 
     /// Retrieve the socket address of the current leader (as updated in `handle_append_entry_request`)
@@ -147,12 +482,85 @@ impl State {
         leader.address.clone()
     }
 
+    /// Retrieve the address of the current leader's api server, if it has told us (in
+    /// `handle_append_entry_request`) what it is
+    pub async fn get_leader_api_address(&self) -> Option<NodeAddr> {
+        let leader = self.leader_metadata.lock().await;
+        leader.api_address.clone()
+    }
+
+    /// Retrieve the index up to which the leader has told us every node has stored its log
+    pub async fn get_leader_compactable_index(&self) -> usize {
+        let leader = self.leader_metadata.lock().await;
+        leader.compactable_index
+    }
+
+    /// Retrieve the (serialized) addresses of all of our peers
+    pub fn get_peer_addresses(&self) -> Vec<NodeAddr> {
+        self.peer_metadata
+            .next_indexes_by_peer
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Start replicating to (and counting toward quorum) a peer at `peer_address`, assuming (until
+    /// it tells us otherwise) that its log matches ours. A no-op if we already track the peer.
+    pub async fn add_peer(&self, peer_address: NodeAddr) {
+        let log = self.log.lock().await;
+        let peers = &self.peer_metadata;
+        if !peers.next_indexes_by_peer.contains_key(&peer_address) {
+            peers.match_indexes_by_peer.insert(peer_address.clone(), 0);
+            peers
+                .next_indexes_by_peer
+                .insert(peer_address, log.get_last_index() + 1);
+        }
+    }
+
+    /// Stop replicating to (and counting toward quorum) the peer at `peer_address`.
+    pub fn remove_peer(&self, peer_address: &str) {
+        self.peer_metadata.next_indexes_by_peer.remove(peer_address);
+        self.peer_metadata
+            .match_indexes_by_peer
+            .remove(peer_address);
+    }
+
+    /// Register a callback to be sent the value digests each peer reports for the audit with id
+    /// `audit_id` (as issued by `Node::verify_replicas`).
+    pub fn register_on_digest_handler(&self, audit_id: u64, handler: DigestHandler) {
+        self.on_digest_callbacks.insert(audit_id, handler);
+    }
+
+    /// Forward the value `digests` reported by `peer_address` to the audit with id `audit_id` (if
+    /// it is still awaiting them).
+    pub fn notify_digests(&self, audit_id: u64, peer_address: NodeAddr, digests: Vec<Option<u64>>) {
+        if let Some(handler) = self.on_digest_callbacks.get(&audit_id) {
+            let _ = handler.send((peer_address, digests));
+        }
+    }
+
     /// Register a callback to be notified when a `LogEntry` with index `log_index` has been
     /// applied to the `StateMachine`.
     pub fn register_on_apply_handler(&self, log_index: usize, handler: OneShotSender<()>) {
         self.on_apply_callbacks.insert(log_index, handler);
     }
 
+    /// (LEADERS ONLY)
+    /// Register a callback to be notified once the `LogEntry` with index `log_index` is stored on at
+    /// least `min_replicas` nodes (counting the leader itself), as reported by followers'
+    /// `AppendEntriesResponse`s.
+    pub fn register_on_replicated_handler(
+        &self,
+        log_index: usize,
+        min_replicas: usize,
+        handler: OneShotSender<()>,
+    ) {
+        self.on_replicated_callbacks
+            .insert(log_index, (min_replicas, handler));
+        // in case followers acknowledged the entry before we registered interest in it
+        self.notify_replicated();
+    }
+
     /// (LEADERS ONLY)
     /// Generate Rpc calls needed to replicate unsynced log entries to all followers, as follows:
     /// For every follower, determine which log entries have been appended to the leader's log but
@@ -161,8 +569,9 @@ impl State {
     /// struct in a tuple with the follower's `PeerAddr` to allow the caller to route requests to
     /// the appropriate follower.
     pub async fn gen_append_entry_requests(&self) -> Vec<(NodeAddr, RpcRequest)> {
-        let node = self.node_metadata.lock().await;
+        // take locks in same order as all other methods (log before node) to avoid deadlock
         let log = self.log.lock().await;
+        let node = self.node_metadata.lock().await;
         let last_leader_index = log.get_last_index();
 
         self.peer_metadata
@@ -170,10 +579,14 @@ impl State {
             .iter()
             .map(|key_value| {
                 let (peer_address, &next_peer_index) = key_value.pair();
+                // we only compact entries every peer has stored, so never need to send them again
+                let next_peer_index = max(next_peer_index, log.first_index + 1);
                 let request = AppendEntriesRequest {
-                    entries: log.entries[next_peer_index..=last_leader_index].to_vec(),
+                    entries: log.get_range(next_peer_index, last_leader_index).to_vec(),
                     leader_address: node.address.clone(),
+                    leader_api_address: Some(node.api_address.clone()),
                     leader_commit: node.last_commit,
+                    leader_compactable_index: min(node.last_commit, self.get_min_match_index()),
                     leader_term: node.persisted.current_term,
                     prev_log_index: next_peer_index - 1,
                     prev_log_term: log.get_term_at(next_peer_index - 1),
@@ -190,7 +603,11 @@ impl State {
     ///
     /// Return early without appending anything if:
     ///
-    /// 1. follower's current term  < leader's current term (§5.1)
+    /// 0. a different leader has already been seen in our current term, and the request claims that
+    ///    same term (which Raft should make impossible, so we raise a split-brain alarm and refuse
+    ///    the second claimant's entries)
+    /// 1. leader's current term < follower's current term (§5.1) -- if the leader's term is higher,
+    ///    we adopt it and revert to follower (as do candidates that lose an election to this leader)
     /// 2. follower's log doesn’t contain an entry at the index just before the entries-to-be-appended
     ///    with a term matching that of of the leader's entry at that index
     /// 3. follower has an existing entry at any index that conflicts with an entry-to-be-appended --
@@ -219,9 +636,30 @@ impl State {
             success: false,
         };
 
+        if request.leader_term == node.current_term()
+            && leader.term == Some(node.current_term())
+            && request.leader_address != leader.address
+        {
+            eprintln!(
+                "> SPLIT BRAIN: {} and {} both claim leadership of term {}",
+                leader.address, request.leader_address, request.leader_term
+            );
+            self.num_split_brain_alarms.fetch_add(1, Ordering::SeqCst);
+            return failure_response;
+        }
         if request.leader_term < node.current_term() {
             return failure_response;
         }
+        if request.leader_term > node.current_term() {
+            if node.step_down(request.leader_term).await.is_err() {
+                return failure_response;
+            }
+        } else if node.role == Role::Candidate {
+            // another candidate won the election for our term
+            node.role = Role::Follower;
+        }
+        self.reset_election_timer();
+
         if !log.has_matching(request.prev_log_index, request.prev_log_term) {
             return failure_response;
         }
@@ -230,7 +668,7 @@ impl State {
             let _ = log.remove_until(conflict_idx - 1).await;
             return failure_response;
         }
-        if let Err(_) = log.append_many(&request.entries).await {
+        if log.append_many(&request.entries).await.is_err() {
             // TODO: do we need more checks for "not already in the log"?
             return failure_response;
         }
@@ -243,15 +681,23 @@ impl State {
             );
             node.last_commit = last_commit;
             // apply all log entries up to this index
-            Self::apply_all_until(last_commit, &mut machine, &mut node, &mut log, callbacks).await;
+            Self::apply_all_until(last_commit, &mut machine, &mut node, &log, callbacks).await;
         }
 
         if request.leader_address != leader.address {
             leader.address = request.leader_address;
         }
+        if request.leader_api_address.is_some() {
+            leader.api_address = request.leader_api_address;
+        }
+        leader.term = Some(request.leader_term);
+        leader.compactable_index = request.leader_compactable_index;
+        if node.last_applied >= request.leader_commit {
+            self.advance_startup_phase(StartupPhase::Serving);
+        }
 
         AppendEntriesResponse {
-            peer_term: node.persisted.current_term.clone(),
+            peer_term: node.persisted.current_term,
             success: true,
         }
     }
@@ -259,8 +705,9 @@ impl State {
     /// TODO: clean up this doc string!
     /// If successful: update nextIndex and matchIndex for follower (§5.3)
     /// - If AppendEntries fails because of log inconsistency: decrement nextIndex and retry (§5.3)
-    /// • If there exists an N such that N > commitIndex, a majority of matchIndex\[i\] ≥ N,
-    /// and log\[N\].term == currentTerm: set commitIndex = N (§5.3, §5.4).
+    /// - If there exists an N such that N > commitIndex, a majority of matchIndex\[i\] ≥ N,
+    ///   and log\[N\].term == currentTerm: set commitIndex = N (§5.3, §5.4).
+    ///
     /// then check conditions for updating state machine, namely:
     ///  If commitIndex > lastApplied: increment lastApplied, apply log\[lastApplied\] to state machine (§5.3
     pub async fn handle_append_entry_response(
@@ -272,10 +719,28 @@ impl State {
         let next_indexes = &self.peer_metadata.next_indexes_by_peer;
         let match_indexes = &self.peer_metadata.match_indexes_by_peer;
 
+        /*** STALE LEADER ***/
+        if resp.peer_term > req.leader_term {
+            let mut node = self.node_metadata.lock().await;
+            if resp.peer_term > node.current_term() {
+                node.step_down(resp.peer_term).await?;
+            }
+            return Ok(());
+        }
+
+        /*** STALE RESPONSE ***/
+        // (to a request sent in an earlier term, or before we stepped down, which tells us
+        // nothing about how the peer's log compares to the one we now lead with)
+        if !self.is_leader_in_term(req.leader_term).await {
+            return Ok(());
+        }
+
         /*** SAD PATH ***/
         if !resp.success {
             // TODO: don't unwrap here...
-            let new_next_index = *next_indexes.get(&peer_address).unwrap().value() - 1;
+            // (never back up past index 1, since every request must reference a previous entry)
+            let next_index = *next_indexes.get(&peer_address).unwrap().value();
+            let new_next_index = max(1, next_index.saturating_sub(1));
             let _ = next_indexes.insert(peer_address, new_next_index);
             return Err(RetryAppendEntry(new_next_index).boxed());
         }
@@ -287,20 +752,31 @@ impl State {
         let mut machine = self.state_machine.lock().await;
         let mut node = self.node_metadata.lock().await;
         let callbacks = self.on_apply_callbacks.clone();
+        // (in case we stepped down while waiting for the locks)
+        if node.role != Role::Leader || node.current_term() != req.leader_term {
+            return Ok(());
+        }
 
-        // update metadata_for_test_node to reflect commits made on followers
-        let new_match_index = req.prev_log_index + req.entries.len();
+        // update metadata_for_test_node to reflect commits made on followers (never lowering a
+        // match index, in case responses to earlier requests arrive after those to later ones)
+        let new_match_index = max(
+            req.prev_log_index + req.entries.len(),
+            match_indexes
+                .get(&peer_address)
+                .map_or(0, |entry| *entry.value()),
+        );
         let _ = match_indexes.insert(peer_address.clone(), new_match_index);
         let new_next_index = new_match_index + 1;
         let _ = next_indexes.insert(peer_address, new_next_index);
+        self.notify_replicated();
 
         // if there is a new index up to which all followers have committed, apply all log entries up to that index
         let curr_match_indexes = match_indexes
             .iter()
-            .map(|entry| entry.value().clone())
+            .map(|entry| *entry.value())
             .collect::<Vec<_>>();
         if let Some(new_consensus_idx) =
-            Self::find_new_consensus_idx(curr_match_indexes, &mut node, &log).await
+            Self::find_new_consensus_idx(curr_match_indexes, &node, &log).await
         {
             // println!("> Found new consensus idx: {:?}", new_consensus_idx);
             node.last_commit = new_consensus_idx;
@@ -311,6 +787,37 @@ impl State {
         Ok(())
     }
 
+    /// Whether we are (still) leader for `term`
+    async fn is_leader_in_term(&self, term: usize) -> bool {
+        let node = self.node_metadata.lock().await;
+        node.role == Role::Leader && node.current_term() == term
+    }
+
+    /// (LEADERS ONLY)
+    /// Trigger (and deregister) all callbacks registered in `register_on_replicated_handler` whose
+    /// entries are now stored on as many replicas as they require.
+    fn notify_replicated(&self) {
+        let match_indexes = &self.peer_metadata.match_indexes_by_peer;
+        let replicated_indexes = self
+            .on_replicated_callbacks
+            .iter()
+            .filter(|entry| {
+                let (log_index, (min_replicas, _)) = entry.pair();
+                let num_replicas = 1 + match_indexes
+                    .iter()
+                    .filter(|match_index| *match_index.value() >= *log_index)
+                    .count();
+                num_replicas >= *min_replicas
+            })
+            .map(|entry| *entry.key())
+            .collect::<Vec<usize>>();
+        for log_index in replicated_indexes {
+            if let Some((_, (_, cb))) = self.on_replicated_callbacks.remove(&log_index) {
+                let _ = cb.send(());
+            }
+        }
+    }
+
     /// (ALL NODES)
     /// Apply all log entries up to and including `last_committed`, update node metadata_for_test_node accordingly,
     /// and trigger callbacks registered by `Node::handle_api_requests` (so that node may indicate
@@ -324,7 +831,7 @@ impl State {
     ) {
         // println!("> Applying {:?} to {:?}", node.last_applied, last_committed);
         machine
-            .apply_many(&log.entries[node.last_applied..=last_committed])
+            .apply_many(log.get_range(node.last_applied, last_committed))
             .await;
 
         for idx in node.last_applied..=last_committed {
@@ -341,6 +848,7 @@ impl State {
     ///   1. has not yet been committed on the leader (ie: `index > node.last_commit`)
     ///   2. has already been committed on a majority of followers
     ///   3. belongs to the current term
+    ///
     /// If such an index is found, store it as the new `last_commit` and return it, else return `None`.
     async fn find_new_consensus_idx<'a>(
         match_indexes: Vec<usize>,
@@ -352,17 +860,588 @@ impl State {
         let majority = num_peers / 2 + num_peers % 2;
 
         let first_uncommitted_idx = node.last_commit + 1;
-        let last_entry_idx = log.entries.len() - 1;
+        let last_entry_idx = log.get_last_index();
         for candidate_idx in (first_uncommitted_idx..=last_entry_idx).rev() {
             let num_matches = match_indexes
                 .iter()
                 .filter(|&&match_idx| match_idx >= candidate_idx)
                 .count();
-            if num_matches >= majority && log.entries[candidate_idx].term == current_term {
+            if num_matches >= majority && log.get_term_at(candidate_idx) == current_term {
                 return Some(candidate_idx);
             }
         }
 
-        return None;
+        None
+    }
+}
+
+#[cfg(test)]
+mod test_state {
+    use futures::future;
+    use tokio::time::{timeout, Duration};
+
+    use crate::test_support::gen::Gen;
+
+    use super::*;
+
+    /// Load a `State` with a single peer from fresh files, returning it with the paths of those files.
+    async fn setup_state(peer_address: NodeAddr) -> (Arc<State>, String, String) {
+        let log_path = format!("test_data/log_{}", Gen::usize());
+        let metadata_path = format!("test_data/metadata_{}", Gen::usize());
+        tokio::fs::create_dir_all(&metadata_path).await.unwrap();
+        let state = StateConfig {
+            role: Role::Follower,
+            leader_address: Gen::socket_addr().to_string(),
+            node_address: Gen::socket_addr().to_string(),
+            api_address: Gen::socket_addr().to_string(),
+            peer_addresses: vec![peer_address],
+            log_path: log_path.clone(),
+            metadata_path: metadata_path.clone(),
+        }
+        .run()
+        .await
+        .unwrap();
+        (Arc::new(state), log_path, metadata_path)
+    }
+
+    /// Like `setup_state`, but have the node lead the cluster (in term 0) from the start
+    async fn setup_leader_state(peer_address: NodeAddr) -> (Arc<State>, String, String) {
+        let (state, log_path, metadata_path) = setup_state(peer_address).await;
+        {
+            let log = state.log.lock().await;
+            let mut node = state.node_metadata.lock().await;
+            let mut leader = state.leader_metadata.lock().await;
+            state.become_leader(&log, &mut node, &mut leader);
+        }
+        (state, log_path, metadata_path)
+    }
+
+    fn match_index_of(state: &State, peer_address: &str) -> usize {
+        *state
+            .peer_metadata
+            .match_indexes_by_peer
+            .get(peer_address)
+            .unwrap()
+            .value()
+    }
+
+    #[tokio::test]
+    async fn replicates_to_added_peers_but_not_removed_ones() {
+        let (peer_address, new_peer_address) = (
+            Gen::socket_addr().to_string(),
+            Gen::socket_addr().to_string(),
+        );
+        let (state, log_path, metadata_path) = setup_state(peer_address.clone()).await;
+
+        state.add_peer(new_peer_address.clone()).await;
+        state.remove_peer(&peer_address);
+        let recipients: Vec<NodeAddr> = state
+            .gen_append_entry_requests()
+            .await
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect();
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!(recipients, vec![new_peer_address.clone()]);
+        assert_eq!(state.get_peer_addresses(), vec![new_peer_address]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn appends_to_log_while_generating_append_entry_requests() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+
+        // `append_to_log` and `gen_append_entry_requests` both take the log and node locks, so
+        // running them concurrently will deadlock unless they take the locks in the same order
+        let appending = {
+            let state = state.clone();
+            tokio::spawn(async move {
+                for _ in 0..200 {
+                    state.append_to_log(Gen::put_cmd()).await.unwrap();
+                }
+            })
+        };
+        let generating = {
+            let state = state.clone();
+            tokio::spawn(async move {
+                for _ in 0..200 {
+                    state.gen_append_entry_requests().await;
+                }
+            })
+        };
+        let result = timeout(Duration::from_secs(5), future::join(appending, generating)).await;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn never_decrements_next_index_below_one() {
+        let peer_address = Gen::socket_addr().to_string();
+        let (state, log_path, metadata_path) = setup_leader_state(peer_address.clone()).await;
+        let failure = AppendEntriesResponse {
+            peer_term: 0,
+            success: false,
+        };
+
+        for _ in 0..3 {
+            let (_, request) = state.gen_append_entry_requests().await.remove(0);
+            let RpcRequest::AppendEntries(request) = request else {
+                panic!("expected an AppendEntries request");
+            };
+            let _ = state
+                .handle_append_entry_response(peer_address.clone(), request, failure.clone())
+                .await;
+        }
+        let next_index = *state
+            .peer_metadata
+            .next_indexes_by_peer
+            .get(&peer_address)
+            .unwrap()
+            .value();
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!(next_index, 1);
+    }
+
+    #[tokio::test]
+    async fn ignores_append_entry_responses_from_earlier_terms() {
+        let peer_address = Gen::socket_addr().to_string();
+        let (state, log_path, metadata_path) = setup_leader_state(peer_address.clone()).await;
+        state.append_to_log(Gen::put_cmd()).await.unwrap();
+        let (_, request) = state.gen_append_entry_requests().await.remove(0);
+        let RpcRequest::AppendEntries(request) = request else {
+            panic!("expected an AppendEntries request");
+        };
+        let success = AppendEntriesResponse {
+            peer_term: 0,
+            success: true,
+        };
+
+        // we win a later term before hearing back about the request we sent in term 0
+        state
+            .node_metadata
+            .lock()
+            .await
+            .persisted
+            .update_current_term(1)
+            .await
+            .unwrap();
+        let _ = state
+            .handle_append_entry_response(peer_address.clone(), request, success)
+            .await;
+        let match_index = match_index_of(&state, &peer_address);
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!(match_index, 0);
+    }
+
+    #[tokio::test]
+    async fn never_lowers_match_index_on_reordered_responses() {
+        let peer_address = Gen::socket_addr().to_string();
+        let (state, log_path, metadata_path) = setup_leader_state(peer_address.clone()).await;
+        let (_, earlier_request) = state.gen_append_entry_requests().await.remove(0);
+        state.append_to_log(Gen::put_cmd()).await.unwrap();
+        state.append_to_log(Gen::put_cmd()).await.unwrap();
+        let (_, later_request) = state.gen_append_entry_requests().await.remove(0);
+        let (RpcRequest::AppendEntries(earlier_request), RpcRequest::AppendEntries(later_request)) =
+            (earlier_request, later_request)
+        else {
+            panic!("expected AppendEntries requests");
+        };
+        let success = AppendEntriesResponse {
+            peer_term: 0,
+            success: true,
+        };
+
+        let _ = state
+            .handle_append_entry_response(peer_address.clone(), later_request, success.clone())
+            .await;
+        let _ = state
+            .handle_append_entry_response(peer_address.clone(), earlier_request, success)
+            .await;
+        let match_index = match_index_of(&state, &peer_address);
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!(match_index, 2);
+    }
+
+    #[tokio::test]
+    async fn compacts_follower_log_only_as_far_as_leader_allows() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        let entries = Gen::log_entries(3);
+        let _ = state
+            .handle_append_entries_request(AppendEntriesRequest {
+                entries: entries.clone(),
+                leader_address: state.get_leader_address().await,
+                leader_api_address: None,
+                leader_commit: entries.len(),
+                leader_compactable_index: 1,
+                leader_term: 0,
+                prev_log_index: 0,
+                prev_log_term: 0,
+            })
+            .await;
+
+        let compactable_index = state.get_leader_compactable_index().await;
+        let snapshot_index = state.snapshot(compactable_index).await.unwrap();
+        let first_index = state.log.lock().await.first_index;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!(snapshot_index, entries.len());
+        assert_eq!(first_index, 1);
+    }
+
+    #[tokio::test]
+    async fn restores_store_from_snapshot_after_restart() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        let entries = Gen::log_entries(3);
+        let _ = state
+            .handle_append_entries_request(AppendEntriesRequest {
+                entries: entries.clone(),
+                leader_address: state.get_leader_address().await,
+                leader_api_address: None,
+                leader_commit: entries.len(),
+                leader_compactable_index: 0,
+                leader_term: 0,
+                prev_log_index: 0,
+                prev_log_term: 0,
+            })
+            .await;
+        let store_before_restart = state.store.to_map();
+
+        let snapshot_index = state.snapshot(usize::MAX).await.unwrap();
+        let restarted = StateConfig {
+            role: Role::Follower,
+            leader_address: Gen::socket_addr().to_string(),
+            node_address: Gen::socket_addr().to_string(),
+            api_address: Gen::socket_addr().to_string(),
+            peer_addresses: vec![Gen::socket_addr().to_string()],
+            log_path: log_path.clone(),
+            metadata_path: metadata_path.clone(),
+        }
+        .run()
+        .await
+        .unwrap();
+        let log = restarted.log.lock().await;
+        let node = restarted.node_metadata.lock().await;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!(snapshot_index, entries.len());
+        assert_eq!(restarted.store.to_map(), store_before_restart);
+        assert_eq!(node.last_applied, entries.len());
+        assert_eq!(log.first_index, entries.len());
+        assert_eq!(log.get_last_index(), entries.len());
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_and_overflowing_id_allocations() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        state.store.advance_sequence("ids", u64::MAX - 5);
+        let last_index = state.get_last_appended_index().await;
+
+        let empty = state.append_id_allocation("ids", 0).await;
+        let overflowing = state.append_id_allocation("ids", 10).await;
+        let appended_index = state.get_last_appended_index().await;
+        let (_, remaining_start) = state.append_id_allocation("ids", 5).await.unwrap();
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!(
+            empty.err().unwrap().to_string(),
+            EmptyIdAllocation.to_string()
+        );
+        assert_eq!(
+            overflowing.err().unwrap().to_string(),
+            SequenceExhausted("ids".to_string(), 10).to_string()
+        );
+        assert_eq!(appended_index, last_index);
+        assert_eq!(remaining_start, u64::MAX - 5);
+    }
+
+    #[tokio::test]
+    async fn allocates_ids_after_unapplied_allocations() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        state.store.advance_sequence("ids", 5);
+
+        let (_, first_start) = state.append_id_allocation("ids", 10).await.unwrap();
+        let (_, second_start) = state.append_id_allocation("ids", 10).await.unwrap();
+        let (_, other_start) = state.append_id_allocation("other", 10).await.unwrap();
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!((first_start, second_start, other_start), (5, 15, 0));
+    }
+
+    #[tokio::test]
+    async fn raises_alarm_when_two_leaders_claim_the_same_term() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        let leader_address = state.get_leader_address().await;
+        let request_from = |leader_address: NodeAddr| AppendEntriesRequest {
+            entries: vec![],
+            leader_address,
+            leader_api_address: None,
+            leader_commit: 0,
+            leader_compactable_index: 0,
+            leader_term: 0,
+            prev_log_index: 0,
+            prev_log_term: 0,
+        };
+
+        let from_leader = state
+            .handle_append_entries_request(request_from(leader_address.clone()))
+            .await;
+        let from_usurper = state
+            .handle_append_entries_request(request_from(Gen::socket_addr().to_string()))
+            .await;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert!(from_leader.success);
+        assert!(!from_usurper.success);
+        assert_eq!(state.num_split_brain_alarms.load(Ordering::SeqCst), 1);
+        assert_eq!(state.get_leader_address().await, leader_address);
+    }
+
+    #[tokio::test]
+    async fn raises_no_alarm_over_claims_to_terms_we_have_moved_past() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        let leader_address = state.get_leader_address().await;
+        let request_from = |leader_address: NodeAddr| AppendEntriesRequest {
+            entries: vec![],
+            leader_address,
+            leader_api_address: None,
+            leader_commit: 0,
+            leader_compactable_index: 0,
+            leader_term: 0,
+            prev_log_index: 0,
+            prev_log_term: 0,
+        };
+
+        let _ = state
+            .handle_append_entries_request(request_from(leader_address))
+            .await;
+        state.node_metadata.lock().await.step_down(1).await.unwrap();
+        let from_stale_leader = state
+            .handle_append_entries_request(request_from(Gen::socket_addr().to_string()))
+            .await;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert!(!from_stale_leader.success);
+        assert_eq!(state.num_split_brain_alarms.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn accepts_first_leader_seen_in_a_term() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        let request = AppendEntriesRequest {
+            entries: vec![],
+            leader_address: Gen::socket_addr().to_string(),
+            leader_api_address: None,
+            leader_commit: 0,
+            leader_compactable_index: 0,
+            leader_term: 0,
+            prev_log_index: 0,
+            prev_log_term: 0,
+        };
+
+        let response = state.handle_append_entries_request(request.clone()).await;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert!(response.success);
+        assert_eq!(state.num_split_brain_alarms.load(Ordering::SeqCst), 0);
+        assert_eq!(state.get_leader_address().await, request.leader_address);
+    }
+
+    #[tokio::test]
+    async fn remembers_api_address_of_leader() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        let api_address_before = state.get_leader_api_address().await;
+        let leader_api_address = Gen::socket_addr().to_string();
+
+        let response = state
+            .handle_append_entries_request(AppendEntriesRequest {
+                entries: vec![],
+                leader_address: state.get_leader_address().await,
+                leader_api_address: Some(leader_api_address.clone()),
+                leader_commit: 0,
+                leader_compactable_index: 0,
+                leader_term: 0,
+                prev_log_index: 0,
+                prev_log_term: 0,
+            })
+            .await;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert!(response.success);
+        assert_eq!(api_address_before, None);
+        assert_eq!(
+            state.get_leader_api_address().await,
+            Some(leader_api_address)
+        );
+    }
+
+    /// A `RequestVoteRequest` from `candidate_address` for `candidate_term`, with an empty log.
+    fn vote_request(candidate_address: NodeAddr, candidate_term: usize) -> RequestVoteRequest {
+        RequestVoteRequest {
+            candidate_address,
+            candidate_term,
+            last_log_index: 0,
+            last_log_term: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn grants_one_vote_per_term() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        let (candidate, rival) = (
+            Gen::socket_addr().to_string(),
+            Gen::socket_addr().to_string(),
+        );
+
+        let to_candidate = state
+            .handle_request_vote_request(vote_request(candidate.clone(), 1))
+            .await;
+        let to_rival = state
+            .handle_request_vote_request(vote_request(rival.clone(), 1))
+            .await;
+        let to_candidate_again = state
+            .handle_request_vote_request(vote_request(candidate, 1))
+            .await;
+        let to_rival_in_next_term = state
+            .handle_request_vote_request(vote_request(rival, 2))
+            .await;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert!(to_candidate.vote_granted);
+        assert!(!to_rival.vote_granted);
+        assert!(to_candidate_again.vote_granted);
+        assert!(to_rival_in_next_term.vote_granted);
+        assert_eq!(to_rival_in_next_term.peer_term, 2);
+    }
+
+    #[tokio::test]
+    async fn denies_vote_to_candidate_with_stale_term() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        state.begin_election().await.unwrap();
+        state.begin_election().await.unwrap();
+
+        let response = state
+            .handle_request_vote_request(vote_request(Gen::socket_addr().to_string(), 1))
+            .await;
+        let role = state.get_role().await;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!(
+            response,
+            RequestVoteResponse {
+                peer_term: 2,
+                vote_granted: false
+            }
+        );
+        assert_eq!(role, Role::Candidate);
+    }
+
+    #[tokio::test]
+    async fn denies_vote_to_candidate_with_outdated_log() {
+        let (state, log_path, metadata_path) = setup_state(Gen::socket_addr().to_string()).await;
+        state.begin_election().await.unwrap();
+        state.append_to_log(Gen::put_cmd()).await.unwrap();
+
+        // candidate has more entries than us, but its last one is from an older term
+        let response = state
+            .handle_request_vote_request(RequestVoteRequest {
+                candidate_address: Gen::socket_addr().to_string(),
+                candidate_term: 2,
+                last_log_index: 5,
+                last_log_term: 0,
+            })
+            .await;
+        let role = state.get_role().await;
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert_eq!(
+            response,
+            RequestVoteResponse {
+                peer_term: 2,
+                vote_granted: false
+            }
+        );
+        assert_eq!(role, Role::Follower);
+    }
+
+    #[tokio::test]
+    async fn becomes_leader_on_winning_majority_of_votes() {
+        let peer_address = Gen::socket_addr().to_string();
+        let (state, log_path, metadata_path) = setup_state(peer_address.clone()).await;
+
+        let mut requests = state.begin_election().await.unwrap();
+        let role_before_vote = state.get_role().await;
+        let (_, request) = requests.remove(0);
+        let RpcRequest::RequestVote(request) = request else {
+            panic!("expected a RequestVote request");
+        };
+        let has_won = state
+            .handle_request_vote_response(
+                peer_address,
+                request,
+                RequestVoteResponse {
+                    peer_term: 1,
+                    vote_granted: true,
+                },
+            )
+            .await
+            .unwrap();
+        let node_address = state.node_metadata.lock().await.address.clone();
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert!(requests.is_empty());
+        assert_eq!(role_before_vote, Role::Candidate);
+        assert!(has_won);
+        assert_eq!(state.get_role().await, Role::Leader);
+        assert_eq!(state.get_leader_address().await, node_address);
+    }
+
+    #[tokio::test]
+    async fn steps_down_on_hearing_of_higher_term() {
+        let peer_address = Gen::socket_addr().to_string();
+        let (state, log_path, metadata_path) = setup_state(peer_address.clone()).await;
+
+        let (_, request) = state.begin_election().await.unwrap().remove(0);
+        let RpcRequest::RequestVote(request) = request else {
+            panic!("expected a RequestVote request");
+        };
+        let has_won = state
+            .handle_request_vote_response(
+                peer_address,
+                request,
+                RequestVoteResponse {
+                    peer_term: 5,
+                    vote_granted: false,
+                },
+            )
+            .await
+            .unwrap();
+        let term = state.node_metadata.lock().await.current_term();
+
+        tokio::fs::remove_file(log_path).await.unwrap();
+        tokio::fs::remove_dir_all(metadata_path).await.unwrap();
+        assert!(!has_won);
+        assert_eq!(state.get_role().await, Role::Follower);
+        assert_eq!(term, 5);
     }
 }
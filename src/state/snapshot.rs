@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{metadata, rename, File};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ErrorKind};
+
+use crate::error::PersistenceError::SnapshotDeserializationError;
+use crate::error::Result;
+
+const SNAPSHOT_FILEPATH: &str = "/snapshot.json";
+
+/// The contents of the store once every log entry up to and including `last_index` has been applied
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Snapshot {
+    pub last_index: usize,
+    pub last_term: usize,
+    pub data: HashMap<String, String>,
+    #[serde(default)]
+    pub sequences: HashMap<String, u64>,
+    #[serde(default)]
+    pub expirations: HashMap<String, u64>,
+}
+
+impl Snapshot {
+    /// Load the snapshot stored in a given metadata directory, if we have ever taken one.
+    pub async fn load_from(path: &str) -> Result<Option<Snapshot>> {
+        let snapshot_path = path.to_string() + SNAPSHOT_FILEPATH;
+        match metadata(&snapshot_path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            _ => {
+                let mut buf = Vec::<u8>::new();
+                let _ = File::open(&snapshot_path)
+                    .await?
+                    .read_to_end(&mut buf)
+                    .await?;
+                serde_json::from_slice(&buf)
+                    .map(Some)
+                    .map_err(|e| SnapshotDeserializationError(e.to_string()).boxed())
+            }
+        }
+    }
+
+    /// Store the snapshot in a given metadata directory (replacing any previous one), writing it
+    /// to a temporary file first so that a crash part way through leaves the previous one intact.
+    pub async fn save_to(&self, path: &str) -> Result<()> {
+        let snapshot_path = path.to_string() + SNAPSHOT_FILEPATH;
+        let tmp_path = format!("{}.tmp", snapshot_path);
+        let mut file = File::create(&tmp_path).await?;
+        file.write_all(&serde_json::to_vec(self)?).await?;
+        file.sync_all().await?;
+        rename(&tmp_path, &snapshot_path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_snapshot {
+    use crate::test_support::gen::Gen;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn saves_and_loads_a_snapshot() {
+        let path = format!("test_data/metadata_{}", Gen::usize());
+        tokio::fs::create_dir_all(&path).await.unwrap();
+        let snapshot = Snapshot {
+            last_index: 3,
+            last_term: 1,
+            data: vec![("foo".to_string(), "bar".to_string())]
+                .into_iter()
+                .collect(),
+            sequences: vec![("ids".to_string(), 100)].into_iter().collect(),
+            expirations: vec![("foo".to_string(), 1000)].into_iter().collect(),
+        };
+
+        let before_save = Snapshot::load_from(&path).await.unwrap();
+        snapshot.save_to(&path).await.unwrap();
+        let after_save = Snapshot::load_from(&path).await.unwrap();
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(before_save, None);
+        assert_eq!(after_save, Some(snapshot));
+    }
+}
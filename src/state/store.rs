@@ -1,32 +1,217 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use dashmap::DashMap;
+use rand::seq::IteratorRandom;
+use sha2::{Digest, Sha256};
+use tokio::sync::{RwLock, RwLockWriteGuard};
 
 /// Thin wrapper around a concurrent hashmap. Wrap it in an Arc to share
 /// between threads or tasks. (No Mutex needed!)
 pub struct Store {
     pub(crate) db: DashMap<String, String>,
+    // for each id sequence, the next id that has not yet been allocated
+    pub(crate) sequences: DashMap<String, u64>,
+    // for each key set with a time to live, when it expires (in millis since the unix epoch)
+    pub(crate) expirations: DashMap<String, u64>,
+    // held for writing while a batch of writes is applied, and for reading by every get (so that
+    // no get sees only some of a batch applied)
+    batch_lock: RwLock<()>,
+}
+
+/// The current wall clock time in millis since the unix epoch (the unit in which expirations are
+/// recorded)
+pub fn now_in_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis() as u64)
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Store {
     pub fn new() -> Store {
-        Self { db: DashMap::new() }
+        Self {
+            db: DashMap::new(),
+            sequences: DashMap::new(),
+            expirations: DashMap::new(),
+            batch_lock: RwLock::new(()),
+        }
+    }
+
+    /// Keeps gets waiting until the returned guard is dropped (so that writes made while it is
+    /// held are seen all at once)
+    pub async fn lock_for_batch(&self) -> RwLockWriteGuard<'_, ()> {
+        self.batch_lock.write().await
     }
 
-    /// Sets `key` to a `value`, returns `true` if `value` changed, `false` if not
+    /// Sets `key` to a `value` (that never expires), returns `true` if `value` changed, `false` if not
     pub async fn put(&self, key: &str, value: &str) -> bool {
+        self.put_expiring(key, value, None).await
+    }
+
+    /// Sets `key` to a `value` that expires at `expires_at` (in millis since the unix epoch) if
+    /// given, or never if not, replacing any expiration set by an earlier put. Returns `true` if
+    /// `value` changed, `false` if not.
+    pub async fn put_expiring(&self, key: &str, value: &str, expires_at: Option<u64>) -> bool {
+        let was_live = self.expire_if_due(key, now_in_millis());
+        match expires_at {
+            Some(expires_at) => self.expirations.insert(key.to_string(), expires_at),
+            None => self
+                .expirations
+                .remove(key)
+                .map(|(_, expires_at)| expires_at),
+        };
         match self.db.insert(key.to_string(), value.to_string()) {
-            Some(v) => v != value,
+            Some(v) => !was_live || v != value,
             None => true,
         }
     }
 
-    /// Retrieves `Some(value)` for a `key`, `None` if not present
+    /// Removes `key`, returns `true` if it was present (and not yet expired), `false` if not
+    pub async fn delete(&self, key: &str) -> bool {
+        let was_live = self.expire_if_due(key, now_in_millis());
+        self.expirations.remove(key);
+        was_live && self.db.remove(key).is_some()
+    }
+
+    /// Retrieves `Some(value)` for a `key`, `None` if not present (removing it if it has expired)
     pub async fn get(&self, key: &str) -> Option<String> {
+        let _batch_guard = self.batch_lock.read().await;
+        self.expire_if_due(key, now_in_millis());
         self.db.get(&key.to_string()).map(|s| s[..].to_string())
     }
 
+    /// Removes every key that has expired as of `now` (in millis since the unix epoch), returning
+    /// how many were removed. (Reads expire keys lazily, so this only reclaims space held by keys
+    /// no one has read since they expired.)
+    pub fn remove_expired(&self, now: u64) -> usize {
+        let expired: Vec<String> = self
+            .expirations
+            .iter()
+            .filter(|entry| *entry.value() <= now)
+            .map(|entry| entry.key().clone())
+            .collect();
+        expired
+            .iter()
+            .filter(|key| !self.expire_if_due(key, now))
+            .count()
+    }
+
+    /// Removes `key` if it has expired as of `now`, returning `true` if it has not (whether or not
+    /// it is present)
+    fn expire_if_due(&self, key: &str, now: u64) -> bool {
+        match self
+            .expirations
+            .remove_if(key, |_, expires_at| *expires_at <= now)
+        {
+            Some(_) => {
+                self.db.remove(key);
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn is_expired(&self, key: &str, now: u64) -> bool {
+        self.expirations
+            .get(key)
+            .is_some_and(|expires_at| *expires_at <= now)
+    }
+
+    /// Retrieves the next unallocated id in `sequence_name` (0 if none have been allocated)
+    pub fn get_sequence_end(&self, sequence_name: &str) -> u64 {
+        self.sequences.get(sequence_name).map_or(0, |end| *end)
+    }
+
+    /// Records that all ids in `sequence_name` below `end` have been allocated (never moving the
+    /// sequence backwards)
+    pub fn advance_sequence(&self, sequence_name: &str, end: u64) {
+        let mut current_end = self.sequences.entry(sequence_name.to_string()).or_insert(0);
+        *current_end = (*current_end).max(end);
+    }
+
+    /// Picks up to `sample` keys starting with `key_prefix` at random
+    pub fn sample_keys(&self, key_prefix: &str, sample: usize) -> Vec<String> {
+        self.db
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|key| key.starts_with(key_prefix))
+            .choose_multiple(&mut rand::thread_rng(), sample)
+    }
+
+    /// Retrieves a digest of the value at each of `keys` (`None` if not present), so that replicas
+    /// may compare values without shipping them around. (Digests are the first 8 bytes of each
+    /// value's SHA-256 hash, so they are comparable between nodes built with any version of Rust.)
+    pub fn digest(&self, keys: &[String]) -> Vec<Option<u64>> {
+        let now = now_in_millis();
+        keys.iter()
+            .map(|key| {
+                if self.is_expired(key, now) {
+                    return None;
+                }
+                self.db.get(key).map(|value| {
+                    let hash = Sha256::digest(value.as_bytes());
+                    u64::from_be_bytes(hash[..8].try_into().unwrap())
+                })
+            })
+            .collect()
+    }
+
     pub async fn size(&self) -> usize {
         self.db.len()
     }
+
+    /// Copy every id sequence out of the store (for snapshotting)
+    pub fn sequences_to_map(&self) -> HashMap<String, u64> {
+        self.sequences
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    /// Copy the expiration of every key set with a time to live out of the store (for snapshotting)
+    pub fn expirations_to_map(&self) -> HashMap<String, u64> {
+        self.expirations
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    /// Copy every key and value out of the store (for snapshotting)
+    pub fn to_map(&self) -> HashMap<String, String> {
+        self.db
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Replace the contents of the store with those of `map`, its id sequences with those of
+    /// `sequences`, and its expirations with those of `expirations` (as restored from a snapshot)
+    pub fn restore(
+        &self,
+        map: HashMap<String, String>,
+        sequences: HashMap<String, u64>,
+        expirations: HashMap<String, u64>,
+    ) {
+        self.db.clear();
+        for (key, value) in map {
+            self.db.insert(key, value);
+        }
+        self.sequences.clear();
+        for (sequence_name, end) in sequences {
+            self.sequences.insert(sequence_name, end);
+        }
+        self.expirations.clear();
+        for (key, expires_at) in expirations {
+            self.expirations.insert(key, expires_at);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -38,7 +223,7 @@ mod store_tests {
         let store = Store::new();
         let was_modified = store.put("foo", "bar").await;
 
-        assert_eq!(was_modified, true);
+        assert!(was_modified);
         assert_eq!(&store.db.get("foo").unwrap()[..], "bar");
     }
 
@@ -58,6 +243,31 @@ mod store_tests {
         assert_eq!(store.get("not here").await, None);
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delete_an_existing_value() {
+        let store = Store::new();
+        let _ = store.put("foo", "bar").await;
+
+        assert!(store.delete("foo").await);
+        assert_eq!(store.get("foo").await, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delete_a_non_existing_value() {
+        let store = Store::new();
+        assert!(!store.delete("foo").await);
+    }
+
+    #[test]
+    fn advance_a_sequence_only_forwards() {
+        let store = Store::new();
+        assert_eq!(store.get_sequence_end("foo"), 0);
+
+        store.advance_sequence("foo", 10);
+        store.advance_sequence("foo", 5);
+        assert_eq!(store.get_sequence_end("foo"), 10);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn reput_a_value() {
         let store = Store::new();
@@ -68,9 +278,9 @@ mod store_tests {
         let was_modified_2 = store.put("foo", "baz").await;
         let res2 = store.get("foo").await.unwrap();
 
-        assert_eq!(was_modified_1, true);
+        assert!(was_modified_1);
         assert_eq!(res1, "bar".to_string());
-        assert_eq!(was_modified_2, true);
+        assert!(was_modified_2);
         assert_eq!(res2, "baz".to_string());
     }
 
@@ -84,9 +294,106 @@ mod store_tests {
         let was_modified_2 = store.put("foo", "bar").await;
         let res2 = store.get("foo").await.unwrap();
 
-        assert_eq!(was_modified_1, true);
+        assert!(was_modified_1);
         assert_eq!(res1, "bar".to_string());
-        assert_eq!(was_modified_2, false);
+        assert!(!was_modified_2);
         assert_eq!(res2, "bar".to_string());
     }
+
+    #[tokio::test]
+    async fn sample_keys_with_a_prefix() {
+        let store = Store::new();
+        for key in ["foo1", "foo2", "foo3", "bar1"] {
+            store.put(key, "baz").await;
+        }
+
+        let mut sampled = store.sample_keys("foo", 2);
+        let mut all_sampled = store.sample_keys("foo", 10);
+        sampled.sort();
+        all_sampled.sort();
+
+        assert_eq!(sampled.len(), 2);
+        assert!(sampled.iter().all(|key| key.starts_with("foo")));
+        assert_eq!(all_sampled, vec!["foo1", "foo2", "foo3"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn expire_a_value_lazily_on_read() {
+        let store = Store::new();
+        let now = now_in_millis();
+        store.put_expiring("foo", "bar", Some(now + 60_000)).await;
+        store.put_expiring("baz", "qux", Some(now - 1)).await;
+
+        assert_eq!(store.get("foo").await, Some("bar".to_string()));
+        assert_eq!(store.get("baz").await, None);
+        assert!(!store.db.contains_key("baz"));
+        assert!(!store.delete("baz").await);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reput_a_value_without_a_ttl_to_clear_its_expiration() {
+        let store = Store::new();
+        store
+            .put_expiring("foo", "bar", Some(now_in_millis() - 1))
+            .await;
+        let was_modified = store.put("foo", "bar").await;
+
+        assert!(was_modified);
+        assert_eq!(store.get("foo").await, Some("bar".to_string()));
+        assert!(store.expirations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn remove_expired_values() {
+        let store = Store::new();
+        store.put_expiring("foo", "bar", Some(10)).await;
+        store.put_expiring("baz", "qux", Some(u64::MAX)).await;
+        store.put("bam", "quux").await;
+
+        assert_eq!(store.remove_expired(now_in_millis()), 1);
+        assert_eq!(store.size().await, 2);
+        assert_eq!(store.expirations_to_map().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn digest_values() {
+        let (store, other_store) = (Store::new(), Store::new());
+        store.put("foo", "bar").await;
+        other_store.put("foo", "bar").await;
+        store.put("baz", "qux").await;
+        other_store.put("baz", "quux").await;
+        let keys = ["foo".to_string(), "baz".to_string(), "bam".to_string()];
+
+        let (digests, other_digests) = (store.digest(&keys), other_store.digest(&keys));
+
+        assert_eq!(digests[0], other_digests[0]);
+        assert_ne!(digests[1], other_digests[1]);
+        assert_eq!(digests[2], None);
+    }
+
+    #[tokio::test]
+    async fn digests_values_the_same_way_on_every_build() {
+        let store = Store::new();
+        store.put("foo", "bar").await;
+        // first 8 bytes of the SHA-256 hash of "bar"
+        assert_eq!(
+            store.digest(&["foo".to_string()]),
+            vec![Some(0xfcde2b2edba56bf4)]
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_to_get_a_value_until_batch_is_applied() {
+        let store = Store::new();
+        store.put("foo", "bar").await;
+
+        let batch_guard = store.lock_for_batch().await;
+        store.put("foo", "baz").await;
+        let during_batch =
+            tokio::time::timeout(tokio::time::Duration::from_millis(10), store.get("foo")).await;
+        drop(batch_guard);
+
+        assert!(during_batch.is_err());
+        assert_eq!(store.get("foo").await, Some("baz".to_string()));
+    }
 }
@@ -1,97 +1,541 @@
-use std::convert::{TryFrom, TryInto};
-use std::fmt::Display;
 use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-use tokio::net::TcpStream;
+use rand::Rng;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter,
+    ErrorKind,
+};
 use tokio::sync::Mutex;
+use tokio::time::{self, Duration, Instant};
 
-use crate::error::NetworkError::{ConnectionClosed, MessageDeserializationError};
-use crate::error::Result;
-use crate::NEWLINE;
+use crate::error::ConfigError::InvalidReconnectJitter;
+use crate::error::NetworkError::{
+    ConnectionClosed, ConnectionReset, FrameTooLarge, MessageDeserializationError, PartialFrame,
+};
+use crate::error::{AsyncError, Result};
+use crate::tls::{self, TlsConnector};
+use crate::transport::Transport;
+use crate::{NodeAddr, NEWLINE};
+use std::result::Result as StdResult;
+
+#[cfg(not(test))]
+const RECONNECT_INITIAL_BACKOFF_IN_MILLIS: u64 = 100;
+#[cfg(test)]
+const RECONNECT_INITIAL_BACKOFF_IN_MILLIS: u64 = 5;
+#[cfg(not(test))]
+const RECONNECT_MAX_BACKOFF_IN_MILLIS: u64 = 5000;
+#[cfg(test)]
+const RECONNECT_MAX_BACKOFF_IN_MILLIS: u64 = 20;
+const RECONNECT_JITTER: f64 = 0.25;
+const RECONNECT_MAX_ATTEMPTS: u32 = 10;
+const LENGTH_PREFIX_SIZE_IN_BYTES: usize = 4;
+// refuse to buffer frames larger than this (so a corrupt length prefix cannot exhaust memory)
+pub const MAX_FRAME_SIZE_IN_BYTES: usize = 64 * 1024 * 1024;
+// first byte a client sends to announce it will use a length-prefixed codec (clients using the
+// legacy newline-delimited codec announce nothing, so their first byte is the `{` opening a frame)
+const LENGTH_PREFIXED_JSON_PREAMBLE: u8 = 0x01;
+const LENGTH_PREFIXED_MESSAGE_PACK_PREAMBLE: u8 = 0x02;
 
 pub struct Connection<InputFrame, OutputFrame>
 where
-    InputFrame: TryFrom<Vec<u8>>,
-    OutputFrame: Into<Vec<u8>>,
+    InputFrame: Frame,
+    OutputFrame: Frame,
 {
-    pub input: Mutex<BufReader<OwnedReadHalf>>,
-    pub output: Mutex<BufWriter<OwnedWriteHalf>>,
+    pub input: Mutex<BufReader<ReadHalf>>,
+    pub output: Mutex<BufWriter<WriteHalf>>,
     pub input_frame: PhantomData<InputFrame>,
     pub output_frame: PhantomData<OutputFrame>,
+    framing: std::sync::Mutex<Framing>,
+    counters: ConnectionCounters,
+}
+
+/// A byte stream that may back a `Connection` (eg: a `TcpStream`, or a TLS stream wrapping one)
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> Stream for S {}
+pub type BoxedStream = Box<dyn Stream>;
+
+type ReadHalf = Box<dyn AsyncRead + Send + Unpin>;
+type WriteHalf = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A message that may be sent over a `Connection` (see `tcp_serializable!`)
+pub trait Frame: Sized {
+    fn encode(self, format: WireFormat) -> Vec<u8>;
+    fn decode(bytes: &[u8], format: WireFormat) -> StdResult<Self, String>;
+}
+
+/// How a `Connection` marks where one frame ends and the next begins, and how it serializes frames
+/// (both sides of a connection must agree on this, which servers ensure by following the lead of
+/// whichever client connects to them -- see `Connection::negotiated`)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FrameCodec {
+    /// each JSON frame is followed by a newline (which serialized frames must therefore never contain)
+    #[default]
+    NewlineDelimited,
+    /// each frame is preceded by its length in bytes, as a big-endian `u32`
+    LengthPrefixed(WireFormat),
+}
+
+/// How frames are serialized
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    /// more compact than JSON, but may contain any byte (so must be length-prefixed)
+    MessagePack,
+}
+
+/// The codec a `Connection` uses, and whether it has yet agreed on it with the other side
+#[derive(Clone, Copy)]
+struct Framing {
+    codec: FrameCodec,
+    handshake: Handshake,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Handshake {
+    Announce, // announce our codec before writing our first frame
+    Await,    // learn the other side's codec from the first bytes it sends
+    Done,
+}
+
+/// Running totals of traffic over a `Connection`, updated on every read and write
+struct ConnectionCounters {
+    opened_at: Instant,
+    frames_read: AtomicU64,
+    frames_written: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    decode_errors: AtomicU64,
+    last_activity_millis: AtomicU64, // since `opened_at`
+}
+
+/// Snapshot of a `Connection`'s counters, as returned by `Connection::stats`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionStats {
+    pub frames_read: u64,
+    pub frames_written: u64,
+    pub bytes_read: u64,    // including the newline delimiting each frame
+    pub bytes_written: u64, // same
+    pub decode_errors: u64, // frames read in full that failed to deserialize
+    pub last_activity: Instant,
+}
+
+/// How a client re-establishes a connection the other side dropped: wait `initial_backoff_in_millis`
+/// before the first attempt, doubling the wait after each failure up to `max_backoff_in_millis`, and
+/// stretching every wait by a random fraction of up to `jitter` (so that many clients dropped at
+/// once do not all retry at once). Give up after `max_attempts` (never try if it is `Some(0)`, and
+/// never give up if it is `None`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectConfig {
+    pub initial_backoff_in_millis: u64,
+    pub max_backoff_in_millis: u64,
+    pub jitter: f64, // from 0 to 1
+    pub max_attempts: Option<u32>,
+}
+
+/// Changes in the state of a client's connection to the server or peer at some address
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionEvent {
+    Disconnected(NodeAddr),
+    Reconnected(NodeAddr),
+    /// we ran out of reconnect attempts, and will not try again
+    Abandoned(NodeAddr),
 }
 
 #[macro_export]
 macro_rules! tcp_serializable {
     ($struct_name:ident) => {
+        impl $crate::tcp::Frame for $struct_name {
+            fn encode(self, format: $crate::tcp::WireFormat) -> Vec<u8> {
+                match format {
+                    $crate::tcp::WireFormat::Json => ::serde_json::to_vec(&self).unwrap(),
+                    $crate::tcp::WireFormat::MessagePack => {
+                        ::rmp_serde::to_vec_named(&self).unwrap()
+                    }
+                }
+            }
+            fn decode(bytes: &[u8], format: $crate::tcp::WireFormat) -> StdResult<Self, String> {
+                match format {
+                    $crate::tcp::WireFormat::Json => {
+                        ::serde_json::from_slice(bytes).map_err(|e| e.to_string())
+                    }
+                    $crate::tcp::WireFormat::MessagePack => {
+                        ::rmp_serde::from_slice(bytes).map_err(|e| e.to_string())
+                    }
+                }
+            }
+        }
         impl TryFrom<Vec<u8>> for $struct_name {
             type Error = serde_json::Error;
             fn try_from(bytes: Vec<u8>) -> StdResult<$struct_name, Self::Error> {
                 serde_json::from_slice(&bytes)
             }
         }
-        impl Into<Vec<u8>> for $struct_name {
-            fn into(self) -> Vec<u8> {
-                serde_json::to_vec(&self).unwrap()
+        impl From<$struct_name> for Vec<u8> {
+            fn from(frame: $struct_name) -> Vec<u8> {
+                serde_json::to_vec(&frame).unwrap()
             }
         }
     };
 }
 
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_in_millis: RECONNECT_INITIAL_BACKOFF_IN_MILLIS,
+            max_backoff_in_millis: RECONNECT_MAX_BACKOFF_IN_MILLIS,
+            jitter: RECONNECT_JITTER,
+            max_attempts: Some(RECONNECT_MAX_ATTEMPTS),
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Like the default, but never give up (as nodes should not on their peers, without whom they
+    /// cannot make progress).
+    pub fn unlimited() -> Self {
+        Self {
+            max_attempts: None,
+            ..ReconnectConfig::default()
+        }
+    }
+
+    /// Check that `jitter` is a fraction we can stretch backoffs by.
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(InvalidReconnectJitter(self.jitter).boxed());
+        }
+        Ok(())
+    }
+
+    /// How long to wait before the (zero-indexed) `attempt`th reconnect attempt, before jitter.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let millis = self
+            .initial_backoff_in_millis
+            .saturating_mul(2u64.saturating_pow(attempt))
+            .min(self.max_backoff_in_millis);
+        Duration::from_millis(millis)
+    }
+
+    /// Try to open a new stream to `address` over `transport` (and TLS, if given a `connector`),
+    /// backing off before each attempt, and return `None` once `max_attempts` have failed.
+    pub async fn reconnect(
+        &self,
+        address: SocketAddr,
+        transport: &dyn Transport,
+        connector: Option<&TlsConnector>,
+    ) -> Option<BoxedStream> {
+        let mut attempt: u32 = 0;
+        while self
+            .max_attempts
+            .is_none_or(|max_attempts| attempt < max_attempts)
+        {
+            let jitter = rand::thread_rng().gen::<f64>() * self.jitter;
+            time::sleep(self.backoff(attempt).mul_f64(1.0 + jitter)).await;
+            if let Ok(socket) = transport.connect(address).await {
+                if let Ok(stream) = tls::connect(socket, address, connector).await {
+                    return Some(stream);
+                }
+            }
+            attempt = attempt.saturating_add(1);
+        }
+        None
+    }
+}
+
+impl ConnectionCounters {
+    fn new() -> ConnectionCounters {
+        Self {
+            opened_at: Instant::now(),
+            frames_read: AtomicU64::new(0),
+            frames_written: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            decode_errors: AtomicU64::new(0),
+            last_activity_millis: AtomicU64::new(0),
+        }
+    }
+
+    fn record_activity(&self) {
+        let millis = self.opened_at.elapsed().as_millis() as u64;
+        self.last_activity_millis.store(millis, Ordering::SeqCst);
+    }
+}
+
+impl FrameCodec {
+    /// The byte a client sends to announce it is using this codec (if any)
+    fn preamble(&self) -> Option<u8> {
+        match self {
+            FrameCodec::NewlineDelimited => None,
+            FrameCodec::LengthPrefixed(WireFormat::Json) => Some(LENGTH_PREFIXED_JSON_PREAMBLE),
+            FrameCodec::LengthPrefixed(WireFormat::MessagePack) => {
+                Some(LENGTH_PREFIXED_MESSAGE_PACK_PREAMBLE)
+            }
+        }
+    }
+
+    /// The codec announced by a client whose first byte is `byte` (if it announced one)
+    fn from_preamble(byte: u8) -> Option<FrameCodec> {
+        match byte {
+            LENGTH_PREFIXED_JSON_PREAMBLE => Some(FrameCodec::LengthPrefixed(WireFormat::Json)),
+            LENGTH_PREFIXED_MESSAGE_PACK_PREAMBLE => {
+                Some(FrameCodec::LengthPrefixed(WireFormat::MessagePack))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl<InputFrame, OutputFrame> Connection<InputFrame, OutputFrame>
 where
-    InputFrame: TryFrom<Vec<u8>>,
-    OutputFrame: Into<Vec<u8>>,
+    InputFrame: Frame,
+    OutputFrame: Frame,
 {
-    /// Create a new `Connection` backed by `socket`, with read and write buffers initialized.
-    pub fn new(socket: TcpStream) -> Connection<InputFrame, OutputFrame> {
-        let (r, w) = socket.into_split();
-        let input = Mutex::new(BufReader::new(r));
-        let output = Mutex::new(BufWriter::new(w));
+    /// Create a new `Connection` backed by `socket`, with read and write buffers initialized,
+    /// delimiting JSON frames with newlines.
+    pub fn new(socket: impl Stream + 'static) -> Connection<InputFrame, OutputFrame> {
+        Self::with_codec(socket, FrameCodec::NewlineDelimited)
+    }
+
+    /// Like `new`, but frame messages per `codec`, announcing it to the server before writing the
+    /// first frame (so use this on the client end of a connection).
+    pub fn with_codec(
+        socket: impl Stream + 'static,
+        codec: FrameCodec,
+    ) -> Connection<InputFrame, OutputFrame> {
+        let handshake = match codec.preamble() {
+            Some(_) => Handshake::Announce,
+            None => Handshake::Done,
+        };
+        Self::with_framing(socket, Framing { codec, handshake })
+    }
+
+    /// Like `new`, but frame messages with whichever codec the client announces before its first
+    /// frame (falling back to newline-delimited JSON if it announces none), so use this on the
+    /// server end of a connection.
+    pub fn negotiated(socket: impl Stream + 'static) -> Connection<InputFrame, OutputFrame> {
+        let framing = Framing {
+            codec: FrameCodec::NewlineDelimited,
+            handshake: Handshake::Await,
+        };
+        Self::with_framing(socket, framing)
+    }
+
+    fn with_framing(
+        socket: impl Stream + 'static,
+        framing: Framing,
+    ) -> Connection<InputFrame, OutputFrame> {
+        let (r, w) = tokio::io::split(socket);
+        let input = Mutex::new(BufReader::new(Box::new(r) as ReadHalf));
+        let output = Mutex::new(BufWriter::new(Box::new(w) as WriteHalf));
 
         Self {
             input,
             output,
             input_frame: PhantomData,
             output_frame: PhantomData,
+            framing: std::sync::Mutex::new(framing),
+            counters: ConnectionCounters::new(),
         }
     }
 
-    /// Read an `InputFrame` from the socket
-    pub async fn read(&self) -> Result<InputFrame>
-    where
-        <InputFrame as TryFrom<Vec<u8>>>::Error: Display,
-    {
-        let mut buf = Vec::new();
+    /// Report how much traffic has crossed this connection, and when it last did.
+    pub fn stats(&self) -> ConnectionStats {
+        let counters = &self.counters;
+        ConnectionStats {
+            frames_read: counters.frames_read.load(Ordering::SeqCst),
+            frames_written: counters.frames_written.load(Ordering::SeqCst),
+            bytes_read: counters.bytes_read.load(Ordering::SeqCst),
+            bytes_written: counters.bytes_written.load(Ordering::SeqCst),
+            decode_errors: counters.decode_errors.load(Ordering::SeqCst),
+            last_activity: counters.opened_at
+                + Duration::from_millis(counters.last_activity_millis.load(Ordering::SeqCst)),
+        }
+    }
+
+    /// Read an `InputFrame` from the socket, distinguishing between four ways a read can end
+    /// without yielding a frame:
+    ///
+    /// - `ConnectionClosed`: peer shut down cleanly between frames
+    /// - `ConnectionReset`: peer reset or aborted the connection
+    /// - `PartialFrame`: peer shut down after sending some (but not all) of a frame. We never try
+    ///   to deserialize these bytes, since a truncated frame might still parse as a valid one.
+    /// - `FrameTooLarge`: a length prefix announced a frame over `MAX_FRAME_SIZE_IN_BYTES` (after
+    ///   which we cannot tell where the next frame starts)
+    pub async fn read(&self) -> Result<InputFrame> {
         let mut input = self.input.lock().await;
-        input.read_until(NEWLINE, &mut buf).await?;
+        let codec = self.await_codec(&mut input).await?;
+        let (buf, format) = match codec {
+            FrameCodec::NewlineDelimited => {
+                (self.read_delimited(&mut input).await?, WireFormat::Json)
+            }
+            FrameCodec::LengthPrefixed(format) => {
+                (self.read_length_prefixed(&mut input).await?, format)
+            }
+        };
+        let frame =
+            InputFrame::decode(&buf, format).map_err(|e| MessageDeserializationError(e).boxed());
+        let counter = match frame {
+            Ok(_) => &self.counters.frames_read,
+            Err(_) => &self.counters.decode_errors,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+        frame
+    }
+
+    /// Retrieve the codec we use to read frames, first learning it from the client's preamble
+    /// (if any) if we have not done so yet.
+    async fn await_codec(&self, input: &mut BufReader<ReadHalf>) -> Result<FrameCodec> {
+        let framing = *self.framing.lock().unwrap();
+        if framing.handshake != Handshake::Await {
+            return Ok(framing.codec);
+        }
+
+        let first_byte = match input.fill_buf().await.map_err(Self::read_error)?.first() {
+            Some(&byte) => byte,
+            None => return Err(ConnectionClosed.boxed()),
+        };
+        let codec = match FrameCodec::from_preamble(first_byte) {
+            Some(codec) => {
+                input.consume(1);
+                self.record_bytes_read(1);
+                codec
+            }
+            None => FrameCodec::NewlineDelimited,
+        };
+        *self.framing.lock().unwrap() = Framing {
+            codec,
+            handshake: Handshake::Done,
+        };
+        Ok(codec)
+    }
+
+    async fn read_delimited(&self, input: &mut BufReader<ReadHalf>) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        input
+            .read_until(NEWLINE, &mut buf)
+            .await
+            .map_err(Self::read_error)?;
+        self.record_bytes_read(buf.len());
+        match buf.last() {
+            None => Err(ConnectionClosed.boxed()),
+            Some(&NEWLINE) => {
+                buf.pop();
+                Ok(buf)
+            }
+            Some(_) => Err(PartialFrame(buf.len()).boxed()),
+        }
+    }
+
+    async fn read_length_prefixed(&self, input: &mut BufReader<ReadHalf>) -> Result<Vec<u8>> {
+        let mut prefix = [0u8; LENGTH_PREFIX_SIZE_IN_BYTES];
+        let prefix_len = Self::read_fully(input, &mut prefix).await?;
+        self.record_bytes_read(prefix_len);
+        if prefix_len == 0 {
+            return Err(ConnectionClosed.boxed());
+        }
+        if prefix_len < LENGTH_PREFIX_SIZE_IN_BYTES {
+            return Err(PartialFrame(prefix_len).boxed());
+        }
+
+        let frame_len = u32::from_be_bytes(prefix) as usize;
+        if frame_len > MAX_FRAME_SIZE_IN_BYTES {
+            return Err(FrameTooLarge(frame_len).boxed());
+        }
+        let mut buf = vec![0u8; frame_len];
+        let body_len = Self::read_fully(input, &mut buf).await?;
+        self.record_bytes_read(body_len);
+        if body_len < frame_len {
+            return Err(PartialFrame(prefix_len + body_len).boxed());
+        }
+        Ok(buf)
+    }
 
-        if buf.is_empty() {
-            Err(ConnectionClosed.boxed())
-        } else {
-            buf.try_into()
-                .map_err(|e: <InputFrame as TryFrom<Vec<u8>>>::Error| {
-                    MessageDeserializationError(e.to_string()).boxed()
-                })
+    /// Fill `buf` from `input`, stopping early only if the peer shuts down. Return how many bytes
+    /// were read.
+    async fn read_fully(input: &mut BufReader<ReadHalf>, buf: &mut [u8]) -> Result<usize> {
+        let mut num_read = 0;
+        while num_read < buf.len() {
+            match input
+                .read(&mut buf[num_read..])
+                .await
+                .map_err(Self::read_error)?
+            {
+                0 => break,
+                n => num_read += n,
+            }
+        }
+        Ok(num_read)
+    }
+
+    fn read_error(e: std::io::Error) -> AsyncError {
+        match e.kind() {
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+                ConnectionReset.boxed()
+            }
+            _ => e.into(),
+        }
+    }
+
+    fn record_bytes_read(&self, num_bytes: usize) {
+        if num_bytes > 0 {
+            self.counters
+                .bytes_read
+                .fetch_add(num_bytes as u64, Ordering::SeqCst);
+            self.counters.record_activity();
         }
     }
 
     /// Write an `OutputFrame` to the socket
     pub async fn write(&self, frame: OutputFrame) -> Result<()> {
-        let bytes: Vec<u8> = frame.into();
-
         let mut output = self.output.lock().await;
-        output.write_all(&bytes).await?;
-        output.write(&[NEWLINE]).await?;
+        let framing = *self.framing.lock().unwrap();
+        let mut num_bytes_written = 0;
+        if framing.handshake == Handshake::Announce {
+            if let Some(preamble) = framing.codec.preamble() {
+                output.write_all(&[preamble]).await?;
+                num_bytes_written += 1;
+            }
+            self.framing.lock().unwrap().handshake = Handshake::Done;
+        }
+
+        let format = match framing.codec {
+            FrameCodec::NewlineDelimited => WireFormat::Json,
+            FrameCodec::LengthPrefixed(format) => format,
+        };
+        let bytes = frame.encode(format);
+        num_bytes_written += match framing.codec {
+            FrameCodec::NewlineDelimited => {
+                output.write_all(&bytes).await?;
+                output.write_all(&[NEWLINE]).await?;
+                bytes.len() + 1
+            }
+            FrameCodec::LengthPrefixed(_) => {
+                if bytes.len() > MAX_FRAME_SIZE_IN_BYTES {
+                    return Err(FrameTooLarge(bytes.len()).boxed());
+                }
+                output
+                    .write_all(&(bytes.len() as u32).to_be_bytes())
+                    .await?;
+                output.write_all(&bytes).await?;
+                LENGTH_PREFIX_SIZE_IN_BYTES + bytes.len()
+            }
+        };
         output.flush().await?;
 
+        self.counters.frames_written.fetch_add(1, Ordering::SeqCst);
+        self.counters
+            .bytes_written
+            .fetch_add(num_bytes_written as u64, Ordering::SeqCst);
+        self.counters.record_activity();
         Ok(())
     }
 
     pub async fn close(&self) -> Result<()> {
         let mut output = self.output.lock().await;
-        let _ = output.shutdown().await?;
+        output.shutdown().await?;
         Ok(())
     }
 }
@@ -104,12 +548,23 @@ mod tcp_tests {
     use serde::{Deserialize, Serialize};
     use serde_json;
     use test_context::{test_context, AsyncTestContext};
+    use tokio::io::AsyncWriteExt;
     use tokio::net::{TcpListener, TcpStream};
     use tokio::sync::oneshot;
+    use tokio::time::{self, Duration};
 
-    use crate::error::NetworkError::ConnectionClosed;
-    use crate::tcp::Connection;
+    use crate::error::ConfigError::InvalidReconnectJitter;
+    use crate::error::NetworkError;
+    use crate::error::NetworkError::{
+        ConnectionClosed, ConnectionReset, FrameTooLarge, PartialFrame,
+    };
+    use crate::tcp::{
+        Connection, Frame, FrameCodec, ReconnectConfig, WireFormat, LENGTH_PREFIXED_JSON_PREAMBLE,
+        MAX_FRAME_SIZE_IN_BYTES,
+    };
+    use crate::test_support::fake::{FaultyStream, ReadStep};
     use crate::test_support::gen::Gen;
+    use crate::transport::TcpTransport;
 
     #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
     struct FakeRequest {
@@ -123,30 +578,66 @@ mod tcp_tests {
     }
     tcp_serializable!(FakeResponse);
 
+    /// Frame whose bytes go over the wire as-is (so may contain newlines)
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    struct RawFrame(Vec<u8>);
+    impl Frame for RawFrame {
+        fn encode(self, _: WireFormat) -> Vec<u8> {
+            self.0
+        }
+        fn decode(bytes: &[u8], _: WireFormat) -> StdResult<RawFrame, String> {
+            Ok(RawFrame(bytes.to_vec()))
+        }
+    }
+
     type FakeClientConnection = Connection<FakeResponse, FakeRequest>;
     type FakeServerConnection = Connection<FakeRequest, FakeResponse>;
+    type RawConnection = Connection<RawFrame, RawFrame>;
 
     struct LiveConnections {
         client: FakeClientConnection,
         server: FakeServerConnection,
     }
 
-    #[async_trait::async_trait]
-    impl AsyncTestContext for LiveConnections {
-        async fn setup() -> Self {
-            let address = Gen::socket_addr();
-            let server_address = address.clone();
-            let (server_socket_tx, server_socket_rx) = oneshot::channel::<TcpStream>();
-            let tcp_listener = TcpListener::bind(server_address).await.unwrap();
+    struct LiveLengthPrefixedConnections {
+        client: RawConnection,
+        server: RawConnection,
+    }
+
+    /// Open a socket connection to ourselves, returning its client and server ends.
+    async fn connect() -> (TcpStream, TcpStream) {
+        let address = Gen::socket_addr();
+        let (server_socket_tx, server_socket_rx) = oneshot::channel::<TcpStream>();
+        let tcp_listener = TcpListener::bind(address).await.unwrap();
 
-            tokio::spawn(async move {
-                let (socket, _) = tcp_listener.accept().await.unwrap();
-                let _ = server_socket_tx.send(socket);
-            });
+        tokio::spawn(async move {
+            let (socket, _) = tcp_listener.accept().await.unwrap();
+            let _ = server_socket_tx.send(socket);
+        });
 
-            let client_socket = TcpStream::connect(address).await.unwrap();
-            let server_socket = server_socket_rx.await.unwrap();
+        let client_socket = TcpStream::connect(address).await.unwrap();
+        let server_socket = server_socket_rx.await.unwrap();
+        (client_socket, server_socket)
+    }
 
+    #[async_trait::async_trait]
+    impl AsyncTestContext for LiveLengthPrefixedConnections {
+        async fn setup() -> Self {
+            let (client_socket, server_socket) = connect().await;
+            Self {
+                client: RawConnection::with_codec(
+                    client_socket,
+                    FrameCodec::LengthPrefixed(WireFormat::Json),
+                ),
+                server: RawConnection::negotiated(server_socket),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for LiveConnections {
+        async fn setup() -> Self {
+            let (client_socket, server_socket) = connect().await;
             Self {
                 client: FakeClientConnection::new(client_socket),
                 server: FakeServerConnection::new(server_socket),
@@ -203,4 +694,342 @@ mod tcp_tests {
             Some(&ConnectionClosed)
         );
     }
+
+    #[test_context(LiveConnections)]
+    #[tokio::test]
+    async fn client_closes_connection_mid_frame(ctx: LiveConnections) {
+        // valid json, but missing its delimiter, so we can't know it is a complete frame
+        let partial_frame = br#"{"foo":42}"#;
+        {
+            let mut output = ctx.client.output.lock().await;
+            output.write_all(partial_frame).await.unwrap();
+            output.flush().await.unwrap();
+        }
+        ctx.client.close().await.unwrap();
+        let server_read = ctx.server.read().await;
+
+        assert_eq!(
+            server_read.err().unwrap().downcast_ref(),
+            Some(&PartialFrame(partial_frame.len()))
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_frame_trickled_in_byte_by_byte_then_clean_eof() {
+        let stream = FaultyStream::trickling(br#"{"foo":42}"#, ReadStep::Bytes(vec![b'\n']));
+        let server = FakeServerConnection::new(stream);
+
+        assert_eq!(server.read().await.unwrap(), FakeRequest { foo: 42 });
+        assert_eq!(
+            server.read().await.err().unwrap().downcast_ref(),
+            Some(&ConnectionClosed)
+        );
+    }
+
+    #[tokio::test]
+    async fn never_yields_frame_truncated_by_eof() {
+        // the second frame parses as valid json once truncated, but lacks its delimiter
+        let stream = FaultyStream::trickling(b"{\"foo\":42}\n{\"foo\":4", ReadStep::Eof);
+        let server = FakeServerConnection::new(stream);
+
+        assert_eq!(server.read().await.unwrap(), FakeRequest { foo: 42 });
+        assert_eq!(
+            server.read().await.err().unwrap().downcast_ref(),
+            Some(&PartialFrame(br#"{"foo":4"#.len()))
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_reset_mid_frame() {
+        let stream = FaultyStream::trickling(
+            br#"{"foo":4"#,
+            ReadStep::Fail(std::io::ErrorKind::ConnectionReset),
+        );
+        let server = FakeServerConnection::new(stream);
+
+        let server_read = server.read().await.err().unwrap();
+        assert_eq!(server_read.downcast_ref(), Some(&ConnectionReset));
+        assert!(NetworkError::is_disconnect(&server_read));
+    }
+
+    #[tokio::test]
+    async fn never_yields_length_prefixed_frame_truncated_by_eof() {
+        let mut bytes = vec![LENGTH_PREFIXED_JSON_PREAMBLE];
+        bytes.extend_from_slice(&10u32.to_be_bytes());
+        bytes.extend_from_slice(b"foo");
+        let server = RawConnection::negotiated(FaultyStream::trickling(&bytes, ReadStep::Eof));
+
+        assert_eq!(
+            server.read().await.err().unwrap().downcast_ref(),
+            Some(&PartialFrame(4 + 3))
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_abort_mid_length_prefix_as_reset() {
+        let bytes = [LENGTH_PREFIXED_JSON_PREAMBLE, 0, 0];
+        let stream = FaultyStream::trickling(
+            &bytes,
+            ReadStep::Fail(std::io::ErrorKind::ConnectionAborted),
+        );
+        let server = RawConnection::negotiated(stream);
+
+        assert_eq!(
+            server.read().await.err().unwrap().downcast_ref(),
+            Some(&ConnectionReset)
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_reading_after_read_times_out() {
+        let stream = FaultyStream::new(vec![
+            ReadStep::Fail(std::io::ErrorKind::TimedOut),
+            ReadStep::Bytes(b"{\"foo\":42}\n".to_vec()),
+        ]);
+        let server = FakeServerConnection::new(stream);
+
+        let timed_out = server.read().await.err().unwrap();
+        assert!(!NetworkError::is_disconnect(&timed_out));
+        assert_eq!(server.read().await.unwrap(), FakeRequest { foo: 42 });
+    }
+
+    #[test_context(LiveLengthPrefixedConnections)]
+    #[tokio::test]
+    async fn sends_length_prefixed_frames_containing_newlines(ctx: LiveLengthPrefixedConnections) {
+        let frames = vec![RawFrame(b"foo\nbar\n".to_vec()), RawFrame(vec![])];
+        for frame in frames.clone() {
+            ctx.client.write(frame).await.unwrap();
+        }
+        let first_read = ctx.server.read().await.unwrap();
+        let second_read = ctx.server.read().await.unwrap();
+        let stats = ctx.server.stats();
+
+        assert_eq!(vec![first_read, second_read], frames);
+        assert_eq!((stats.frames_read, stats.bytes_read), (2, 1 + 4 + 8 + 4));
+    }
+
+    #[test_context(LiveLengthPrefixedConnections)]
+    #[tokio::test]
+    async fn client_closes_length_prefixed_connection_mid_frame(
+        ctx: LiveLengthPrefixedConnections,
+    ) {
+        // announce a 10-byte frame, but only send 3 bytes of it
+        {
+            let mut output = ctx.client.output.lock().await;
+            output
+                .write_all(&[LENGTH_PREFIXED_JSON_PREAMBLE])
+                .await
+                .unwrap();
+            output.write_all(&10u32.to_be_bytes()).await.unwrap();
+            output.write_all(b"foo").await.unwrap();
+            output.flush().await.unwrap();
+        }
+        ctx.client.close().await.unwrap();
+        let server_read = ctx.server.read().await;
+
+        assert_eq!(
+            server_read.err().unwrap().downcast_ref(),
+            Some(&PartialFrame(4 + 3))
+        );
+    }
+
+    #[test_context(LiveLengthPrefixedConnections)]
+    #[tokio::test]
+    async fn rejects_frame_longer_than_max_frame_size(ctx: LiveLengthPrefixedConnections) {
+        let frame_len = MAX_FRAME_SIZE_IN_BYTES as u32 + 1;
+        {
+            let mut output = ctx.client.output.lock().await;
+            output
+                .write_all(&[LENGTH_PREFIXED_JSON_PREAMBLE])
+                .await
+                .unwrap();
+            output.write_all(&frame_len.to_be_bytes()).await.unwrap();
+            output.flush().await.unwrap();
+        }
+        let server_read = ctx.server.read().await;
+
+        assert_eq!(
+            server_read.err().unwrap().downcast_ref(),
+            Some(&FrameTooLarge(frame_len as usize))
+        );
+    }
+
+    #[tokio::test]
+    async fn exchanges_message_pack_frames_once_client_announces_them() {
+        let (client_socket, server_socket) = connect().await;
+        let codec = FrameCodec::LengthPrefixed(WireFormat::MessagePack);
+        let client = FakeClientConnection::with_codec(client_socket, codec);
+        let server = FakeServerConnection::negotiated(server_socket);
+
+        client.write(FakeRequest { foo: 42 }).await.unwrap();
+        let server_read = server.read().await.unwrap();
+        server.write(FakeResponse { bar: 7 }).await.unwrap();
+        let client_read = client.read().await.unwrap();
+        let request_len = rmp_serde::to_vec_named(&FakeRequest { foo: 42 })
+            .unwrap()
+            .len();
+
+        assert_eq!(server_read, FakeRequest { foo: 42 });
+        assert_eq!(client_read, FakeResponse { bar: 7 });
+        assert_eq!(server.stats().bytes_read, (1 + 4 + request_len) as u64);
+    }
+
+    #[tokio::test]
+    async fn negotiates_newline_delimited_json_with_client_announcing_no_codec() {
+        let (client_socket, server_socket) = connect().await;
+        let client = FakeClientConnection::new(client_socket);
+        let server = FakeServerConnection::negotiated(server_socket);
+
+        client.write(FakeRequest { foo: 42 }).await.unwrap();
+        let server_read = server.read().await.unwrap();
+        server.write(FakeResponse { bar: 7 }).await.unwrap();
+        let client_read = client.read().await.unwrap();
+
+        assert_eq!(server_read, FakeRequest { foo: 42 });
+        assert_eq!(client_read, FakeResponse { bar: 7 });
+    }
+
+    #[test_context(LiveConnections)]
+    #[tokio::test]
+    async fn counts_frames_and_bytes_in_both_directions(ctx: LiveConnections) {
+        let before = ctx.client.stats().last_activity;
+        ctx.client.write(FakeRequest { foo: 42 }).await.unwrap();
+        ctx.server.read().await.unwrap();
+        ctx.server.write(FakeResponse { bar: 7 }).await.unwrap();
+        ctx.client.read().await.unwrap();
+
+        let request_len = br#"{"foo":42}"#.len() as u64 + 1;
+        let response_len = br#"{"bar":7}"#.len() as u64 + 1;
+        let (client_stats, server_stats) = (ctx.client.stats(), ctx.server.stats());
+
+        assert_eq!(
+            (client_stats.frames_written, client_stats.bytes_written),
+            (1, request_len)
+        );
+        assert_eq!(
+            (client_stats.frames_read, client_stats.bytes_read),
+            (1, response_len)
+        );
+        assert_eq!(
+            (server_stats.frames_read, server_stats.bytes_read),
+            (1, request_len)
+        );
+        assert_eq!(
+            (server_stats.frames_written, server_stats.bytes_written),
+            (1, response_len)
+        );
+        assert!(client_stats.last_activity >= before);
+    }
+
+    #[test_context(LiveConnections)]
+    #[tokio::test]
+    async fn counts_decode_errors(ctx: LiveConnections) {
+        {
+            let mut output = ctx.client.output.lock().await;
+            output.write_all(b"not json\n").await.unwrap();
+            output.flush().await.unwrap();
+        }
+        let _ = ctx.server.read().await;
+        let stats = ctx.server.stats();
+
+        assert_eq!(stats.frames_read, 0);
+        assert_eq!(stats.decode_errors, 1);
+        assert_eq!(stats.bytes_read, b"not json\n".len() as u64);
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn client_resets_connection_to_server() {
+        let address = Gen::socket_addr();
+        let tcp_listener = TcpListener::bind(address).await.unwrap();
+        let client_socket = TcpStream::connect(address).await.unwrap();
+        let (server_socket, _) = tcp_listener.accept().await.unwrap();
+        let server = FakeServerConnection::new(server_socket);
+
+        // closing a socket with a zero linger timeout sends RST instead of FIN
+        client_socket
+            .set_linger(Some(Duration::from_secs(0)))
+            .unwrap();
+        drop(client_socket);
+        let server_read = server.read().await;
+
+        assert_eq!(
+            server_read.err().unwrap().downcast_ref(),
+            Some(&ConnectionReset)
+        );
+    }
+
+    #[test]
+    fn doubles_reconnect_backoff_up_to_max() {
+        let config = ReconnectConfig {
+            initial_backoff_in_millis: 10,
+            max_backoff_in_millis: 50,
+            jitter: 0.0,
+            max_attempts: Some(5),
+        };
+        let backoffs: Vec<u128> = (0..5).map(|n| config.backoff(n).as_millis()).collect();
+        assert_eq!(backoffs, vec![10, 20, 40, 50, 50]);
+    }
+
+    #[tokio::test]
+    async fn reconnects_once_listener_is_up() {
+        let address = Gen::socket_addr();
+        let config = ReconnectConfig::default();
+        let reconnecting =
+            tokio::spawn(async move { config.reconnect(address, &TcpTransport, None).await });
+
+        let tcp_listener = TcpListener::bind(address).await.unwrap();
+        let accept = tcp_listener.accept().await;
+
+        assert!(accept.is_ok());
+        assert!(reconnecting.await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn gives_up_reconnecting_after_max_attempts() {
+        let config = ReconnectConfig {
+            max_attempts: Some(2),
+            ..ReconnectConfig::default()
+        };
+        assert!(config
+            .reconnect(Gen::socket_addr(), &TcpTransport, None)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn keeps_reconnecting_without_max_attempts() {
+        let address = Gen::socket_addr();
+        let config = ReconnectConfig {
+            initial_backoff_in_millis: 1,
+            max_backoff_in_millis: 1,
+            jitter: 0.0,
+            ..ReconnectConfig::unlimited()
+        };
+        let reconnecting =
+            tokio::spawn(async move { config.reconnect(address, &TcpTransport, None).await });
+
+        // long enough for the default number of attempts to have failed
+        time::sleep(Duration::from_millis(50)).await;
+        let tcp_listener = TcpListener::bind(address).await.unwrap();
+        let accept = tcp_listener.accept().await;
+
+        assert!(accept.is_ok());
+        assert!(reconnecting.await.unwrap().is_some());
+    }
+
+    #[test]
+    fn rejects_reconnect_jitter_out_of_range() {
+        for jitter in [-0.5, 1.5, f64::NAN] {
+            let config = ReconnectConfig {
+                jitter,
+                ..ReconnectConfig::default()
+            };
+            assert_eq!(
+                config.validate().err().unwrap().to_string(),
+                InvalidReconnectJitter(jitter).to_string()
+            );
+        }
+        assert!(ReconnectConfig::default().validate().is_ok());
+    }
 }
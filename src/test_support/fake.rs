@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// One thing that happens when a `FaultyStream` is read from
+#[derive(Clone, Debug)]
+pub enum ReadStep {
+    Bytes(Vec<u8>),  // hand back these bytes (over as many reads as it takes)
+    Eof,             // the peer shut down cleanly
+    Fail(ErrorKind), // the read fails with an io error of this kind
+}
+
+/// Fake connection that plays back a script of `ReadStep`s to whoever reads from it (so that tests
+/// can inject faults at exact points in a stream of bytes), reporting EOF once the script runs
+/// out. Writes are accepted and discarded.
+pub struct FaultyStream {
+    steps: VecDeque<ReadStep>,
+}
+
+impl FaultyStream {
+    pub fn new(steps: Vec<ReadStep>) -> FaultyStream {
+        FaultyStream {
+            steps: steps.into(),
+        }
+    }
+
+    /// Play back `bytes` one at a time (so that readers must stitch every frame together from
+    /// many reads) before taking `last_step`.
+    pub fn trickling(bytes: &[u8], last_step: ReadStep) -> FaultyStream {
+        let mut steps: Vec<ReadStep> = bytes.iter().map(|&b| ReadStep::Bytes(vec![b])).collect();
+        steps.push(last_step);
+        FaultyStream::new(steps)
+    }
+}
+
+impl AsyncRead for FaultyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.steps.pop_front() {
+            Some(ReadStep::Bytes(mut bytes)) => {
+                let n = bytes.len().min(buf.remaining());
+                buf.put_slice(&bytes[..n]);
+                if n < bytes.len() {
+                    self.steps.push_front(ReadStep::Bytes(bytes.split_off(n)));
+                }
+                Poll::Ready(Ok(()))
+            }
+            Some(ReadStep::Fail(kind)) => Poll::Ready(Err(IoError::from(kind))),
+            Some(ReadStep::Eof) | None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl AsyncWrite for FaultyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -1,14 +1,26 @@
 #![allow(dead_code)]
-use crate::api::client::ApiClientConfig;
+use crate::api::client::{ApiClientConfig, ValueFormat, DEFAULT_MAX_INFLIGHT_REQUESTS};
 use crate::api::request::{ApiRequest, ApiRequestEnvelope};
-use crate::api::response::{ApiResponse, ApiResponseEnvelope};
+use crate::api::response::{ApiResponse, ApiResponseEnvelope, ReplicaAudit};
 use crate::rpc::client::RpcClientConfig;
-use crate::rpc::request::{AppendEntriesRequest, RpcRequest, RpcRequestEnvelope};
-use crate::rpc::response::{AppendEntriesResponse, RpcResponse, RpcResponseEnvelope};
+use crate::rpc::request::{
+    AppendEntriesRequest, DigestValuesRequest, RequestVoteRequest, RpcRequest, RpcRequestEnvelope,
+};
+use crate::rpc::response::{
+    AppendEntriesResponse, DigestValuesResponse, RequestVoteResponse, RpcResponse,
+    RpcResponseEnvelope,
+};
 use crate::state::log::{Command, LogEntry};
+use crate::tcp::{FrameCodec, ReconnectConfig};
+#[cfg(feature = "tls")]
+use crate::tls::{TlsClientConfig, TlsServerConfig};
+use crate::transport::TcpTransport;
 use rand::seq::SliceRandom;
 use rand::Rng;
+#[cfg(feature = "tls")]
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 pub struct Gen {}
 
@@ -24,7 +36,7 @@ impl Gen {
     }
 
     pub fn str() -> String {
-        let strs = vec![
+        let strs = [
             "foo".to_string(),
             "bar".to_string(),
             "baz".to_string(),
@@ -35,10 +47,63 @@ impl Gen {
     }
 
     pub fn bool() -> bool {
-        vec![true, false]
-            .choose(&mut rand::thread_rng())
+        *[true, false].choose(&mut rand::thread_rng()).unwrap()
+    }
+
+    #[cfg(feature = "tls")]
+    /// Write a fresh CA certificate, and a certificate for 127.0.0.1 (and ::1) signed by it (along
+    /// with its key), into `dir`, returning configs for a server presenting the latter to clients
+    /// trusting the former.
+    pub async fn tls_configs(dir: &str) -> (TlsServerConfig, TlsClientConfig) {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["127.0.0.1".to_string(), "::1".to_string()])
             .unwrap()
-            .clone()
+            .signed_by(&key, &ca_cert, &ca_key)
+            .unwrap();
+
+        let server_tls = TlsServerConfig {
+            cert_path: format!("{}/cert.pem", dir),
+            key_path: format!("{}/key.pem", dir),
+            client_ca_path: None,
+        };
+        let client_tls = TlsClientConfig {
+            ca_path: format!("{}/ca.pem", dir),
+            cert_path: None,
+            key_path: None,
+        };
+        tokio::fs::create_dir_all(dir).await.unwrap();
+        tokio::fs::write(&server_tls.cert_path, cert.pem())
+            .await
+            .unwrap();
+        tokio::fs::write(&server_tls.key_path, key.serialize_pem())
+            .await
+            .unwrap();
+        tokio::fs::write(&client_tls.ca_path, ca_cert.pem())
+            .await
+            .unwrap();
+        (server_tls, client_tls)
+    }
+
+    #[cfg(feature = "tls")]
+    /// Like `tls_configs`, but have the server require clients to present certificates signed by
+    /// the same CA (and have clients present the server's own certificate, as peers do).
+    pub async fn mutual_tls_configs(dir: &str) -> (TlsServerConfig, TlsClientConfig) {
+        let (server_tls, client_tls) = Gen::tls_configs(dir).await;
+        let mutual_server_tls = TlsServerConfig {
+            client_ca_path: Some(client_tls.ca_path.clone()),
+            ..server_tls.clone()
+        };
+        let mutual_client_tls = TlsClientConfig {
+            cert_path: Some(server_tls.cert_path),
+            key_path: Some(server_tls.key_path),
+            ..client_tls
+        };
+        (mutual_server_tls, mutual_client_tls)
     }
 
     pub fn socket_addr() -> SocketAddr {
@@ -54,14 +119,28 @@ impl Gen {
     }
 
     pub fn rpc_request() -> RpcRequest {
-        let requests = vec![RpcRequest::AppendEntries(AppendEntriesRequest {
-            entries: vec![],
-            leader_address: Gen::socket_addr().to_string(),
-            leader_commit: 0,
-            leader_term: 0,
-            prev_log_index: 0,
-            prev_log_term: 0,
-        })];
+        let requests = [
+            RpcRequest::AppendEntries(AppendEntriesRequest {
+                entries: vec![],
+                leader_address: Gen::socket_addr().to_string(),
+                leader_api_address: Some(Gen::socket_addr().to_string()),
+                leader_commit: 0,
+                leader_compactable_index: 0,
+                leader_term: 0,
+                prev_log_index: 0,
+                prev_log_term: 0,
+            }),
+            RpcRequest::RequestVote(RequestVoteRequest {
+                candidate_address: Gen::socket_addr().to_string(),
+                candidate_term: 0,
+                last_log_index: 0,
+                last_log_term: 0,
+            }),
+            RpcRequest::DigestValues(DigestValuesRequest {
+                audit_id: Gen::u64(),
+                keys: vec![Gen::str()],
+            }),
+        ];
         requests.choose(&mut rand::thread_rng()).unwrap().clone()
     }
 
@@ -87,6 +166,7 @@ impl Gen {
         Command::Put {
             key: Gen::str(),
             value: Gen::str(),
+            expires_at: None,
         }
     }
 
@@ -102,6 +182,9 @@ impl Gen {
             ApiRequest::Put {
                 key: Gen::str(),
                 value: Gen::str(),
+                is_async: Gen::bool(),
+                min_replicas: None,
+                ttl_ms: None,
             },
             ApiRequest::Get { key: Gen::str() },
         ];
@@ -116,7 +199,7 @@ impl Gen {
     }
 
     pub fn api_response() -> ApiResponse {
-        let responses = vec![
+        let responses = [
             ApiResponse::ToGet {
                 value: Some(Gen::str()),
             },
@@ -136,6 +219,20 @@ impl Gen {
             ApiRequest::Put { .. } => ApiResponse::ToPut {
                 was_modified: Gen::bool(),
             },
+            ApiRequest::Delete { .. } => ApiResponse::ToDelete {
+                was_present: Gen::bool(),
+            },
+            ApiRequest::NextIds { count, .. } => ApiResponse::ToNextIds {
+                start: Gen::u64(),
+                count,
+            },
+            ApiRequest::VerifyReplicas { .. } => ApiResponse::ToVerifyReplicas {
+                audit: ReplicaAudit::default(),
+            },
+            ApiRequest::Batch { requests } => ApiResponse::ToBatch {
+                responses: requests.into_iter().map(Gen::api_response_to).collect(),
+            },
+            ApiRequest::Ping => ApiResponse::Pong,
         }
     }
 
@@ -147,10 +244,16 @@ impl Gen {
     }
 
     pub fn rpc_response() -> RpcResponse {
-        let responses = vec![RpcResponse::ToAppendEntries(AppendEntriesResponse {
-            peer_term: 0,
-            success: true,
-        })];
+        let responses = [
+            RpcResponse::ToAppendEntries(AppendEntriesResponse {
+                peer_term: 0,
+                success: true,
+            }),
+            RpcResponse::ToRequestVote(RequestVoteResponse {
+                peer_term: 0,
+                vote_granted: Gen::bool(),
+            }),
+        ];
         responses.choose(&mut rand::thread_rng()).unwrap().clone()
     }
 
@@ -162,17 +265,36 @@ impl Gen {
                     success: true,
                 })
             }
+            RpcRequest::RequestVote { .. } => RpcResponse::ToRequestVote(RequestVoteResponse {
+                peer_term: 0,
+                vote_granted: true,
+            }),
+            RpcRequest::DigestValues(req) => RpcResponse::ToDigestValues(DigestValuesResponse {
+                digests: req.keys.iter().map(|_| Some(Gen::u64())).collect(),
+            }),
         }
     }
 
     pub fn api_client_config() -> ApiClientConfig {
         ApiClientConfig {
             server_address: Gen::socket_addr(),
+            max_inflight_requests: DEFAULT_MAX_INFLIGHT_REQUESTS,
+            reconnect: ReconnectConfig::default(),
+            codec: FrameCodec::default(),
+            value_format: ValueFormat::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(TcpTransport),
         }
     }
     pub fn rpc_client_config() -> RpcClientConfig {
         RpcClientConfig {
             peer_addresses: vec![Gen::socket_addr(), Gen::socket_addr(), Gen::socket_addr()],
+            reconnect: ReconnectConfig::default(),
+            codec: FrameCodec::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            transport: Arc::new(TcpTransport),
         }
     }
 }
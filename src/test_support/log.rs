@@ -6,8 +6,12 @@ use tokio::io::{AsyncWriteExt, BufWriter};
 
 impl Log {
     pub async fn from_entries(path: String, entries: Vec<LogEntry>) -> Result<Log> {
-        let log = Log { path, entries };
-        let _ = log.dump().await?;
+        let log = Log {
+            path,
+            entries,
+            first_index: 0,
+        };
+        log.dump().await?;
         Ok(log)
     }
 
@@ -17,8 +21,8 @@ impl Log {
 
         let mut writer = BufWriter::new(file);
         for entry in entries {
-            writer.write(&entry.to_bytes()).await?;
-            writer.write(&[NEWLINE]).await?;
+            writer.write_all(&entry.to_bytes()).await?;
+            writer.write_all(&[NEWLINE]).await?;
             writer.flush().await?;
         }
 
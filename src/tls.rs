@@ -0,0 +1,312 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::time::{timeout, Duration};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+pub use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::error::ConfigError::InvalidTlsCredentials;
+use crate::error::NetworkError::HandshakeTimeout;
+use crate::error::Result;
+use crate::tcp::BoxedStream;
+
+// how long a client may take to complete its handshake before we drop it (so that clients that
+// connect but never speak cannot tie up our accept tasks)
+#[cfg(not(test))]
+const HANDSHAKE_TIMEOUT_IN_MILLIS: u64 = 5000;
+#[cfg(test)]
+const HANDSHAKE_TIMEOUT_IN_MILLIS: u64 = 50;
+
+/// Where a server finds the (PEM-encoded) certificate chain and private key it presents to clients
+/// (and, if it requires clients to present certificates of their own, the bundle of CA certificates
+/// it trusts to sign them)
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsServerConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub client_ca_path: Option<String>, // if set, only accept clients with certificates signed by these
+}
+
+/// Where a client finds the (PEM-encoded) bundle of CA certificates it trusts to sign the
+/// certificates servers present to it (and, for servers that require one, the certificate chain
+/// and private key it presents to them -- both or neither)
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsClientConfig {
+    pub ca_path: String,
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+}
+
+impl TlsServerConfig {
+    /// Load our certificate chain and key from disk, failing fast if either is missing or invalid.
+    pub fn load(&self) -> Result<TlsAcceptor> {
+        Ok(TlsAcceptor::from(Arc::new(self.rustls_config()?)))
+    }
+
+    pub(crate) fn rustls_config(&self) -> Result<ServerConfig> {
+        let certs = load_certs(&self.cert_path)?;
+        let key = load_key(&self.key_path)?;
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca_path {
+            Some(client_ca_path) => builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(load_roots(client_ca_path)?),
+                    provider,
+                )
+                .build()?,
+            ),
+            None => builder.with_no_client_auth(),
+        };
+        Ok(builder.with_single_cert(certs, key)?)
+    }
+}
+
+impl TlsClientConfig {
+    /// Load the CA certificates we trust from disk, failing fast if none are found.
+    pub fn load(&self) -> Result<TlsConnector> {
+        Ok(TlsConnector::from(Arc::new(self.rustls_config()?)))
+    }
+
+    pub(crate) fn rustls_config(&self) -> Result<ClientConfig> {
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(load_roots(&self.ca_path)?);
+        match (&self.cert_path, &self.key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                Ok(builder.with_client_auth_cert(load_certs(cert_path)?, load_key(key_path)?)?)
+            }
+            (None, None) => Ok(builder.with_no_client_auth()),
+            _ => Err(InvalidTlsCredentials(
+                "client certificate and key must be given together".to_string(),
+            )
+            .boxed()),
+        }
+    }
+}
+
+/// Complete a TLS handshake with the client on the other end of `socket` (if we have an `acceptor`,
+/// otherwise leave the socket as it is), failing if it takes longer than
+/// `HANDSHAKE_TIMEOUT_IN_MILLIS`.
+pub async fn accept(socket: BoxedStream, acceptor: Option<&TlsAcceptor>) -> Result<BoxedStream> {
+    match acceptor {
+        Some(acceptor) => {
+            let handshake = acceptor.accept(socket);
+            match timeout(
+                Duration::from_millis(HANDSHAKE_TIMEOUT_IN_MILLIS),
+                handshake,
+            )
+            .await
+            {
+                Ok(stream) => Ok(Box::new(stream?)),
+                Err(_) => Err(HandshakeTimeout.boxed()),
+            }
+        }
+        None => Ok(socket),
+    }
+}
+
+/// Complete a TLS handshake with the server at `address` on the other end of `socket` (if we have
+/// a `connector`, otherwise leave the socket as it is), verifying that the server's certificate is
+/// signed by a CA we trust and names the server's ip address.
+pub async fn connect(
+    socket: BoxedStream,
+    address: SocketAddr,
+    connector: Option<&TlsConnector>,
+) -> Result<BoxedStream> {
+    match connector {
+        Some(connector) => {
+            let server_name = ServerName::from(address.ip());
+            Ok(Box::new(connector.connect(server_name, socket).await?))
+        }
+        None => Ok(socket),
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<std::io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(InvalidTlsCredentials(format!("no certificates found in {}", path)).boxed());
+    }
+    Ok(certs)
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| InvalidTlsCredentials(format!("no private key found in {}", path)).boxed())
+}
+
+#[cfg(test)]
+mod tls_tests {
+    use super::*;
+    use crate::tcp::Connection;
+    use crate::tcp_serializable;
+    use crate::test_support::gen::Gen;
+    use crate::transport::{TcpTransport, Transport};
+    use serde::{Deserialize, Serialize};
+    use std::convert::TryFrom;
+    use std::result::Result as StdResult;
+
+    #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+    struct FakeFrame {
+        foo: usize,
+    }
+    tcp_serializable!(FakeFrame);
+
+    /// Accept one connection at `address` (over TLS per `server_tls`) and echo the first frame
+    /// read on it back to the client.
+    async fn run_echo_server(address: SocketAddr, server_tls: TlsServerConfig) {
+        let mut listener = TcpTransport.bind(address).await.unwrap();
+        let acceptor = server_tls.load().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            if let Ok(stream) = accept(socket, Some(&acceptor)).await {
+                let conn = Connection::<FakeFrame, FakeFrame>::negotiated(stream);
+                if let Ok(frame) = conn.read().await {
+                    let _ = conn.write(frame).await;
+                }
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn exchanges_frames_over_tls() {
+        let path = format!("test_data/tls_{}", Gen::usize());
+        let (server_tls, client_tls) = Gen::tls_configs(&path).await;
+        let address = Gen::socket_addr();
+        run_echo_server(address, server_tls).await;
+
+        let socket = TcpTransport.connect(address).await.unwrap();
+        let connector = client_tls.load().unwrap();
+        let stream = connect(socket, address, Some(&connector)).await.unwrap();
+        let conn = Connection::<FakeFrame, FakeFrame>::new(stream);
+        conn.write(FakeFrame { foo: 42 }).await.unwrap();
+        let echoed = conn.read().await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(echoed.unwrap(), FakeFrame { foo: 42 });
+    }
+
+    #[tokio::test]
+    async fn rejects_server_whose_certificate_is_not_signed_by_trusted_ca() {
+        let (path, other_path) = (
+            format!("test_data/tls_{}", Gen::usize()),
+            format!("test_data/tls_{}", Gen::usize()),
+        );
+        let (server_tls, _) = Gen::tls_configs(&path).await;
+        let (_, other_client_tls) = Gen::tls_configs(&other_path).await;
+        let address = Gen::socket_addr();
+        run_echo_server(address, server_tls).await;
+
+        let socket = TcpTransport.connect(address).await.unwrap();
+        let connector = other_client_tls.load().unwrap();
+        let stream = connect(socket, address, Some(&connector)).await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        tokio::fs::remove_dir_all(other_path).await.unwrap();
+        assert!(stream.is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_to_load_missing_credentials() {
+        let server_tls = TlsServerConfig {
+            cert_path: format!("test_data/missing_{}.pem", Gen::usize()),
+            key_path: format!("test_data/missing_{}.pem", Gen::usize()),
+            client_ca_path: None,
+        };
+        assert!(server_tls.load().is_err());
+    }
+
+    #[tokio::test]
+    async fn exchanges_frames_with_client_presenting_trusted_certificate() {
+        let path = format!("test_data/tls_{}", Gen::usize());
+        let (server_tls, client_tls) = Gen::mutual_tls_configs(&path).await;
+        let address = Gen::socket_addr();
+        run_echo_server(address, server_tls).await;
+
+        let socket = TcpTransport.connect(address).await.unwrap();
+        let connector = client_tls.load().unwrap();
+        let stream = connect(socket, address, Some(&connector)).await.unwrap();
+        let conn = Connection::<FakeFrame, FakeFrame>::new(stream);
+        conn.write(FakeFrame { foo: 42 }).await.unwrap();
+        let echoed = conn.read().await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(echoed.unwrap(), FakeFrame { foo: 42 });
+    }
+
+    #[tokio::test]
+    async fn drops_client_that_presents_no_certificate_when_one_is_required() {
+        let path = format!("test_data/tls_{}", Gen::usize());
+        let (server_tls, _) = Gen::mutual_tls_configs(&path).await;
+        let (_, client_tls) = Gen::tls_configs(&format!("{}/anonymous", path)).await;
+        let client_tls = TlsClientConfig {
+            ca_path: server_tls.client_ca_path.clone().unwrap(),
+            ..client_tls
+        };
+        let address = Gen::socket_addr();
+        run_echo_server(address, server_tls).await;
+
+        let socket = TcpTransport.connect(address).await.unwrap();
+        let connector = client_tls.load().unwrap();
+        // (under TLS 1.3, the server only checks for our certificate once we have finished our
+        // side of the handshake, so we learn we were refused when we next read)
+        let echoed = match connect(socket, address, Some(&connector)).await {
+            Ok(stream) => {
+                let conn = Connection::<FakeFrame, FakeFrame>::new(stream);
+                let _ = conn.write(FakeFrame { foo: 42 }).await;
+                conn.read().await
+            }
+            Err(e) => Err(e),
+        };
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert!(echoed.is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_to_load_client_certificate_without_key() {
+        let path = format!("test_data/tls_{}", Gen::usize());
+        let (server_tls, client_tls) = Gen::tls_configs(&path).await;
+        let client_tls = TlsClientConfig {
+            cert_path: Some(server_tls.cert_path),
+            ..client_tls
+        };
+        let loaded = client_tls.load();
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert!(loaded.is_err());
+    }
+
+    #[tokio::test]
+    async fn times_out_handshake_with_silent_client() {
+        let path = format!("test_data/tls_{}", Gen::usize());
+        let (server_tls, _) = Gen::tls_configs(&path).await;
+        let acceptor = server_tls.load().unwrap();
+        let (socket, _silent_client) = tokio::io::duplex(1024);
+
+        let accepted = accept(Box::new(socket), Some(&acceptor)).await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(
+            accepted.err().unwrap().downcast_ref(),
+            Some(&HandshakeTimeout)
+        );
+    }
+}
@@ -0,0 +1,406 @@
+#[cfg(feature = "quic")]
+use std::convert::TryFrom;
+use std::io::{Error as IoError, ErrorKind};
+use std::net::SocketAddr;
+#[cfg(feature = "quic")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+#[cfg(feature = "quic")]
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+#[cfg(feature = "quic")]
+use quinn::{ClientConfig as QuinnClientConfig, Endpoint, ServerConfig as QuinnServerConfig};
+#[cfg(feature = "quic")]
+use tokio::io::join;
+use tokio::io::{duplex, DuplexStream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::{Receiver, Sender};
+#[cfg(feature = "quic")]
+use tokio::sync::OnceCell;
+
+#[cfg(feature = "quic")]
+use crate::error::ConfigError::MissingQuicServerCredentials;
+use crate::error::Result;
+use crate::tcp::BoxedStream;
+#[cfg(feature = "quic")]
+use crate::tls::{TlsClientConfig, TlsServerConfig};
+use crate::CHAN_BUF_SIZE;
+
+// how many bytes an in-memory stream buffers in each direction before writes wait for reads
+const MEMORY_STREAM_BUF_SIZE_IN_BYTES: usize = 64 * 1024;
+
+/// How clients open streams to servers (and servers listen for them), so that every `Connection`
+/// runs over the same code whatever carries its bytes. (TLS is layered over whichever stream a
+/// transport provides -- see `tls::connect` and `tls::accept` -- except for those that encrypt on
+/// their own, like `QuicTransport`.)
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn connect(&self, address: SocketAddr) -> Result<BoxedStream>;
+    async fn bind(&self, address: SocketAddr) -> Result<Box<dyn Listener>>;
+
+    /// Whether the streams we carry are already encrypted (so that TLS must not be layered over them)
+    fn encrypts(&self) -> bool {
+        false
+    }
+}
+
+/// The listening end of a `Transport`, bound to some address
+#[async_trait]
+pub trait Listener: Send {
+    /// Wait for a client to connect, returning its stream and a description of where it came from.
+    async fn accept(&mut self) -> Result<(BoxedStream, String)>;
+}
+
+/// Carries bytes over TCP sockets
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TcpTransport;
+
+/// Carries bytes over in-memory pipes between tasks in the same process, so that servers and
+/// clients may be exercised (eg: in tests) without opening any sockets. Only transports cloned
+/// from the same `MemoryTransport` can reach each other's listeners.
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    listeners: Arc<DashMap<SocketAddr, Sender<DuplexStream>>>,
+}
+
+/// Carries bytes over QUIC, opening a new stream for each connection to an address over a single
+/// QUIC connection to it (so that a stalled stream never holds up the others, and reconnecting a
+/// stream skips the handshake). QUIC always encrypts, so there is no need to layer TLS over it:
+/// listeners present the certificate in `server_tls`, and clients only trust servers whose
+/// certificates are signed by a CA in `client_tls`.
+#[cfg(feature = "quic")]
+#[derive(Clone)]
+pub struct QuicTransport {
+    server_config: Option<QuinnServerConfig>, // unset for transports that only ever `connect`
+    client_config: QuinnClientConfig,
+    // bound on first `connect` to an address in each family (as a socket only reaches its own)
+    ipv4_client_endpoint: Arc<OnceCell<Endpoint>>,
+    ipv6_client_endpoint: Arc<OnceCell<Endpoint>>,
+    connections: Arc<DashMap<SocketAddr, quinn::Connection>>,
+}
+
+#[cfg(feature = "quic")]
+struct QuicListener {
+    endpoint: Endpoint,
+    streams: Receiver<(BoxedStream, String)>,
+}
+
+struct MemoryListener {
+    address: SocketAddr,
+    connections: Receiver<DuplexStream>,
+    listeners: Arc<DashMap<SocketAddr, Sender<DuplexStream>>>,
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn connect(&self, address: SocketAddr) -> Result<BoxedStream> {
+        Ok(Box::new(TcpStream::connect(address).await?))
+    }
+
+    async fn bind(&self, address: SocketAddr) -> Result<Box<dyn Listener>> {
+        Ok(Box::new(TcpListener::bind(address).await?))
+    }
+}
+
+#[async_trait]
+impl Listener for TcpListener {
+    async fn accept(&mut self) -> Result<(BoxedStream, String)> {
+        let (socket, address) = TcpListener::accept(self).await?;
+        Ok((Box::new(socket), address.to_string()))
+    }
+}
+
+#[cfg(feature = "quic")]
+impl QuicTransport {
+    /// Load the credentials we present to clients (if given) and the CA certificates we trust to
+    /// sign those presented by servers, failing fast if any are missing or invalid.
+    pub fn new(
+        server_tls: Option<&TlsServerConfig>,
+        client_tls: &TlsClientConfig,
+    ) -> Result<QuicTransport> {
+        let server_config = match server_tls {
+            Some(tls) => {
+                let crypto = QuicServerConfig::try_from(tls.rustls_config()?)?;
+                Some(QuinnServerConfig::with_crypto(Arc::new(crypto)))
+            }
+            None => None,
+        };
+        let crypto = QuicClientConfig::try_from(client_tls.rustls_config()?)?;
+        Ok(QuicTransport {
+            server_config,
+            client_config: QuinnClientConfig::new(Arc::new(crypto)),
+            ipv4_client_endpoint: Arc::new(OnceCell::new()),
+            ipv6_client_endpoint: Arc::new(OnceCell::new()),
+            connections: Arc::new(DashMap::new()),
+        })
+    }
+
+    /// Reuse our connection to `address` if it is still open, otherwise open a new one (verifying
+    /// that the server's certificate names its ip address).
+    async fn connection_to(&self, address: SocketAddr) -> Result<quinn::Connection> {
+        let open = self
+            .connections
+            .get(&address)
+            .map(|c| c.value().clone())
+            .filter(|c| c.close_reason().is_none());
+        if let Some(connection) = open {
+            return Ok(connection);
+        }
+        let (endpoint, unspecified): (_, IpAddr) = match address {
+            SocketAddr::V4(_) => (&self.ipv4_client_endpoint, Ipv4Addr::UNSPECIFIED.into()),
+            SocketAddr::V6(_) => (&self.ipv6_client_endpoint, Ipv6Addr::UNSPECIFIED.into()),
+        };
+        let endpoint = endpoint
+            .get_or_try_init(|| async { Endpoint::client(SocketAddr::new(unspecified, 0)) })
+            .await?;
+        let server_name = address.ip().to_string();
+        let connection = endpoint
+            .connect_with(self.client_config.clone(), address, &server_name)?
+            .await?;
+        self.connections.insert(address, connection.clone());
+        Ok(connection)
+    }
+}
+
+#[cfg(feature = "quic")]
+#[async_trait]
+impl Transport for QuicTransport {
+    async fn connect(&self, address: SocketAddr) -> Result<BoxedStream> {
+        let (send, recv) = self.connection_to(address).await?.open_bi().await?;
+        Ok(Box::new(join(recv, send)))
+    }
+
+    fn encrypts(&self) -> bool {
+        true
+    }
+
+    /// Listen at `address`, accepting each stream a client opens on any of its connections to us.
+    /// (A client's stream only reaches us once the client has written to it.)
+    async fn bind(&self, address: SocketAddr) -> Result<Box<dyn Listener>> {
+        let server_config = self
+            .server_config
+            .clone()
+            .ok_or_else(|| MissingQuicServerCredentials.boxed())?;
+        let endpoint = Endpoint::server(server_config, address)?;
+        let (tx, rx) = mpsc::channel(CHAN_BUF_SIZE);
+        let accepting = endpoint.clone();
+        tokio::spawn(async move {
+            while let Some(incoming) = accepting.accept().await {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    if let Ok(connection) = incoming.await {
+                        let client = connection.remote_address().to_string();
+                        while let Ok((send, recv)) = connection.accept_bi().await {
+                            let stream: BoxedStream = Box::new(join(recv, send));
+                            if tx.send((stream, client.clone())).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        Ok(Box::new(QuicListener {
+            endpoint,
+            streams: rx,
+        }))
+    }
+}
+
+#[cfg(feature = "quic")]
+#[async_trait]
+impl Listener for QuicListener {
+    async fn accept(&mut self) -> Result<(BoxedStream, String)> {
+        match self.streams.recv().await {
+            Some(accepted) => Ok(accepted),
+            None => Err(IoError::from(ErrorKind::NotConnected).into()),
+        }
+    }
+}
+
+#[cfg(feature = "quic")]
+impl Drop for QuicListener {
+    /// Close every connection to us (and stop accepting new ones).
+    fn drop(&mut self) {
+        self.endpoint.close(0u32.into(), b"listener dropped");
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    /// Hand the server end of a new pipe to whoever is listening at `address` (failing as TCP
+    /// would if no one is).
+    async fn connect(&self, address: SocketAddr) -> Result<BoxedStream> {
+        let listener = self.listeners.get(&address).map(|l| l.value().clone());
+        let (client_end, server_end) = duplex(MEMORY_STREAM_BUF_SIZE_IN_BYTES);
+        match listener {
+            Some(listener) if listener.send(server_end).await.is_ok() => Ok(Box::new(client_end)),
+            _ => Err(IoError::from(ErrorKind::ConnectionRefused).into()),
+        }
+    }
+
+    /// Listen at `address` (failing as TCP would if someone already is).
+    async fn bind(&self, address: SocketAddr) -> Result<Box<dyn Listener>> {
+        let (tx, rx) = mpsc::channel(CHAN_BUF_SIZE);
+        match self.listeners.entry(address) {
+            Entry::Occupied(_) => Err(IoError::from(ErrorKind::AddrInUse).into()),
+            Entry::Vacant(entry) => {
+                entry.insert(tx);
+                Ok(Box::new(MemoryListener {
+                    address,
+                    connections: rx,
+                    listeners: self.listeners.clone(),
+                }))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    async fn accept(&mut self) -> Result<(BoxedStream, String)> {
+        match self.connections.recv().await {
+            Some(stream) => Ok((Box::new(stream), format!("memory:{}", self.address))),
+            None => Err(IoError::from(ErrorKind::NotConnected).into()),
+        }
+    }
+}
+
+impl Drop for MemoryListener {
+    /// Free our address for another listener (and refuse any further connections to it).
+    fn drop(&mut self) {
+        self.listeners.remove(&self.address);
+    }
+}
+
+#[cfg(test)]
+mod transport_tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::test_support::gen::Gen;
+
+    async fn assert_carries_bytes(transport: &dyn Transport) {
+        let address = Gen::socket_addr();
+        let mut listener = transport.bind(address).await.unwrap();
+        assert_carries_bytes_to(transport, address, &mut listener).await;
+    }
+
+    async fn assert_carries_bytes_to(
+        transport: &dyn Transport,
+        address: SocketAddr,
+        listener: &mut Box<dyn Listener>,
+    ) {
+        // write before accepting, as QUIC streams only reach the listener once written to
+        let mut client = transport.connect(address).await.unwrap();
+        client.write_all(b"foo").await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        let mut buf = [0u8; 3];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"foo");
+    }
+
+    #[cfg(feature = "quic")]
+    async fn quic_transport(path: &str) -> QuicTransport {
+        let (server_tls, client_tls) = Gen::tls_configs(path).await;
+        QuicTransport::new(Some(&server_tls), &client_tls).unwrap()
+    }
+
+    #[tokio::test]
+    async fn carries_bytes_over_tcp() {
+        assert_carries_bytes(&TcpTransport).await;
+    }
+
+    #[tokio::test]
+    async fn carries_bytes_over_memory() {
+        assert_carries_bytes(&MemoryTransport::default()).await;
+    }
+
+    #[cfg(feature = "quic")]
+    #[tokio::test]
+    async fn carries_bytes_over_quic() {
+        let path = format!("test_data/quic_{}", Gen::usize());
+        let transport = quic_transport(&path).await;
+        assert_carries_bytes(&transport).await;
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[cfg(feature = "quic")]
+    #[tokio::test]
+    async fn multiplexes_quic_streams_over_one_connection() {
+        let path = format!("test_data/quic_{}", Gen::usize());
+        let transport = quic_transport(&path).await;
+        let address = Gen::socket_addr();
+        let mut listener = transport.bind(address).await.unwrap();
+
+        assert_carries_bytes_to(&transport, address, &mut listener).await;
+        assert_carries_bytes_to(&transport, address, &mut listener).await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(transport.connections.len(), 1);
+    }
+
+    #[cfg(feature = "quic")]
+    #[tokio::test]
+    async fn carries_bytes_over_quic_to_ipv6_address() {
+        let path = format!("test_data/quic_{}", Gen::usize());
+        let transport = quic_transport(&path).await;
+        let address = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), Gen::socket_addr().port());
+        let mut listener = transport.bind(address).await.unwrap();
+
+        assert_carries_bytes_to(&transport, address, &mut listener).await;
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[cfg(feature = "quic")]
+    #[tokio::test]
+    async fn refuses_to_bind_quic_address_without_server_credentials() {
+        let path = format!("test_data/quic_{}", Gen::usize());
+        let (_, client_tls) = Gen::tls_configs(&path).await;
+        let transport = QuicTransport::new(None, &client_tls).unwrap();
+        let bind = transport.bind(Gen::socket_addr()).await;
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+        assert_eq!(
+            bind.err().unwrap().to_string(),
+            MissingQuicServerCredentials.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_memory_connection_to_address_no_one_listens_at() {
+        let transport = MemoryTransport::default();
+        let address = Gen::socket_addr();
+        let before_bind = transport.connect(address).await;
+        let listener = transport.bind(address).await.unwrap();
+        drop(listener);
+        let after_drop = transport.connect(address).await;
+
+        for result in [before_bind, after_drop] {
+            let err = result.err().unwrap();
+            assert_eq!(
+                err.downcast_ref::<IoError>().unwrap().kind(),
+                ErrorKind::ConnectionRefused
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_to_bind_memory_address_twice() {
+        let transport = MemoryTransport::default();
+        let address = Gen::socket_addr();
+        let _listener = transport.bind(address).await.unwrap();
+        let rebind = transport.clone().bind(address).await;
+
+        let err = rebind.err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<IoError>().unwrap().kind(),
+            ErrorKind::AddrInUse
+        );
+    }
+}